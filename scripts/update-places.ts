@@ -0,0 +1,63 @@
+// Rebuild src-tauri/data/cities.tsv from a GeoNames dump.
+//
+//   bun scripts/update-places.ts <dir>
+//
+// <dir> must hold cities15000.txt (or cities5000.txt), admin1CodesASCII.txt
+// and countryInfo.txt from https://download.geonames.org/export/dump/.
+// The app reloads its places, and reindexes, the next time it opens a
+// library built with different data.
+import { existsSync, readFileSync, writeFileSync } from "node:fs";
+import { join } from "node:path";
+
+const OUTPUT = new URL("../src-tauri/data/cities.tsv", import.meta.url);
+
+const dir = process.argv[2];
+if (!dir) {
+  console.error("usage: bun scripts/update-places.ts <geonames dump dir>");
+  process.exit(1);
+}
+
+const rows = (file: string) =>
+  readFileSync(join(dir, file), "utf8")
+    .split("\n")
+    .filter((line) => line && !line.startsWith("#"))
+    .map((line) => line.split("\t"));
+
+const citiesFile = ["cities15000.txt", "cities5000.txt"].find((f) => existsSync(join(dir, f)));
+if (!citiesFile) {
+  console.error(`no cities15000.txt or cities5000.txt in ${dir}`);
+  process.exit(1);
+}
+
+// "US.NY" -> "New York"
+const regions = new Map(rows("admin1CodesASCII.txt").map(([code, name]) => [code, name]));
+// "US" -> "United States"
+const countries = new Map(rows("countryInfo.txt").map((cols) => [cols[0], cols[4]]));
+
+// geonameid, name, asciiname, alternatenames, latitude, longitude, feature
+// class and code, country code, cc2, admin1..4 codes, population, ...
+const places = rows(citiesFile)
+  .map((cols) => ({
+    name: cols[1],
+    region: regions.get(`${cols[8]}.${cols[10]}`) ?? "",
+    countryCode: cols[8],
+    country: countries.get(cols[8]) ?? cols[8],
+    latitude: cols[4],
+    longitude: cols[5],
+    population: Number(cols[14]) || 0,
+  }))
+  .filter((p) => p.name && p.countryCode)
+  // Most populous first, so the file diffs cleanly between dumps
+  .sort((a, b) => b.population - a.population || a.name.localeCompare(b.name));
+
+const header = [
+  "# Offline reverse-geocoding places, one per line, tab-separated:",
+  "# name\tregion\tcountry_code\tcountry\tlatitude\tlongitude\tpopulation",
+  `# Generated from GeoNames ${citiesFile} (CC BY 4.0, https://www.geonames.org)`,
+  "# by scripts/update-places.ts. Embedded at build time by src/geocode.rs.",
+];
+const lines = places.map((p) =>
+  [p.name, p.region, p.countryCode, p.country, p.latitude, p.longitude, p.population].join("\t"),
+);
+writeFileSync(OUTPUT, [...header, ...lines, ""].join("\n"));
+console.log(`Wrote ${places.length} places to ${OUTPUT.pathname}`);
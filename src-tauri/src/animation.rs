@@ -0,0 +1,337 @@
+//! Animated GIF and WebP: frame counts and durations read from the container
+//! structure without decoding, plus full-frame decoding for previews.
+
+use crate::formats::Format;
+use anyhow::{anyhow, Result};
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, Frames};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationInfo {
+    pub frame_count: u32,
+    pub duration_ms: u64,
+}
+
+/// Browsers play GIF frames with a delay of 0 or 1 centiseconds at 100ms, so
+/// durations are reported the way the file will actually play.
+const GIF_MIN_DELAY_CS: u16 = 2;
+const GIF_DEFAULT_DELAY_CS: u16 = 10;
+
+/// Frame count and total duration of an animated GIF or WebP. Returns `None`
+/// for other formats and for files holding a single frame.
+pub fn probe(path: &Path, format: Format) -> io::Result<Option<AnimationInfo>> {
+    let mut r = BufReader::new(File::open(path)?);
+    let info = match format {
+        Format::Gif => gif_info(&mut r)?,
+        Format::WebP => webp_info(&mut r)?,
+        _ => return Ok(None),
+    };
+    Ok(info.filter(|i| i.frame_count > 1))
+}
+
+/// Delay a decoded frame will actually play for, applying the same GIF
+/// minimum-delay rule as [`probe`].
+pub fn playback_delay_ms(format: Format, delay_ms: f64) -> f64 {
+    if format == Format::Gif && delay_ms < GIF_MIN_DELAY_CS as f64 * 10.0 {
+        GIF_DEFAULT_DELAY_CS as f64 * 10.0
+    } else {
+        delay_ms
+    }
+}
+
+/// Fully composited frames of an animated GIF or WebP.
+pub fn decode_frames(path: &Path, format: Format) -> Result<Frames<'static>> {
+    let r = BufReader::new(File::open(path)?);
+    match format {
+        Format::Gif => Ok(GifDecoder::new(r)?.into_frames()),
+        Format::WebP => Ok(WebPDecoder::new(r)?.into_frames()),
+        _ => Err(anyhow!("{:?} has no animation decoder", format)),
+    }
+}
+
+fn gif_info<R: Read + Seek>(r: &mut R) -> io::Result<Option<AnimationInfo>> {
+    let mut header = [0u8; 13];
+    r.read_exact(&mut header)?;
+    if &header[..3] != b"GIF" {
+        return Ok(None);
+    }
+    let packed = header[10];
+    if packed & 0x80 != 0 {
+        skip(r, color_table_len(packed))?;
+    }
+
+    let mut info = AnimationInfo {
+        frame_count: 0,
+        duration_ms: 0,
+    };
+    end_at_eof(gif_frames(r, &mut info))?;
+    Ok(Some(info))
+}
+
+/// Count the frames after the header into `info`, up to the trailer.
+fn gif_frames<R: Read + Seek>(r: &mut R, info: &mut AnimationInfo) -> io::Result<()> {
+    let mut delay_cs = 0u16;
+    loop {
+        let mut introducer = [0u8; 1];
+        r.read_exact(&mut introducer)?;
+        match introducer[0] {
+            // Extension block
+            0x21 => {
+                let mut label = [0u8; 1];
+                r.read_exact(&mut label)?;
+                if label[0] == 0xF9 {
+                    // Graphic control: size, packed, delay (LE), transparency index
+                    let mut gce = [0u8; 5];
+                    r.read_exact(&mut gce)?;
+                    delay_cs = u16::from_le_bytes([gce[2], gce[3]]);
+                }
+                skip_sub_blocks(r)?;
+            }
+            // Image descriptor
+            0x2C => {
+                let mut desc = [0u8; 9];
+                r.read_exact(&mut desc)?;
+                if desc[8] & 0x80 != 0 {
+                    skip(r, color_table_len(desc[8]))?;
+                }
+                skip(r, 1)?; // LZW minimum code size
+                skip_sub_blocks(r)?;
+                info.frame_count += 1;
+                let delay = if delay_cs < GIF_MIN_DELAY_CS {
+                    GIF_DEFAULT_DELAY_CS
+                } else {
+                    delay_cs
+                };
+                info.duration_ms += delay as u64 * 10;
+                delay_cs = 0;
+            }
+            // Trailer or garbage
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn color_table_len(packed: u8) -> u64 {
+    3 * (1u64 << ((packed & 0x07) + 1))
+}
+
+fn skip_sub_blocks<R: Read + Seek>(r: &mut R) -> io::Result<()> {
+    loop {
+        let mut len = [0u8; 1];
+        r.read_exact(&mut len)?;
+        if len[0] == 0 {
+            return Ok(());
+        }
+        skip(r, len[0] as u64)?;
+    }
+}
+
+fn webp_info<R: Read + Seek>(r: &mut R) -> io::Result<Option<AnimationInfo>> {
+    let mut header = [0u8; 12];
+    r.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WEBP" {
+        return Ok(None);
+    }
+
+    let mut animated = false;
+    let mut info = AnimationInfo {
+        frame_count: 0,
+        duration_ms: 0,
+    };
+    end_at_eof(webp_frames(r, &mut animated, &mut info))?;
+    if !animated {
+        return Ok(Some(AnimationInfo {
+            frame_count: 1,
+            duration_ms: 0,
+        }));
+    }
+    Ok(Some(info))
+}
+
+/// Count the `ANMF` frames into `info`, walking the chunks after the RIFF
+/// header to the end of the file.
+fn webp_frames<R: Read + Seek>(
+    r: &mut R,
+    animated: &mut bool,
+    info: &mut AnimationInfo,
+) -> io::Result<()> {
+    loop {
+        let mut chunk = [0u8; 8];
+        r.read_exact(&mut chunk)?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        // Chunks are padded to an even length
+        let padded = size + (size & 1);
+        match &chunk[..4] {
+            b"VP8X" if size >= 1 => {
+                let mut flags = [0u8; 1];
+                r.read_exact(&mut flags)?;
+                *animated = flags[0] & 0x02 != 0;
+                skip(r, padded - 1)?;
+            }
+            // Frame: x, y, width - 1, height - 1, duration (24-bit LE each), flags
+            b"ANMF" if size >= 16 => {
+                let mut frame = [0u8; 16];
+                r.read_exact(&mut frame)?;
+                info.frame_count += 1;
+                info.duration_ms += u32::from_le_bytes([frame[12], frame[13], frame[14], 0]) as u64;
+                skip(r, padded - 16)?;
+            }
+            _ => skip(r, padded)?,
+        }
+    }
+}
+
+/// A file cut short (e.g. still downloading) ends the walk; the frames seen
+/// so far are reported.
+fn end_at_eof(walk: io::Result<()>) -> io::Result<()> {
+    match walk {
+        Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn skip<R: Seek>(r: &mut R, n: u64) -> io::Result<()> {
+    r.seek(SeekFrom::Current(n as i64))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Logical screen descriptor with an optional two-colour global table.
+    fn gif_header(color_table: bool) -> Vec<u8> {
+        let mut out = b"GIF89a\x10\0\x10\0".to_vec();
+        if color_table {
+            out.extend_from_slice(&[0x80, 0, 0]);
+            out.extend_from_slice(&[0; 6]);
+        } else {
+            out.extend_from_slice(&[0, 0, 0]);
+        }
+        out
+    }
+
+    /// Graphic control extension with `delay_cs`, then a one-block image.
+    fn gif_frame(delay_cs: u16) -> Vec<u8> {
+        let [lo, hi] = delay_cs.to_le_bytes();
+        let mut out = vec![0x21, 0xF9, 4, 0, lo, hi, 0, 0];
+        out.extend_from_slice(&[0x2C, 0, 0, 0, 0, 0x10, 0, 0x10, 0, 0]);
+        out.extend_from_slice(&[2, 2, 0x4C, 0x01, 0]);
+        out
+    }
+
+    fn gif(frames: &[Vec<u8>]) -> Vec<u8> {
+        [gif_header(true), frames.concat(), vec![0x3B]].concat()
+    }
+
+    fn chunk(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = kind.to_vec();
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn anmf(duration_ms: u32) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&duration_ms.to_le_bytes()[..3]);
+        frame.push(0);
+        // A frame's image data
+        frame.extend(chunk(b"VP8L", &[0x2F; 5]));
+        chunk(b"ANMF", &frame)
+    }
+
+    fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut out = b"RIFF".to_vec();
+        out.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        out.extend_from_slice(b"WEBP");
+        out.extend(body);
+        out
+    }
+
+    fn vp8x(flags: u8) -> Vec<u8> {
+        chunk(b"VP8X", &[flags, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+    }
+
+    #[test]
+    fn counts_gif_frames_and_their_delays() {
+        let bytes = gif(&[gif_frame(5), gif_frame(0), gif_frame(20)]);
+        let info = gif_info(&mut Cursor::new(bytes)).unwrap();
+        // The zero delay plays at the browser default
+        assert_eq!(
+            info,
+            Some(AnimationInfo {
+                frame_count: 3,
+                duration_ms: 350,
+            })
+        );
+        let bytes = [gif_header(false), gif_frame(10), vec![0x3B]].concat();
+        let info = gif_info(&mut Cursor::new(bytes)).unwrap().unwrap();
+        assert_eq!(info.frame_count, 1);
+        assert_eq!(gif_info(&mut Cursor::new(b"PNG..........")).unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_gif_reports_the_frames_seen() {
+        let bytes = gif(&[gif_frame(10), gif_frame(10), gif_frame(10)]);
+        let frame_len = gif_frame(10).len();
+        let header_len = gif_header(true).len();
+        // Cut inside the extension, the descriptor and the image data of the
+        // third frame
+        for cut in [3, 10, frame_len - 2] {
+            let len = header_len + 2 * frame_len + cut;
+            let info = gif_info(&mut Cursor::new(&bytes[..len])).unwrap().unwrap();
+            assert_eq!(info.frame_count, 2, "{len} bytes");
+            assert_eq!(info.duration_ms, 200);
+        }
+    }
+
+    #[test]
+    fn counts_webp_frames_and_durations() {
+        let bytes = webp(&[vp8x(0x02), chunk(b"ANIM", &[0; 6]), anmf(40), anmf(60)]);
+        let info = webp_info(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(
+            info,
+            Some(AnimationInfo {
+                frame_count: 2,
+                duration_ms: 100,
+            })
+        );
+        // Without the animation flag it is a still image
+        let bytes = webp(&[vp8x(0), chunk(b"VP8L", &[0x2F; 5])]);
+        let info = webp_info(&mut Cursor::new(bytes)).unwrap().unwrap();
+        assert_eq!(info.frame_count, 1);
+        let bytes = webp(&[chunk(b"VP8 ", &[0; 10])]);
+        let info = webp_info(&mut Cursor::new(bytes)).unwrap().unwrap();
+        assert_eq!(info.frame_count, 1);
+        assert_eq!(
+            webp_info(&mut Cursor::new(b"RIFF\0\0\0\0WAVE")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn truncated_webp_reports_the_frames_seen() {
+        let bytes = webp(&[vp8x(0x02), anmf(40), anmf(60), anmf(80)]);
+        let anmf_len = anmf(40).len();
+        let end_of_second = bytes.len() - anmf_len;
+        // Frames count once their header is read; the image data is skipped
+        let cuts = [
+            (end_of_second + 4, 2),
+            (end_of_second + 12, 2),
+            (bytes.len() - 1, 3),
+        ];
+        for (len, frames) in cuts {
+            let info = webp_info(&mut Cursor::new(&bytes[..len])).unwrap().unwrap();
+            assert_eq!(info.frame_count, frames, "{len} bytes");
+        }
+    }
+}
@@ -1,39 +1,76 @@
 use crate::database::Database;
+use crate::formats;
 use crate::indexer::{process_file, scan_directory, scan_directory_shallow};
 use crate::models::*;
-use crate::thumbnail::{generate_thumbnail, remove_all_thumbnails, remove_thumbnails_for_paths};
+use crate::thumbnail::{
+    generate_animated_preview, generate_thumbnail, remove_all_thumbnails,
+    remove_thumbnails_for_paths,
+};
+use crate::{dataset, sidecar, xmp};
+use notify::event::{ModifyKind, RenameMode};
 use notify::{Event, EventKind, RecursiveMode, Watcher};
 use once_cell::sync::Lazy;
-use rusqlite::params;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use std::sync::Mutex;
-use tauri::{AppHandle, Emitter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
 
 // --- Global Watcher Storage ---
 // This keeps the file watchers alive in memory
 static WATCHERS: Lazy<Mutex<HashMap<String, notify::RecommendedWatcher>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
-static DB: Lazy<Mutex<Database>> = Lazy::new(|| match Database::new() {
-    Ok(db) => Mutex::new(db),
-    Err(e) => {
-        eprintln!("CRITICAL: Failed to open database: {}", e);
-        panic!("Database initialization failed");
-    }
-});
-
-fn with_db<F, R>(f: F) -> Result<R, String>
+/// Run blocking SQLite work on the blocking thread pool so async command
+/// workers stay free while a query waits on a connection.
+async fn with_db<F, R>(db: &Database, f: F) -> Result<R, String>
 where
-    F: FnOnce(&Database) -> anyhow::Result<R>,
+    F: FnOnce(&Database) -> anyhow::Result<R> + Send + 'static,
+    R: Send + 'static,
 {
-    let db_guard = DB.lock().map_err(|e| e.to_string())?;
-    f(&db_guard).map_err(|e| e.to_string())
+    let db = db.clone();
+    tauri::async_runtime::spawn_blocking(move || f(&db))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// Setting that turns on writing gallery edits to XMP sidecars. Off by
+/// default: it changes files outside the library database.
+const XMP_WRITE_BACK: &str = "xmp_write_back";
+
+fn xmp_write_back(db: &Database) -> anyhow::Result<bool> {
+    Ok(db.get_setting(XMP_WRITE_BACK)?.as_deref() == Some("true"))
 }
 
-pub fn initialize_persistent_db() {}
+/// With write-back on, mirror the files' rating, tags and description into
+/// their XMP sidecars so other tools see edits made in the gallery.
+fn write_back_sidecars(db: &Database, file_ids: &[String]) -> anyhow::Result<()> {
+    if !xmp_write_back(db)? {
+        return Ok(());
+    }
+    for id in file_ids {
+        let Some(file) = db.get_file(id)? else {
+            continue;
+        };
+        // Lightroom and darktable read a 0 rating as unrated; leaving the
+        // property out would keep the rating cleared here in the sidecar
+        let rating = match file.rating {
+            None if db.rating_cleared(id)? => Some(0),
+            rating => rating,
+        };
+        let data = XmpSidecar {
+            rating,
+            labels: file.labels,
+            keywords: file.tags,
+            description: file.description,
+        };
+        xmp::write(Path::new(&file.path), &data)?;
+    }
+    Ok(())
+}
 
 fn normalize_path(p: &str) -> String {
     let canon = std::fs::canonicalize(p)
@@ -59,17 +96,23 @@ pub async fn update_settings(settings: AppSettings) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub async fn index_folder(root: String, _recursive: bool) -> Result<IndexResult, String> {
+pub async fn index_folder(
+    db: State<'_, Database>,
+    root: String,
+    _recursive: bool,
+) -> Result<IndexResult, String> {
     let norm_root = normalize_path(&root);
     let path = Path::new(&norm_root);
     if !path.exists() {
         return Err("Folder does not exist".to_string());
     }
 
-    with_db(|db| {
-        db.clear_folder(&norm_root)?;
+    let root_clone = norm_root.clone();
+    with_db(&db, move |db| {
+        db.clear_folder(&root_clone)?;
         Ok(())
-    })?;
+    })
+    .await?;
 
     let files = scan_directory(path, false)
         .await
@@ -84,17 +127,21 @@ pub async fn index_folder(root: String, _recursive: bool) -> Result<IndexResult,
         }
         let file_clone = file.clone();
         let root_clone = norm_root.clone();
-        with_db(move |db| {
+        with_db(&db, move |db| {
             db.add_file(&file_clone, &root_clone)?;
             Ok(())
-        })?;
+        })
+        .await?;
         indexed_count += 1;
     }
 
-    with_db(|db| {
-        db.set_setting("last_selected_folder", &norm_root)?;
+    let root_clone = norm_root.clone();
+    with_db(&db, move |db| {
+        db.link_raw_variants(&root_clone)?;
+        db.set_setting("last_selected_folder", &root_clone)?;
         Ok(())
-    })?;
+    })
+    .await?;
 
     Ok(IndexResult {
         total_files: indexed_count,
@@ -106,27 +153,247 @@ pub async fn index_folder(root: String, _recursive: bool) -> Result<IndexResult,
 
 #[tauri::command]
 pub async fn get_files(
+    db: State<'_, Database>,
     folder_path: Option<String>,
-    offset: usize,
+    filter: Option<FileFilter>,
+    sort: Option<FileSort>,
+    cursor: Option<FileCursor>,
     limit: usize,
+) -> Result<FilePage, String> {
+    with_db(&db, move |db| {
+        db.get_files(
+            folder_path.as_deref(),
+            &filter.unwrap_or_default(),
+            sort.unwrap_or_default(),
+            cursor.as_ref(),
+            limit,
+        )
+    })
+    .await
+}
+
+/// Map markers for geotagged files; files inside a marker can be listed
+/// with `get_files` and a `bounds` filter.
+#[tauri::command]
+pub async fn get_map_clusters(
+    db: State<'_, Database>,
+    folder_path: Option<String>,
+    filter: Option<FileFilter>,
+    zoom: u8,
+) -> Result<Vec<MapCluster>, String> {
+    with_db(&db, move |db| {
+        db.get_map_clusters(folder_path.as_deref(), &filter.unwrap_or_default(), zoom)
+    })
+    .await
+}
+
+/// Places with photos; list one with `get_files` and a `place` filter.
+#[tauri::command]
+pub async fn get_places(
+    db: State<'_, Database>,
+    folder_path: Option<String>,
+) -> Result<Vec<PlaceCount>, String> {
+    with_db(&db, move |db| db.get_places(folder_path.as_deref())).await
+}
+
+/// Photo counts per year, month or day for a timeline scrubber. Scope to an
+/// album with the filter's `album_id`.
+#[tauri::command]
+pub async fn get_date_histogram(
+    db: State<'_, Database>,
+    folder_path: Option<String>,
+    filter: Option<FileFilter>,
+    granularity: Option<DateGranularity>,
+) -> Result<Vec<DateBucket>, String> {
+    with_db(&db, move |db| {
+        db.get_date_histogram(
+            folder_path.as_deref(),
+            &filter.unwrap_or_default(),
+            granularity.unwrap_or_default(),
+        )
+    })
+    .await
+}
+
+/// Offset and `get_files` cursor of a histogram period in a `date_taken`
+/// listing.
+#[tauri::command]
+pub async fn jump_to_date(
+    db: State<'_, Database>,
+    folder_path: Option<String>,
+    filter: Option<FileFilter>,
+    direction: Option<SortDirection>,
+    period: String,
+) -> Result<DateJump, String> {
+    with_db(&db, move |db| {
+        db.jump_to_date(
+            folder_path.as_deref(),
+            &filter.unwrap_or_default(),
+            direction.unwrap_or_default(),
+            &period,
+        )
+    })
+    .await
+}
+
+/// Photos taken on today's date in earlier years.
+#[tauri::command]
+pub async fn get_on_this_day(
+    db: State<'_, Database>,
+    folder_path: Option<String>,
+    filter: Option<FileFilter>,
 ) -> Result<Vec<FileMeta>, String> {
-    with_db(|db| match folder_path {
-        Some(folder) => db.get_files_for_folder(&folder, offset, limit),
-        None => db.get_files(offset, limit),
+    let today = chrono::Local::now().date_naive();
+    with_db(&db, move |db| {
+        db.get_on_this_day(folder_path.as_deref(), &filter.unwrap_or_default(), today)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn get_albums(
+    db: State<'_, Database>,
+    kind: Option<AlbumKind>,
+) -> Result<Vec<Album>, String> {
+    with_db(&db, move |db| db.get_albums(kind)).await
+}
+
+/// Regenerate the event albums; list one with `get_files` and an
+/// `album_id` filter.
+#[tauri::command]
+pub async fn cluster_events(
+    db: State<'_, Database>,
+    options: Option<EventOptions>,
+) -> Result<Vec<Album>, String> {
+    with_db(&db, move |db| {
+        db.cluster_events(&options.unwrap_or_default())
+    })
+    .await
+}
+
+/// Save a search as a smart album; list it with `get_files` and an
+/// `album_id` filter like any other album.
+#[tauri::command]
+pub async fn create_smart_album(
+    db: State<'_, Database>,
+    name: String,
+    description: Option<String>,
+    query: SearchQuery,
+) -> Result<Album, String> {
+    with_db(&db, move |db| {
+        db.create_smart_album(&name, description.as_deref(), query)
     })
+    .await
 }
 
 #[tauri::command]
-pub async fn is_folder_indexed(folder_path: String) -> Result<bool, String> {
-    with_db(|db| {
+pub async fn update_smart_album(
+    db: State<'_, Database>,
+    album_id: String,
+    name: String,
+    description: Option<String>,
+    query: SearchQuery,
+) -> Result<(), String> {
+    with_db(&db, move |db| {
+        db.update_smart_album(&album_id, &name, description.as_deref(), &query)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn delete_album(db: State<'_, Database>, album_id: String) -> Result<(), String> {
+    with_db(&db, move |db| db.delete_album(&album_id)).await
+}
+
+/// The tag tree, flattened in path order.
+#[tauri::command]
+pub async fn get_tags(db: State<'_, Database>) -> Result<Vec<Tag>, String> {
+    with_db(&db, |db| db.get_tags()).await
+}
+
+/// Tag files with a path such as `Places|Europe|Paris`; list a tag and its
+/// descendants through `get_files` with a `search.tags` filter.
+#[tauri::command]
+pub async fn tag_files(
+    db: State<'_, Database>,
+    file_ids: Vec<String>,
+    tag: String,
+) -> Result<(), String> {
+    with_db(&db, move |db| {
+        db.tag_files(&file_ids, &tag)?;
+        write_back_sidecars(db, &file_ids)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn untag_files(
+    db: State<'_, Database>,
+    file_ids: Vec<String>,
+    tag: String,
+) -> Result<(), String> {
+    with_db(&db, move |db| {
+        db.untag_files(&file_ids, &tag)?;
+        write_back_sidecars(db, &file_ids)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn delete_tag(db: State<'_, Database>, tag_id: String) -> Result<(), String> {
+    with_db(&db, move |db| {
+        let file_ids = db.delete_tag(&tag_id)?;
+        write_back_sidecars(db, &file_ids)
+    })
+    .await
+}
+
+/// Rate files from 0 to 5 stars; `None` clears the rating.
+#[tauri::command]
+pub async fn set_rating(
+    db: State<'_, Database>,
+    file_ids: Vec<String>,
+    rating: Option<i32>,
+) -> Result<(), String> {
+    if rating.is_some_and(|r| !(0..=5).contains(&r)) {
+        return Err("Rating must be between 0 and 5".into());
+    }
+    with_db(&db, move |db| {
+        db.set_rating(&file_ids, rating)?;
+        write_back_sidecars(db, &file_ids)
+    })
+    .await
+}
+
+/// Whether gallery edits are written to XMP sidecars.
+#[tauri::command]
+pub async fn get_xmp_write_back(db: State<'_, Database>) -> Result<bool, String> {
+    with_db(&db, xmp_write_back).await
+}
+
+#[tauri::command]
+pub async fn set_xmp_write_back(db: State<'_, Database>, enabled: bool) -> Result<(), String> {
+    with_db(&db, move |db| {
+        db.set_setting(XMP_WRITE_BACK, if enabled { "true" } else { "false" })
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn is_folder_indexed(
+    db: State<'_, Database>,
+    folder_path: String,
+) -> Result<bool, String> {
+    with_db(&db, move |db| {
         let folders = db.get_indexed_folders()?;
         Ok(folders.contains(&folder_path))
     })
+    .await
 }
 
 #[tauri::command]
-pub async fn get_indexed_folders() -> Result<Vec<String>, String> {
-    with_db(|db| db.get_indexed_folders())
+pub async fn get_indexed_folders(db: State<'_, Database>) -> Result<Vec<String>, String> {
+    with_db(&db, |db| db.get_indexed_folders()).await
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -136,8 +403,8 @@ pub struct LibraryState {
 }
 
 #[tauri::command]
-pub async fn get_library_state() -> Result<LibraryState, String> {
-    with_db(|db| {
+pub async fn get_library_state(db: State<'_, Database>) -> Result<LibraryState, String> {
+    with_db(&db, |db| {
         let last = db.get_setting("last_selected_folder")?;
         let folders = db.get_indexed_folders()?;
         Ok(LibraryState {
@@ -145,21 +412,27 @@ pub async fn get_library_state() -> Result<LibraryState, String> {
             indexed_folders: folders,
         })
     })
+    .await
 }
 
 #[tauri::command]
-pub async fn update_last_selected_folder(folder: Option<String>) -> Result<(), String> {
-    with_db(|db| {
+pub async fn update_last_selected_folder(
+    db: State<'_, Database>,
+    folder: Option<String>,
+) -> Result<(), String> {
+    with_db(&db, move |db| {
         if let Some(f) = folder {
             db.set_setting("last_selected_folder", &f)?;
         }
         Ok(())
     })
+    .await
 }
 
 #[tauri::command]
 pub async fn index_folder_streaming(
     app_handle: AppHandle,
+    db: State<'_, Database>,
     root: String,
     _recursive: bool,
 ) -> Result<IndexResult, String> {
@@ -177,7 +450,8 @@ pub async fn index_folder_streaming(
     let current_snapshot = crate::indexer::compute_folder_snapshot(path)
         .await
         .map_err(|e| e.to_string())?;
-    let last_snapshot = with_db(|db| db.get_snapshot(&norm_root))?;
+    let root_clone = norm_root.clone();
+    let last_snapshot = with_db(&db, move |db| db.get_snapshot(&root_clone)).await?;
 
     if let Some((last_count, last_mtime)) = last_snapshot {
         if last_count == current_snapshot.file_count && last_mtime == current_snapshot.agg_mtime {
@@ -207,7 +481,9 @@ pub async fn index_folder_streaming(
         shallow_map.insert(norm_lc(&s.path), (s.name.clone(), s.size, s.modified_sec));
     }
 
-    let existing_files = with_db(|db| db.get_all_file_paths_in_folder(&norm_root))?;
+    let root_clone = norm_root.clone();
+    let existing_files =
+        with_db(&db, move |db| db.get_all_file_paths_in_folder(&root_clone)).await?;
 
     let mut deleted_count = 0;
     let mut to_delete_ids = Vec::new();
@@ -222,14 +498,15 @@ pub async fn index_folder_streaming(
     }
 
     if !to_delete_ids.is_empty() {
-        with_db(|db| {
+        deleted_count = to_delete_ids.len();
+        with_db(&db, move |db| {
             for id in &to_delete_ids {
                 db.remove_file(id)?;
             }
             Ok(())
-        })?;
+        })
+        .await?;
         remove_thumbnails_for_paths(&to_delete_paths, 300);
-        deleted_count = to_delete_ids.len();
     }
 
     let mut upserted = 0;
@@ -249,10 +526,11 @@ pub async fn index_folder_streaming(
             }
             let fm_clone = fm.clone();
             let root_clone = norm_root.clone();
-            with_db(move |db| {
+            with_db(&db, move |db| {
                 db.add_file(&fm_clone, &root_clone)?;
                 Ok(())
-            })?;
+            })
+            .await?;
 
             upserted += 1;
             batch.push(fm);
@@ -274,15 +552,15 @@ pub async fn index_folder_streaming(
         app_handle.emit("files-indexed-batch", &batch).ok();
     }
 
-    with_db(|db| {
-        db.set_setting("last_selected_folder", &norm_root)?;
-        db.save_snapshot(
-            &norm_root,
-            current_snapshot.file_count,
-            current_snapshot.agg_mtime,
-        )?;
+    let root_clone = norm_root.clone();
+    let snapshot = current_snapshot.clone();
+    with_db(&db, move |db| {
+        db.link_raw_variants(&root_clone)?;
+        db.set_setting("last_selected_folder", &root_clone)?;
+        db.save_snapshot(&root_clone, snapshot.file_count, snapshot.agg_mtime)?;
         Ok(())
-    })?;
+    })
+    .await?;
 
     app_handle.emit("indexing-completed", &norm_root).ok();
 
@@ -295,8 +573,12 @@ pub async fn index_folder_streaming(
 }
 
 #[tauri::command]
-pub async fn get_thumbnail(file_id: String, size: u32) -> Result<String, String> {
-    let file_opt = with_db(|db| db.get_file(&file_id))?;
+pub async fn get_thumbnail(
+    db: State<'_, Database>,
+    file_id: String,
+    size: u32,
+) -> Result<String, String> {
+    let file_opt = with_db(&db, move |db| db.get_file(&file_id)).await?;
     if let Some(file) = file_opt {
         generate_thumbnail(&file.path, size)
             .await
@@ -306,6 +588,24 @@ pub async fn get_thumbnail(file_id: String, size: u32) -> Result<String, String>
     }
 }
 
+/// Looping GIF preview of an animated GIF/WebP, for hover playback.
+#[tauri::command]
+pub async fn get_animated_preview(
+    db: State<'_, Database>,
+    file_id: String,
+    size: u32,
+) -> Result<String, String> {
+    let file = with_db(&db, move |db| db.get_file(&file_id))
+        .await?
+        .ok_or("File not found")?;
+    if !file.is_animated {
+        return Err("File is not animated".to_string());
+    }
+    generate_animated_preview(&file.path, size)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 // Stubs for other commands
 #[tauri::command]
 pub async fn create_album(_name: String, _description: Option<String>) -> Result<Album, String> {
@@ -328,9 +628,93 @@ pub async fn open_in_explorer(_path: String) -> Result<(), String> {
     Ok(())
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WatchedFolder {
+    pub path: String,
+    /// False when the folder is persisted but no watcher is running, e.g. the
+    /// root is missing or its drive is not mounted.
+    pub active: bool,
+}
+
 #[tauri::command]
-pub async fn watch_folder(app_handle: AppHandle, folder_path: String) -> Result<(), String> {
+pub async fn watch_folder(
+    app_handle: AppHandle,
+    db: State<'_, Database>,
+    folder_path: String,
+) -> Result<(), String> {
     let norm_path = normalize_path(&folder_path);
+    start_watcher(&app_handle, &norm_path)?;
+    with_db(&db, move |db| db.add_watched_folder(&norm_path)).await
+}
+
+#[tauri::command]
+pub async fn unwatch_folder(db: State<'_, Database>, folder_path: String) -> Result<(), String> {
+    let norm_path = normalize_path(&folder_path);
+    stop_watcher(&norm_path)?;
+    with_db(&db, move |db| db.remove_watched_folder(&norm_path)).await
+}
+
+#[tauri::command]
+pub async fn list_watched_folders(db: State<'_, Database>) -> Result<Vec<WatchedFolder>, String> {
+    let persisted = with_db(&db, |db| db.get_watched_folders()).await?;
+    let watchers = WATCHERS.lock().map_err(|e| e.to_string())?;
+    Ok(persisted
+        .into_iter()
+        .map(|path| WatchedFolder {
+            active: watchers.contains_key(&path),
+            path,
+        })
+        .collect())
+}
+
+/// Re-create watchers for every persisted watched folder. Libraries created
+/// before watchers were persisted are seeded once from the indexed folders.
+/// Roots that are missing (deleted, or on an unmounted drive) are skipped but
+/// kept in the persisted set so they are picked up again on a later start.
+pub fn restore_watchers(app_handle: &AppHandle) {
+    // Runs during setup, before any async runtime work, so query directly
+    let db = app_handle.state::<Database>();
+    let load = || -> anyhow::Result<Vec<String>> {
+        if db.get_setting("watched_folders_seeded")?.is_none() {
+            for folder in db.get_indexed_folders()? {
+                db.add_watched_folder(&folder)?;
+            }
+            db.set_setting("watched_folders_seeded", "1")?;
+        }
+        db.get_watched_folders()
+    };
+    let folders = match load() {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("Failed to load watched folders: {}", e);
+            return;
+        }
+    };
+
+    for folder in folders {
+        if !Path::new(&folder).is_dir() {
+            println!("Skipping watcher for missing folder: {}", folder);
+            continue;
+        }
+        if let Err(e) = start_watcher(app_handle, &folder) {
+            eprintln!("Failed to restore watcher for {}: {}", folder, e);
+        }
+    }
+}
+
+fn stop_watcher(norm_path: &str) -> Result<(), String> {
+    let mut watchers = WATCHERS.lock().map_err(|e| e.to_string())?;
+    if let Some(mut watcher) = watchers.remove(norm_path) {
+        println!("Stopping filesystem watcher for: {}", norm_path);
+        // Dropping the watcher also stops it; unwatch is best-effort since the
+        // root may already be gone.
+        let _ = watcher.unwatch(Path::new(norm_path));
+    }
+    Ok(())
+}
+
+fn start_watcher(app_handle: &AppHandle, norm_path: &str) -> Result<(), String> {
+    let norm_path = norm_path.to_string();
 
     // 1. Check if we are already watching this folder
     let mut watchers = WATCHERS.lock().map_err(|e| e.to_string())?;
@@ -341,6 +725,7 @@ pub async fn watch_folder(app_handle: AppHandle, folder_path: String) -> Result<
     println!("Starting filesystem watcher for: {}", norm_path);
     let app_handle_clone = app_handle.clone();
     let path_clone = norm_path.clone();
+    let pending_renames: PendingRenames = Arc::new(Mutex::new(Vec::new()));
 
     // 2. Create the Watcher
     let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
@@ -350,55 +735,30 @@ pub async fn watch_folder(app_handle: AppHandle, folder_path: String) -> Result<
                     return;
                 }
 
+                // Renames are paired into a single path update so the row keeps its id
+                if let EventKind::Modify(ModifyKind::Name(mode)) = event.kind {
+                    handle_rename_event(
+                        &app_handle_clone,
+                        &path_clone,
+                        &pending_renames,
+                        mode,
+                        event.attrs.tracker(),
+                        event.paths,
+                    );
+                    return;
+                }
+
                 for path_buf in event.paths {
-                    let path_str = path_buf.to_string_lossy().to_string();
-                    let ext = path_buf
-                        .extension()
-                        .and_then(|e| e.to_str())
-                        .unwrap_or("")
-                        .to_lowercase();
-
-                    if !["jpg", "jpeg", "png", "gif", "webp"].contains(&ext.as_str()) {
+                    if !formats::is_supported(&path_buf) {
                         continue;
                     }
 
                     match event.kind {
                         EventKind::Create(_) | EventKind::Modify(_) => {
-                            let p = path_buf.clone();
-                            let root = path_clone.clone();
-                            let app = app_handle_clone.clone();
-
-                            std::thread::spawn(move || {
-                                // --- FIX IS HERE ---
-                                // .unwrap_or(None) converts the Result<Option<FileMeta>> into just Option<FileMeta>
-                                // So we pattern match on "Some(mut fm)" directly.
-                                if let Some(mut fm) =
-                                    tauri::async_runtime::block_on(process_file(&p)).unwrap_or(None)
-                                {
-                                    if let Ok(thumb) = tauri::async_runtime::block_on(
-                                        generate_thumbnail(&fm.path, 300),
-                                    ) {
-                                        fm.thumbnail_path = Some(thumb);
-                                    }
-
-                                    if let Ok(db) = DB.lock() {
-                                        let _ = db.add_file(&fm, &root);
-                                    }
-
-                                    let _ = app.emit("library-updated", ());
-                                }
-                            });
+                            spawn_upsert(&app_handle_clone, &path_clone, path_buf);
                         }
                         EventKind::Remove(_) => {
-                            let p_str = path_str.clone();
-                            let app = app_handle_clone.clone();
-
-                            std::thread::spawn(move || {
-                                if let Ok(db) = DB.lock() {
-                                    let _ = db.remove_file_by_path(&p_str);
-                                }
-                                let _ = app.emit("library-updated", ());
-                            });
+                            spawn_remove(&app_handle_clone, path_buf);
                         }
                         _ => {}
                     }
@@ -417,60 +777,182 @@ pub async fn watch_folder(app_handle: AppHandle, folder_path: String) -> Result<
 
     Ok(())
 }
+
+/// How long a rename "from" half waits for its "to" half before it is treated
+/// as a move out of the watched folder.
+const RENAME_PAIR_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Unpaired rename sources, keyed by the backend's rename tracker when it has one.
+type PendingRenames = Arc<Mutex<Vec<(Option<usize>, PathBuf)>>>;
+
+fn handle_rename_event(
+    app: &AppHandle,
+    root: &str,
+    pending: &PendingRenames,
+    mode: RenameMode,
+    tracker: Option<usize>,
+    paths: Vec<PathBuf>,
+) {
+    match mode {
+        // inotify follows a paired From/To with a combined event; the To half
+        // has already been matched through the tracker, so this is redundant.
+        RenameMode::Both => {}
+        RenameMode::From => {
+            for from in paths {
+                stash_rename_source(app, pending, tracker, from);
+            }
+        }
+        RenameMode::To => {
+            for to in paths {
+                match take_rename_source(pending, tracker) {
+                    Some(from) => spawn_rename(app, root, from, to),
+                    None if formats::is_supported(&to) => spawn_upsert(app, root, to),
+                    None => {}
+                }
+            }
+        }
+        // FSEvents and some other backends don't say which half a path is, so
+        // infer it from whether the path still exists.
+        _ => {
+            for p in paths {
+                if p.exists() {
+                    match take_rename_source(pending, tracker) {
+                        Some(from) => spawn_rename(app, root, from, p),
+                        None if formats::is_supported(&p) => spawn_upsert(app, root, p),
+                        None => {}
+                    }
+                } else {
+                    stash_rename_source(app, pending, tracker, p);
+                }
+            }
+        }
+    }
+}
+
+fn stash_rename_source(
+    app: &AppHandle,
+    pending: &PendingRenames,
+    tracker: Option<usize>,
+    from: PathBuf,
+) {
+    if let Ok(mut list) = pending.lock() {
+        list.push((tracker, from.clone()));
+    }
+
+    // If no matching "to" arrives the file left the folder; drop its row.
+    let pending = pending.clone();
+    let app = app.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(RENAME_PAIR_TIMEOUT);
+        let still_pending = match pending.lock() {
+            Ok(mut list) => match list.iter().position(|(t, p)| *t == tracker && *p == from) {
+                Some(idx) => {
+                    list.remove(idx);
+                    true
+                }
+                None => false,
+            },
+            Err(_) => false,
+        };
+        if still_pending && formats::is_supported(&from) {
+            spawn_remove(&app, from);
+        }
+    });
+}
+
+fn take_rename_source(pending: &PendingRenames, tracker: Option<usize>) -> Option<PathBuf> {
+    let mut list = pending.lock().ok()?;
+    let idx = match tracker {
+        Some(_) => list.iter().position(|(t, _)| *t == tracker)?,
+        None => list.iter().position(|(t, _)| t.is_none())?,
+    };
+    Some(list.remove(idx).1)
+}
+
+fn spawn_upsert(app: &AppHandle, root: &str, path: PathBuf) {
+    let root = root.to_string();
+    let app = app.clone();
+
+    std::thread::spawn(move || {
+        // .unwrap_or(None) converts the Result<Option<FileMeta>> into just Option<FileMeta>
+        // So we pattern match on "Some(mut fm)" directly.
+        if let Some(mut fm) = tauri::async_runtime::block_on(process_file(&path)).unwrap_or(None) {
+            if let Ok(thumb) = tauri::async_runtime::block_on(generate_thumbnail(&fm.path, 300)) {
+                fm.thumbnail_path = Some(thumb);
+            }
+
+            let db = app.state::<Database>();
+            let _ = db.add_file(&fm, &root);
+            let _ = db.link_raw_variants(&root);
+
+            let _ = app.emit("library-updated", ());
+        }
+    });
+}
+
+fn spawn_remove(app: &AppHandle, path: PathBuf) {
+    let app = app.clone();
+
+    std::thread::spawn(move || {
+        let p_str = path.to_string_lossy().to_string();
+        let _ = app.state::<Database>().remove_file_by_path(&p_str);
+        remove_thumbnails_for_paths(&[p_str], 300);
+        let _ = app.emit("library-updated", ());
+    });
+}
+
+fn spawn_rename(app: &AppHandle, root: &str, from: PathBuf, to: PathBuf) {
+    match (formats::is_supported(&from), formats::is_supported(&to)) {
+        (true, true) => {}
+        (true, false) => return spawn_remove(app, from),
+        (false, true) => return spawn_upsert(app, root, to),
+        (false, false) => return,
+    }
+
+    let root_owned = root.to_string();
+    let app_owned = app.clone();
+    std::thread::spawn(move || {
+        let from_str = from.to_string_lossy().to_string();
+        let to_str = to.to_string_lossy().to_string();
+
+        let renamed = app_owned
+            .state::<Database>()
+            .rename_file(&from_str, &to_str, &root_owned)
+            .unwrap_or(false);
+        // Thumbnails are keyed by path, so the old one is stale either way
+        remove_thumbnails_for_paths(&[from_str], 300);
+
+        if renamed {
+            let _ = app_owned.emit("library-updated", ());
+        }
+        // Refresh size/mtime/thumbnail; the upsert matches the moved row by path
+        spawn_upsert(&app_owned, &root_owned, to);
+    });
+}
+
 // Sidecar commands
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SidecarData {
     pub caption: Option<String>,
     pub metadata: Option<serde_json::Value>,
+    /// Parsed `<name>.xmp` or `<stem>.xmp`, as Lightroom and darktable write.
+    #[serde(default)]
+    pub xmp: Option<XmpSidecar>,
 }
 
 #[tauri::command]
 pub async fn get_sidecar_caption(image_path: String) -> Result<Option<String>, String> {
+    // Look for .txt, .caption.txt, or .md, then an XMP description
     let p = Path::new(&image_path);
-    let parent = match p.parent() {
-        Some(d) => d,
-        None => return Ok(None),
-    };
-    let stem_os = match p.file_stem() {
-        Some(s) => s,
-        None => return Ok(None),
-    };
-    let stem = stem_os.to_string_lossy();
-
-    // Look for .txt, .caption.txt, or .md
-    let candidates = [
-        format!("{}.txt", stem),
-        format!("{}.caption.txt", stem),
-        format!("{}.md", stem),
-    ];
-
-    for name in &candidates {
-        let candidate = parent.join(name);
-        if candidate.is_file() {
-            match fs::read_to_string(&candidate) {
-                Ok(text) => return Ok(Some(text)),
-                Err(_) => continue,
-            }
-        }
-    }
-    Ok(None)
+    Ok(sidecar::read_caption(p).or_else(|| xmp::read(p).and_then(|x| x.description)))
 }
 
 #[tauri::command]
 pub async fn get_sidecar_json(image_path: String) -> Result<Option<serde_json::Value>, String> {
-    let p = Path::new(&image_path);
-    let parent = match p.parent() {
-        Some(d) => d,
-        None => return Ok(None),
+    let candidate = match sidecar::json_path(Path::new(&image_path)) {
+        Ok(path) => path,
+        Err(_) => return Ok(None),
     };
-    let stem_os = match p.file_stem() {
-        Some(s) => s,
-        None => return Ok(None),
-    };
-    let stem = stem_os.to_string_lossy();
-
-    let json_file = format!("{}.json", stem);
-    let candidate = parent.join(&json_file);
 
     if candidate.is_file() {
         match fs::read_to_string(&candidate) {
@@ -486,116 +968,297 @@ pub async fn get_sidecar_json(image_path: String) -> Result<Option<serde_json::V
 
 #[tauri::command]
 pub async fn get_sidecar_data(image_path: String) -> Result<SidecarData, String> {
-    // We can reuse the logic above or combine it for efficiency
     let p = Path::new(&image_path);
-    let parent = match p.parent() {
-        Some(d) => d,
-        None => {
-            return Ok(SidecarData {
-                caption: None,
-                metadata: None,
-            })
+
+    // 1. Get Caption
+    let mut caption = sidecar::read_caption(p);
+
+    // 2. Get Metadata (JSON)
+    let metadata = sidecar::json_path(p)
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok());
+
+    // 3. XMP sidecar; its description stands in for a missing text caption
+    let xmp = xmp::read(p);
+    if caption.is_none() {
+        caption = xmp.as_ref().and_then(|x| x.description.clone());
+    }
+
+    Ok(SidecarData {
+        caption,
+        metadata,
+        xmp,
+    })
+}
+
+/// Replace the caption sidecar of an image, keeping its naming (`.txt`,
+/// `.caption.txt` or `.md`) or creating `<stem>.txt`, and update the caption
+/// search index. With `backup`, the previous caption is kept as `<file>.bak`.
+/// Returns the sidecar's path.
+#[tauri::command]
+pub async fn set_sidecar_caption(
+    db: State<'_, Database>,
+    image_path: String,
+    caption: String,
+    backup: Option<bool>,
+) -> Result<String, String> {
+    let image_path = normalize_path(&image_path);
+    with_db(&db, move |db| {
+        let image = Path::new(&image_path);
+        let path = sidecar::write_caption(image, &caption, backup.unwrap_or(false))?;
+        if !db.set_caption(&image_path, Some(&caption))? {
+            return Ok(path.to_string_lossy().to_string());
         }
-    };
-    let stem_os = match p.file_stem() {
-        Some(s) => s,
-        None => {
-            return Ok(SidecarData {
-                caption: None,
-                metadata: None,
-            })
+        // A RAW+JPEG pair reads the same sidecar, so its other file changes too
+        if let Some(dir) = image.parent() {
+            for sibling in db.get_file_paths_in_dir(&dir.to_string_lossy())? {
+                if sibling != image_path
+                    && sidecar::caption_path(Path::new(&sibling)).as_ref() == Some(&path)
+                {
+                    db.set_caption(&sibling, Some(&caption))?;
+                }
+            }
         }
-    };
-    let stem = stem_os.to_string_lossy();
+        Ok(path.to_string_lossy().to_string())
+    })
+    .await
+}
 
-    // 1. Get Caption
-    let mut caption = None;
-    let caption_candidates = [
-        format!("{}.txt", stem),
-        format!("{}.caption.txt", stem),
-        format!("{}.md", stem),
-    ];
-
-    for name in &caption_candidates {
-        let candidate = parent.join(name);
-        if candidate.is_file() {
-            if let Ok(text) = fs::read_to_string(&candidate) {
-                caption = Some(text);
-                break;
+/// Run `edits` over the caption sidecars of `file_ids`, or of every file in
+/// `folder_path`; files without a caption sidecar are skipped. Returns the
+/// captions that change. With `dry_run` nothing is written, so the result
+/// is a preview.
+#[tauri::command]
+pub async fn edit_captions(
+    db: State<'_, Database>,
+    file_ids: Option<Vec<String>>,
+    folder_path: Option<String>,
+    edits: Vec<CaptionEdit>,
+    dry_run: bool,
+    backup: Option<bool>,
+) -> Result<Vec<CaptionChange>, String> {
+    with_db(&db, move |db| {
+        let editor = sidecar::CaptionEditor::new(&edits)?;
+        let files = match (file_ids, folder_path) {
+            (Some(ids), _) => {
+                let mut files = Vec::new();
+                for id in ids {
+                    if let Some(file) = db.get_file(&id)? {
+                        files.push((file.id, file.path));
+                    }
+                }
+                files
+            }
+            (None, Some(folder)) => db.get_all_file_paths_in_folder(&folder)?,
+            (None, None) => anyhow::bail!("Either file ids or a folder is required"),
+        };
+
+        // RAW+JPEG pairs and repeated ids share a sidecar: edit each sidecar
+        // once, then store the caption on every file that reads it
+        let mut sidecars: Vec<(PathBuf, String, Vec<String>)> = Vec::new();
+        let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+        for (file_id, path) in files {
+            let Some(sidecar_path) = sidecar::caption_path(Path::new(&path)) else {
+                continue;
+            };
+            match seen.get(&sidecar_path) {
+                Some(&i) if sidecars[i].2.contains(&path) => {}
+                Some(&i) => sidecars[i].2.push(path),
+                None => {
+                    seen.insert(sidecar_path.clone(), sidecars.len());
+                    sidecars.push((sidecar_path, file_id, vec![path]));
+                }
             }
         }
-    }
 
-    // 2. Get Metadata (JSON)
-    let mut metadata = None;
-    let json_file = format!("{}.json", stem);
-    let json_candidate = parent.join(&json_file);
+        let mut changes = Vec::new();
+        for (sidecar_path, file_id, paths) in sidecars {
+            let before = fs::read_to_string(&sidecar_path)?;
+            let after = editor.apply(&before);
+            if after == before {
+                continue;
+            }
+            if !dry_run {
+                sidecar::write_atomic(&sidecar_path, after.as_bytes(), backup.unwrap_or(false))?;
+                for path in &paths {
+                    db.set_caption(path, Some(&after))?;
+                }
+            }
+            changes.push(CaptionChange {
+                file_id,
+                sidecar_path: sidecar_path.to_string_lossy().to_string(),
+                before,
+                after,
+            });
+        }
+        Ok(changes)
+    })
+    .await
+}
+
+/// Tag counts and caption problems of a training-dataset folder. Captions
+/// longer than `max_tokens` (75 if unset) are reported as too long.
+#[tauri::command]
+pub async fn get_caption_stats(
+    folder_path: String,
+    max_tokens: Option<usize>,
+) -> Result<CaptionStats, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let max_tokens = max_tokens.unwrap_or(sidecar::DEFAULT_MAX_TOKENS);
+        sidecar::caption_stats(Path::new(&folder_path), max_tokens).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
 
-    if json_candidate.is_file() {
-        if let Ok(content) = fs::read_to_string(&json_candidate) {
-            metadata = serde_json::from_str(&content).ok();
+/// Files of a selection, an album or a folder, in that order of precedence.
+fn dataset_files(
+    db: &Database,
+    file_ids: Option<Vec<String>>,
+    album_id: Option<String>,
+    folder_path: Option<String>,
+) -> anyhow::Result<Vec<FileMeta>> {
+    let mut files = Vec::new();
+    if let Some(ids) = file_ids {
+        for id in ids {
+            files.extend(db.get_file(&id)?);
+        }
+    } else if album_id.is_some() || folder_path.is_some() {
+        let filter = FileFilter {
+            album_id,
+            ..Default::default()
+        };
+        let mut cursor = None;
+        loop {
+            let page = db.get_files(
+                folder_path.as_deref(),
+                &filter,
+                FileSort::default(),
+                cursor.as_ref(),
+                500,
+            )?;
+            files.extend(page.files);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
         }
+    } else {
+        anyhow::bail!("File ids, an album or a folder is required");
     }
+    Ok(files)
+}
+
+/// Export a selection, an album or a folder as a training dataset, with
+/// `dataset-export-progress` events along the way.
+#[tauri::command]
+pub async fn export_dataset(
+    app: AppHandle,
+    db: State<'_, Database>,
+    file_ids: Option<Vec<String>>,
+    album_id: Option<String>,
+    folder_path: Option<String>,
+    options: DatasetExportOptions,
+) -> Result<DatasetExportSummary, String> {
+    with_db(&db, move |db| {
+        let files = dataset_files(db, file_ids, album_id, folder_path)?;
+        dataset::export(&files, &options, |current, total| {
+            let update = ProgressUpdate {
+                current,
+                total,
+                message: format!("Exported {} of {} images...", current, total),
+            };
+            app.emit("dataset-export-progress", update).ok();
+        })
+    })
+    .await
+}
 
-    Ok(SidecarData { caption, metadata })
+/// Aspect-ratio buckets and quality problems of a selection, an album or a
+/// folder, with `dataset-check-progress` events during the decode pass.
+#[tauri::command]
+pub async fn check_dataset(
+    app: AppHandle,
+    db: State<'_, Database>,
+    file_ids: Option<Vec<String>>,
+    album_id: Option<String>,
+    folder_path: Option<String>,
+    options: Option<BucketOptions>,
+) -> Result<BucketReport, String> {
+    with_db(&db, move |db| {
+        let files = dataset_files(db, file_ids, album_id, folder_path)?;
+        dataset::check(&files, &options.unwrap_or_default(), |current, total| {
+            let update = ProgressUpdate {
+                current,
+                total,
+                message: format!("Checked {} of {} images...", current, total),
+            };
+            app.emit("dataset-check-progress", update).ok();
+        })
+    })
+    .await
 }
-#[derive(Deserialize)]
-pub struct SearchQuery {
-    pub query: Option<String>,
+
+/// Replace the `<stem>.json` sidecar of an image. Returns the sidecar's path.
+#[tauri::command]
+pub async fn set_sidecar_json(
+    image_path: String,
+    metadata: serde_json::Value,
+    backup: Option<bool>,
+) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        sidecar::write_json(Path::new(&image_path), &metadata, backup.unwrap_or(false))
+            .map(|path| path.to_string_lossy().to_string())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
+/// Files matching `query`, newest first; at most 100.
 #[tauri::command]
-pub async fn search_files(query: SearchQuery) -> Result<Vec<FileMeta>, String> {
-    let q = query.query.unwrap_or_default();
-    if q.is_empty() {
+pub async fn search_files(
+    db: State<'_, Database>,
+    query: SearchQuery,
+) -> Result<Vec<FileMeta>, String> {
+    let query = SearchQuery {
+        query: query.query.filter(|q| !q.is_empty()),
+        ..query
+    };
+    if query == SearchQuery::default() {
         return Ok(vec![]);
     }
 
-    with_db(|db| {
-        let conn = db.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id, path, name, size, modified, file_type, thumbnail_path FROM files WHERE name LIKE ?1 OR path LIKE ?1 LIMIT 100")
-            .map_err(|e| anyhow::anyhow!(e))?;
-
-        let wildcard = format!("%{}%", q);
-        let rows = stmt
-            .query_map(params![wildcard], |row| {
-                Ok(FileMeta {
-                    id: row.get(0)?,
-                    path: row.get(1)?,
-                    name: row.get(2)?,
-                    size: row.get(3)?,
-                    modified: row.get(4)?,
-                    file_type: row.get(5)?,
-                    thumbnail_path: row.get(6)?,
-                    ..Default::default() // <--- ADDED THIS
-                })
-            })
-            .map_err(|e| anyhow::anyhow!(e))?;
-
-        let mut results = Vec::new();
-        for r in rows {
-            results.push(r.unwrap());
-        }
-        Ok(results)
+    with_db(&db, move |db| {
+        let filter = FileFilter {
+            search: Some(query),
+            ..Default::default()
+        };
+        let page = db.get_files(None, &filter, FileSort::default(), None, 100)?;
+        Ok(page.files)
     })
+    .await
 }
 
 #[tauri::command]
-pub async fn reset_library() -> Result<(), String> {
-    with_db(|db| db.clear_library())?;
+pub async fn reset_library(db: State<'_, Database>) -> Result<(), String> {
+    with_db(&db, |db| db.clear_library()).await?;
+    WATCHERS.lock().map_err(|e| e.to_string())?.clear();
     remove_all_thumbnails();
     Ok(())
 }
 
 #[tauri::command]
-pub async fn reset_folder(folder_path: String) -> Result<(), String> {
+pub async fn reset_folder(db: State<'_, Database>, folder_path: String) -> Result<(), String> {
     let norm = normalize_path(&folder_path);
-    let paths: Vec<String> = with_db(|db| {
+    let paths: Vec<String> = with_db(&db, move |db| {
         let files = db.get_all_file_paths_in_folder(&norm)?;
+        db.clear_folder(&norm)?;
         Ok(files.into_iter().map(|(_, p)| p).collect())
-    })?;
+    })
+    .await?;
 
-    with_db(|db| db.clear_folder(&norm))?;
     remove_thumbnails_for_paths(&paths, 300);
     Ok(())
 }
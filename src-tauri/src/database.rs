@@ -1,26 +1,450 @@
-use crate::models::FileMeta;
-use anyhow::Result;
-use rusqlite::{params, Connection, OptionalExtension};
+use crate::formats::Format;
+use crate::geocode;
+use crate::keywords;
+use crate::metadata;
+use crate::migrations;
+use crate::models::{
+    Album, AlbumKind, AnimationMetadata, DateBucket, DateGranularity, DateJump, Dimensions,
+    EventOptions, FileCursor, FileFilter, FileMeta, FilePage, FileSort, FileVariant, GeoBounds,
+    GpsCoordinates, ImageMetadata, MapCluster, MediaType, Orientation, Place, PlaceCount,
+    SearchQuery, SortDirection, SortKey, SortValue, Tag, VideoMetadata,
+};
+use anyhow::{anyhow, Result};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Row, ToSql};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 
+/// Read-only connections kept open next to the writer. WAL lets them read a
+/// consistent snapshot while the writer is mid-transaction, so grid paging
+/// doesn't wait on indexing.
+const READ_POOL_SIZE: usize = 4;
+
+/// Handle to the library database: one writer connection plus a small pool
+/// of read-only connections. Cheap to clone; clones share the connections.
+#[derive(Clone)]
 pub struct Database {
-    pub conn: Mutex<Connection>,
+    inner: Arc<Connections>,
 }
 
-impl Database {
-    pub fn new() -> Result<Self> {
-        let db_path = Self::db_file_path();
+struct Connections {
+    writer: Mutex<Connection>,
+    /// Empty for in-memory databases, which can't be shared between
+    /// connections; reads then go through the writer.
+    readers: Option<ReadPool>,
+}
+
+struct ReadPool {
+    idle: Mutex<Vec<Connection>>,
+    returned: Condvar,
+}
+
+/// A connection for reading: pooled when the database has a read pool,
+/// otherwise the writer.
+pub enum ReadConnection<'a> {
+    Pooled(PooledConnection<'a>),
+    Writer(MutexGuard<'a, Connection>),
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            ReadConnection::Pooled(c) => c,
+            ReadConnection::Writer(c) => c,
+        }
+    }
+}
+
+/// Read-only connection checked out of the pool; returned on drop.
+pub struct PooledConnection<'a> {
+    pool: &'a ReadPool,
+    conn: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection present until drop")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            if let Ok(mut idle) = self.pool.idle.lock() {
+                idle.push(conn);
+                self.pool.returned.notify_one();
+            }
+        }
+    }
+}
+
+impl ReadPool {
+    fn open(path: &Path, size: usize) -> Result<Self> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let mut idle = Vec::with_capacity(size);
+        for _ in 0..size {
+            idle.push(Connection::open_with_flags(path, flags)?);
+        }
+        Ok(Self {
+            idle: Mutex::new(idle),
+            returned: Condvar::new(),
+        })
+    }
+
+    /// Wait for an idle connection.
+    fn get(&self) -> Result<PooledConnection<'_>> {
+        let mut idle = self
+            .idle
+            .lock()
+            .map_err(|_| anyhow!("database read pool poisoned"))?;
+        loop {
+            if let Some(conn) = idle.pop() {
+                return Ok(PooledConnection {
+                    pool: self,
+                    conn: Some(conn),
+                });
+            }
+            idle = self
+                .returned
+                .wait(idle)
+                .map_err(|_| anyhow!("database read pool poisoned"))?;
+        }
+    }
+}
+
+/// Columns read by [`Database::file_from_row`], in order.
+pub const FILE_COLUMNS: &str = "id, path, name, size, modified_at, file_type, thumbnail_path, \
+     media_type, duration_ms, video_codec, is_animated, frame_count, date_taken, width, height, \
+     rating, created_at, latitude, longitude, country_code, country, region, city, labels, \
+     description, caption";
+const FILE_COLUMN_COUNT: usize = 26;
+
+/// Shuffle keys keep only their low 48 bits, so the XOR in [`sort_expr`]
+/// stays within SQLite's positive integers.
+const SHUFFLE_MASK: u64 = (1 << 48) - 1;
+
+/// Lowercased name with each digit run replaced by its length and value, so
+/// that comparing keys as text orders `img2` before `img10`.
+pub fn natural_sort_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len() + 8);
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() {
+            let mut run = String::from(c);
+            while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+                run.push(d);
+            }
+            let digits = run.trim_start_matches('0');
+            key.push_str(&format!("{:02}{}", digits.len(), digits));
+        } else {
+            key.extend(c.to_lowercase());
+        }
+    }
+    key
+}
+
+/// Stable per-file value that random order is derived from.
+pub fn shuffle_key(id: &str) -> i64 {
+    let digest = Sha256::digest(id.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    (u64::from_le_bytes(bytes) & SHUFFLE_MASK) as i64
+}
+
+/// Aspect ratios this close to 1 count as square.
+const SQUARE_TOLERANCE: f64 = 0.01;
+
+/// Append `filter`'s conditions to a `WHERE` clause. `smart` is the saved
+/// query of the filter's album when that is a smart album; see
+/// [`Database::smart_query`].
+fn push_filter<'a>(
+    sql: &mut String,
+    args: &mut Vec<&'a dyn ToSql>,
+    filter: &'a FileFilter,
+    smart: Option<&'a SearchQuery>,
+) -> Result<()> {
+    match filter.orientation {
+        Some(Orientation::Portrait) => {
+            sql.push_str(&format!(" AND aspect_ratio < {}", 1.0 - SQUARE_TOLERANCE))
+        }
+        Some(Orientation::Landscape) => {
+            sql.push_str(&format!(" AND aspect_ratio > {}", 1.0 + SQUARE_TOLERANCE))
+        }
+        Some(Orientation::Square) => sql.push_str(&format!(
+            " AND aspect_ratio BETWEEN {} AND {}",
+            1.0 - SQUARE_TOLERANCE,
+            1.0 + SQUARE_TOLERANCE
+        )),
+        None => {}
+    }
+    if let Some(min) = &filter.min_width {
+        sql.push_str(" AND width >= ?");
+        args.push(min);
+    }
+    if let Some(min) = &filter.min_height {
+        sql.push_str(" AND height >= ?");
+        args.push(min);
+    }
+    if let Some(b) = &filter.bounds {
+        sql.push_str(" AND latitude BETWEEN ? AND ?");
+        args.push(&b.south);
+        args.push(&b.north);
+        if b.west <= b.east {
+            sql.push_str(" AND longitude BETWEEN ? AND ?");
+        } else {
+            sql.push_str(" AND (longitude >= ? OR longitude <= ?)");
+        }
+        args.push(&b.west);
+        args.push(&b.east);
+    }
+    if let Some(place) = &filter.place {
+        sql.push_str(" AND country_code = ?");
+        args.push(&place.country_code);
+        if let Some(region) = &place.region {
+            sql.push_str(" AND region = ?");
+            args.push(region);
+        }
+        if let Some(city) = &place.city {
+            sql.push_str(" AND city = ?");
+            args.push(city);
+        }
+    }
+    match (smart, &filter.album_id) {
+        (Some(query), _) => push_search(sql, args, query)?,
+        (None, Some(album)) => {
+            sql.push_str(" AND id IN (SELECT file_id FROM album_files WHERE album_id = ?)");
+            args.push(album);
+        }
+        (None, None) => {}
+    }
+    if let Some(query) = &filter.search {
+        push_search(sql, args, query)?;
+    }
+    Ok(())
+}
+
+/// Append a search's conditions to a `WHERE` clause.
+fn push_search<'a>(
+    sql: &mut String,
+    args: &mut Vec<&'a dyn ToSql>,
+    query: &'a SearchQuery,
+) -> Result<()> {
+    let placeholders = |n: usize, each: &str| vec![each; n].join(", ");
+    if let Some(text) = &query.query {
+        sql.push_str(
+            " AND (name LIKE '%' || ? || '%' OR path LIKE '%' || ? || '%'
+                   OR caption LIKE '%' || ? || '%')",
+        );
+        args.extend([text as &dyn ToSql, text, text]);
+    }
+    if let Some(types) = &query.file_types {
+        sql.push_str(&format!(
+            " AND file_type IN ({})",
+            placeholders(types.len(), "LTRIM(LOWER(?), '.')")
+        ));
+        args.extend(types.iter().map(|t| t as &dyn ToSql));
+    }
+    if let Some(range) = &query.date_range {
+        // Bounds are parsed here, so they go into the SQL as integers
+        if let Some(start) = &range.start {
+            sql.push_str(&format!(
+                " AND {} >= {}",
+                CAPTURE_TIME,
+                date_bound(start, false)?
+            ));
+        }
+        if let Some(end) = &range.end {
+            sql.push_str(&format!(
+                " AND {} <= {}",
+                CAPTURE_TIME,
+                date_bound(end, true)?
+            ));
+        }
+    }
+    if let Some(range) = &query.size_range {
+        if let Some(min) = &range.min {
+            sql.push_str(" AND size >= ?");
+            args.push(min);
+        }
+        if let Some(max) = &range.max {
+            sql.push_str(" AND size <= ?");
+            args.push(max);
+        }
+    }
+    for tag in query.tags.iter().flatten() {
+        sql.push_str(&format!(
+            " AND id IN (SELECT file_id FROM file_tags WHERE tag_id IN ({}))",
+            TAG_SUBTREE
+        ));
+        args.extend([tag as &dyn ToSql, tag, tag]);
+    }
+    if let Some(albums) = &query.albums {
+        sql.push_str(&format!(
+            " AND id IN (SELECT file_id FROM album_files WHERE album_id IN ({}))",
+            placeholders(albums.len(), "?")
+        ));
+        args.extend(albums.iter().map(|a| a as &dyn ToSql));
+    }
+    if let Some(rating) = &query.rating {
+        sql.push_str(" AND rating >= ?");
+        args.push(rating);
+    }
+    Ok(())
+}
+
+/// Ids of the tag at a path and every tag below it; binds the path three
+/// times. Paths below `a` sort between `a|` and `a}`, so both halves use the
+/// unique index on `path`.
+const TAG_SUBTREE: &str =
+    "SELECT id FROM tags WHERE path = ? OR (path > ? || '|' AND path < ? || '}')";
+
+/// Epoch milliseconds of a [`DateRange`](crate::models::DateRange) bound: a
+/// timestamp, or the first or last moment of a period.
+fn date_bound(value: &str, end: bool) -> Result<i64> {
+    match period_range(value) {
+        Some((start, next)) => Ok(if end { next - 1 } else { start }),
+        None => metadata::timestamp_ms(value).ok_or_else(|| anyhow!("Invalid date: {}", value)),
+    }
+}
+
+/// Marker size the map clusters for, relative to a 256px web-map tile.
+const CLUSTER_PX: f64 = 60.0;
+const TILE_PX: f64 = 256.0;
+const MAX_ZOOM: u8 = 22;
+
+/// When a file was taken, or modified if that's unknown.
+const CAPTURE_TIME: &str = "COALESCE(taken_at, modified_at)";
+
+/// Local-time bounds `[start, end)` in epoch milliseconds of a
+/// [`DateBucket::period`].
+fn period_range(period: &str) -> Option<(i64, i64)> {
+    use chrono::NaiveDate;
+    let parts = period
+        .split('-')
+        .map(|p| p.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (start, end) = match parts[..] {
+        [y] => (
+            NaiveDate::from_ymd_opt(y as i32, 1, 1)?,
+            NaiveDate::from_ymd_opt(y as i32 + 1, 1, 1)?,
+        ),
+        [y, m] => {
+            let start = NaiveDate::from_ymd_opt(y as i32, m, 1)?;
+            (start, start.checked_add_months(chrono::Months::new(1))?)
+        }
+        [y, m, d] => {
+            let start = NaiveDate::from_ymd_opt(y as i32, m, d)?;
+            (start, start.succ_opt()?)
+        }
+        _ => return None,
+    };
+    let midnight = |date: NaiveDate| metadata::timestamp_ms(&format!("{}T00:00:00", date));
+    Some((midnight(start)?, midnight(end)?))
+}
+
+/// Stored epoch milliseconds as the UTC RFC 3339 string `FileMeta` carries.
+fn rfc3339_from_ms(ms: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(ms)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+/// Stored epoch milliseconds as RFC 3339, or `""` for unknown times.
+fn optional_rfc3339(ms: Option<i64>) -> String {
+    ms.map(rfc3339_from_ms).unwrap_or_default()
+}
 
+/// Title of an event album: its place, if known, and its local date span
+/// such as `2–5 March 2024`.
+fn event_name(city: Option<&str>, starts_at: i64, ends_at: i64) -> String {
+    use chrono::{Datelike, Local, TimeZone};
+    let date = |ms: i64| {
+        Local
+            .timestamp_millis_opt(ms)
+            .earliest()
+            .unwrap_or_default()
+            .date_naive()
+    };
+    let (start, end) = (date(starts_at), date(ends_at));
+    let span = if start == end {
+        end.format("%-d %B %Y").to_string()
+    } else if (start.year(), start.month()) == (end.year(), end.month()) {
+        format!("{}–{}", start.format("%-d"), end.format("%-d %B %Y"))
+    } else if start.year() == end.year() {
+        format!("{} – {}", start.format("%-d %B"), end.format("%-d %B %Y"))
+    } else {
+        format!(
+            "{} – {}",
+            start.format("%-d %B %Y"),
+            end.format("%-d %B %Y")
+        )
+    };
+    match city {
+        Some(city) => format!("{}, {}", city, span),
+        None => span,
+    }
+}
+
+/// SQL expression a listing is ordered by. Nullable columns are coalesced so
+/// keyset comparisons never meet NULL; the expressions match the indexes
+/// created by the "sort orders" migration.
+fn sort_expr(sort: &FileSort) -> String {
+    match sort.key {
+        SortKey::Name => "name_key".into(),
+        SortKey::Size => "size".into(),
+        SortKey::DateTaken => CAPTURE_TIME.into(),
+        SortKey::Modified => "modified_at".into(),
+        SortKey::Dimensions => "COALESCE(width * height, 0)".into(),
+        SortKey::Rating => "COALESCE(rating, 0)".into(),
+        // XOR with the hashed seed: a different permutation per seed, even
+        // for neighbouring seeds, with no index needed
+        SortKey::Random => {
+            let seed = shuffle_key(&sort.seed.to_string());
+            format!("((shuffle_key | {0}) - (shuffle_key & {0}))", seed)
+        }
+    }
+}
+
+impl ToSql for SortValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            SortValue::Int(v) => v.to_sql(),
+            SortValue::Text(v) => v.to_sql(),
+        }
+    }
+}
+
+impl FromSql for SortValue {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Integer(v) => Ok(SortValue::Int(v)),
+            ValueRef::Text(_) => String::column_result(value).map(SortValue::Text),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl Database {
+    /// Open (or create) a library database at `path` and migrate it to the
+    /// current schema.
+    pub fn open(path: &Path) -> Result<Self> {
         // Ensure directory exists
-        if let Some(parent) = db_path.parent() {
+        if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        let conn = Connection::open(db_path)?;
+        let mut conn = Connection::open(path)?;
 
-        // --- FIX STARTS HERE ---
         // We use execute_batch because 'PRAGMA journal_mode' returns a row ("wal"),
         // which causes normal 'execute()' to panic with "Execute returned results".
         conn.execute_batch(
@@ -28,248 +452,1247 @@ impl Database {
              PRAGMA synchronous = NORMAL;
              PRAGMA foreign_keys = ON;",
         )?;
-        // --- FIX ENDS HERE ---
 
-        let db = Self {
-            conn: Mutex::new(conn),
-        };
-        db.init_schema()?;
-        Ok(db)
-    }
+        migrations::run(&mut conn, Some(path))?;
+        geocode::sync_places(&conn)?;
 
-    fn db_file_path() -> PathBuf {
-        dirs::data_dir()
-            .unwrap_or_else(|| std::env::current_dir().unwrap())
-            .join("local-gallery")
-            .join("library.db")
+        // Readers open after migrating so they never see an older schema
+        let readers = ReadPool::open(path, READ_POOL_SIZE)?;
+        Ok(Self {
+            inner: Arc::new(Connections {
+                writer: Mutex::new(conn),
+                readers: Some(readers),
+            }),
+        })
     }
 
-    fn init_schema(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS files (
-                id TEXT PRIMARY KEY,
-                path TEXT NOT NULL UNIQUE,
-                name TEXT NOT NULL,
-                size INTEGER NOT NULL,
-                modified TEXT NOT NULL,
-                file_type TEXT,
-                thumbnail_path TEXT,
-                folder_path TEXT NOT NULL
-            )",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_folder_path ON files(folder_path)",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS folder_snapshots (
-                path TEXT PRIMARY KEY,
-                file_count INTEGER NOT NULL,
-                agg_mtime INTEGER NOT NULL
-            )",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS kv_store (
-                key TEXT PRIMARY KEY,
-                value TEXT
-            )",
-            [],
-        )?;
+    /// A private, empty library that lives only as long as the value; used
+    /// by tests.
+    pub fn in_memory() -> Result<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        migrations::run(&mut conn, None)?;
+        geocode::sync_places(&conn)?;
+        Ok(Self {
+            inner: Arc::new(Connections {
+                writer: Mutex::new(conn),
+                readers: None,
+            }),
+        })
+    }
 
-        // New tables for Albums
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS albums (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                description TEXT,
-                cover_image_id TEXT
-            )",
-            [],
-        )?;
+    /// The single writer connection. Hold it only for the write itself;
+    /// every other writer waits on it.
+    pub fn writer(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.inner
+            .writer
+            .lock()
+            .map_err(|_| anyhow!("database writer poisoned"))
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS album_files (
-                album_id TEXT NOT NULL,
-                file_id TEXT NOT NULL,
-                PRIMARY KEY (album_id, file_id),
-                FOREIGN KEY(album_id) REFERENCES albums(id) ON DELETE CASCADE,
-                FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
-            )",
-            [],
-        )?;
+    /// A connection for queries. Never blocks on the writer when the
+    /// database has a read pool.
+    pub fn reader(&self) -> Result<ReadConnection<'_>> {
+        match &self.inner.readers {
+            Some(pool) => Ok(ReadConnection::Pooled(pool.get()?)),
+            None => Ok(ReadConnection::Writer(self.writer()?)),
+        }
+    }
 
-        Ok(())
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| std::env::current_dir().unwrap())
+            .join("local-gallery")
+            .join("library.db")
     }
 
     // --- File Operations ---
 
     pub fn add_file(&self, file: &FileMeta, folder_path: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO files (id, path, name, size, modified, file_type, thumbnail_path, folder_path)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        let conn = self.writer()?;
+        let video = file.video.as_ref();
+        let date_taken = file.metadata.as_ref().and_then(|m| m.date_taken.clone());
+        let gps = file
+            .metadata
+            .as_ref()
+            .and_then(|m| m.gps_coordinates.as_ref());
+        let place = match gps {
+            Some(gps) => geocode::nearest_place(&conn, gps.latitude, gps.longitude)?,
+            None => None,
+        };
+        // Videos and animated images share the duration column
+        let duration_ms = video
+            .and_then(|v| v.duration_ms)
+            .or(file.animation.as_ref().map(|a| a.duration_ms));
+        // The row and its embedded keywords change together
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO files (id, path, name, size, modified_at, file_type, thumbnail_path, folder_path,
+                                media_type, duration_ms, video_codec, is_animated, frame_count,
+                                name_key, shuffle_key, date_taken, width, height, rating,
+                                created_at, taken_at, aspect_ratio,
+                                latitude, longitude, country_code, country, region, city,
+                                labels, description, caption, rating_source)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13,
+                     ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22,
+                     ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32)
              ON CONFLICT(path) DO UPDATE SET
                 size=excluded.size,
-                modified=excluded.modified,
+                modified_at=excluded.modified_at,
+                created_at=excluded.created_at,
+                taken_at=excluded.taken_at,
+                file_type=excluded.file_type,
                 thumbnail_path=excluded.thumbnail_path,
-                folder_path=excluded.folder_path",
+                folder_path=excluded.folder_path,
+                media_type=excluded.media_type,
+                duration_ms=excluded.duration_ms,
+                video_codec=excluded.video_codec,
+                is_animated=excluded.is_animated,
+                frame_count=excluded.frame_count,
+                date_taken=excluded.date_taken,
+                width=excluded.width,
+                height=excluded.height,
+                aspect_ratio=excluded.aspect_ratio,
+                latitude=excluded.latitude,
+                longitude=excluded.longitude,
+                country_code=excluded.country_code,
+                country=excluded.country,
+                region=excluded.region,
+                city=excluded.city,
+                labels=excluded.labels,
+                caption=excluded.caption,
+                rating=CASE WHEN files.rating_source = 'user' THEN files.rating
+                            ELSE excluded.rating END,
+                rating_source=CASE WHEN files.rating_source = 'user' THEN 'user'
+                                   ELSE excluded.rating_source END,
+                description=COALESCE(excluded.description, files.description)",
             params![
                 file.id,
                 file.path,
                 file.name,
                 file.size,
-                file.modified,
+                metadata::timestamp_ms(&file.modified).unwrap_or_default(),
                 file.file_type,
                 file.thumbnail_path,
-                folder_path
+                folder_path,
+                file.media_type.as_str(),
+                duration_ms.map(|d| d as i64),
+                video.and_then(|v| v.codec.clone()),
+                file.is_animated,
+                file.animation.as_ref().map(|a| a.frame_count),
+                natural_sort_key(&file.name),
+                shuffle_key(&file.id),
+                date_taken,
+                file.dimensions.as_ref().map(|d| d.width),
+                file.dimensions.as_ref().map(|d| d.height),
+                file.rating,
+                metadata::timestamp_ms(&file.created),
+                date_taken.as_deref().and_then(metadata::timestamp_ms),
+                file.dimensions.as_ref().and_then(Dimensions::aspect_ratio),
+                gps.map(|g| g.latitude),
+                gps.map(|g| g.longitude),
+                place.as_ref().map(|p| &p.country_code),
+                place.as_ref().map(|p| &p.country),
+                place.as_ref().map(|p| &p.region),
+                place.as_ref().map(|p| &p.city),
+                (!file.labels.is_empty())
+                    .then(|| serde_json::to_string(&file.labels))
+                    .transpose()?,
+                file.description,
+                file.caption,
+                file.rating.map(|_| "embedded"),
             ],
         )?;
+        // An upsert keeps the stored id
+        let id: String = tx.query_row(
+            "SELECT id FROM files WHERE path = ?1",
+            params![file.path],
+            |row| row.get(0),
+        )?;
+        Self::replace_embedded_tags(&tx, &id, &file.tags)?;
+        tx.commit()?;
         Ok(())
     }
 
+    /// Map a row selected with [`FILE_COLUMNS`].
+    pub fn file_from_row(row: &Row) -> rusqlite::Result<FileMeta> {
+        let media_type = MediaType::from_db(&row.get::<_, String>(7)?);
+        let duration_ms = row.get::<_, Option<i64>>(8)?.map(|d| d as u64);
+        let video = match media_type {
+            MediaType::Video => Some(VideoMetadata {
+                duration_ms,
+                codec: row.get(9)?,
+            }),
+            MediaType::Image => None,
+        };
+        let is_animated: bool = row.get(10)?;
+        let animation = if is_animated {
+            Some(AnimationMetadata {
+                frame_count: row.get::<_, Option<u32>>(11)?.unwrap_or_default(),
+                duration_ms: duration_ms.unwrap_or_default(),
+            })
+        } else {
+            None
+        };
+        let dimensions = match (
+            row.get::<_, Option<u32>>(13)?,
+            row.get::<_, Option<u32>>(14)?,
+        ) {
+            (Some(width), Some(height)) => Some(Dimensions { width, height }),
+            _ => None,
+        };
+        let date_taken: Option<String> = row.get(12)?;
+        let gps_coordinates = match (row.get(17)?, row.get(18)?) {
+            (Some(latitude), Some(longitude)) => Some(GpsCoordinates {
+                latitude,
+                longitude,
+            }),
+            _ => None,
+        };
+        let metadata = (date_taken.is_some() || gps_coordinates.is_some()).then(|| ImageMetadata {
+            date_taken,
+            gps_coordinates,
+            ..Default::default()
+        });
+        let place = match row.get::<_, Option<String>>(19)? {
+            Some(country_code) => Some(Place {
+                country_code,
+                country: row.get(20)?,
+                region: row.get(21)?,
+                city: row.get(22)?,
+            }),
+            None => None,
+        };
+        Ok(FileMeta {
+            id: row.get(0)?,
+            path: row.get(1)?,
+            name: row.get(2)?,
+            size: row.get(3)?,
+            modified: rfc3339_from_ms(row.get(4)?),
+            created: row
+                .get::<_, Option<i64>>(16)?
+                .map(rfc3339_from_ms)
+                .unwrap_or_default(),
+            file_type: row.get(5)?,
+            thumbnail_path: row.get(6)?,
+            media_type,
+            dimensions,
+            rating: row.get(15)?,
+            metadata,
+            video,
+            is_animated,
+            animation,
+            place,
+            labels: match row.get::<_, Option<String>>(23)? {
+                Some(json) => serde_json::from_str(&json).unwrap_or_default(),
+                None => Vec::new(),
+            },
+            description: row.get(24)?,
+            caption: row.get(25)?,
+            ..Default::default()
+        })
+    }
+
     pub fn get_file(&self, id: &str) -> Result<Option<FileMeta>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id, path, name, size, modified, file_type, thumbnail_path FROM files WHERE id = ?1")?;
+        let conn = self.reader()?;
+        let mut stmt =
+            conn.prepare(&format!("SELECT {} FROM files WHERE id = ?1", FILE_COLUMNS))?;
 
-        let file = stmt
-            .query_row(params![id], |row| {
-                Ok(FileMeta {
-                    id: row.get(0)?,
-                    path: row.get(1)?,
-                    name: row.get(2)?,
-                    size: row.get(3)?,
-                    modified: row.get(4)?,
-                    file_type: row.get(5)?,
-                    thumbnail_path: row.get(6)?,
-                    ..Default::default()
-                })
-            })
+        let mut file = stmt
+            .query_row(params![id], Self::file_from_row)
             .optional()?;
+        if let Some(file) = &mut file {
+            Self::attach_tags(&conn, std::slice::from_mut(file))?;
+        }
 
         Ok(file)
     }
 
-    pub fn get_files(&self, offset: usize, limit: usize) -> Result<Vec<FileMeta>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, path, name, size, modified, file_type, thumbnail_path
+    /// One page of the library, or of one folder, in `sort` order. Pass the
+    /// cursor of the previous page to continue after it; `None` starts from
+    /// the top.
+    pub fn get_files(
+        &self,
+        folder_path: Option<&str>,
+        filter: &FileFilter,
+        sort: FileSort,
+        after: Option<&FileCursor>,
+        limit: usize,
+    ) -> Result<FilePage> {
+        let conn = self.reader()?;
+        // One read transaction, so the page and the total see the same
+        // snapshot while the indexer writes
+        let tx = conn.unchecked_transaction()?;
+        let (files, next_cursor) = Self::query_page(&tx, folder_path, filter, &sort, after, limit)?;
+        let total = Self::count_with(&tx, folder_path, filter)?;
+        Ok(FilePage {
+            files,
+            next_cursor,
+            total,
+        })
+    }
+
+    /// Number of listed items (variants excluded) matching `filter`, in one
+    /// folder or overall.
+    pub fn count_files(&self, folder_path: Option<&str>, filter: &FileFilter) -> Result<usize> {
+        let conn = self.reader()?;
+        Self::count_with(&conn, folder_path, filter)
+    }
+
+    fn count_with(
+        conn: &Connection,
+        folder_path: Option<&str>,
+        filter: &FileFilter,
+    ) -> Result<usize> {
+        let mut sql = String::from("SELECT COUNT(*) FROM files WHERE variant_of IS NULL");
+        let mut args: Vec<&dyn ToSql> = Vec::new();
+        if let Some(folder) = &folder_path {
+            sql.push_str(" AND folder_path = ?");
+            args.push(folder);
+        }
+        let smart = Self::smart_query(conn, filter)?;
+        push_filter(&mut sql, &mut args, filter, smart.as_ref())?;
+        let count: i64 = conn.query_row(&sql, params_from_iter(args), |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    /// Geotagged files matching `filter`, grouped into grid cells sized so
+    /// markers at web-map `zoom` don't overlap.
+    pub fn get_map_clusters(
+        &self,
+        folder_path: Option<&str>,
+        filter: &FileFilter,
+        zoom: u8,
+    ) -> Result<Vec<MapCluster>> {
+        let conn = self.reader()?;
+        let cell = CLUSTER_PX / TILE_PX * 360.0 / 2f64.powi(zoom.min(MAX_ZOOM) as i32);
+        let mut sql = String::from(
+            "SELECT AVG(latitude), AVG(longitude), COUNT(*),
+                    MIN(latitude), MIN(longitude), MAX(latitude), MAX(longitude), MIN(id)
              FROM files
-             ORDER BY modified DESC
-             LIMIT ?1 OFFSET ?2",
+             WHERE variant_of IS NULL AND latitude IS NOT NULL AND longitude IS NOT NULL",
+        );
+        let mut args: Vec<&dyn ToSql> = Vec::new();
+        if let Some(folder) = &folder_path {
+            sql.push_str(" AND folder_path = ?");
+            args.push(folder);
+        }
+        let smart = Self::smart_query(&conn, filter)?;
+        push_filter(&mut sql, &mut args, filter, smart.as_ref())?;
+        // Offsets keep cell numbers positive, so CAST's truncation acts as floor
+        sql.push_str(&format!(
+            " GROUP BY CAST((latitude + 90.0) / {0} AS INTEGER),
+                       CAST((longitude + 180.0) / {0} AS INTEGER)",
+            cell
+        ));
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(args), |row| {
+            Ok(MapCluster {
+                latitude: row.get(0)?,
+                longitude: row.get(1)?,
+                count: row.get::<_, i64>(2)? as usize,
+                bounds: GeoBounds {
+                    south: row.get(3)?,
+                    west: row.get(4)?,
+                    north: row.get(5)?,
+                    east: row.get(6)?,
+                },
+                sample_id: row.get(7)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Places with geotagged files, most files first.
+    pub fn get_places(&self, folder_path: Option<&str>) -> Result<Vec<PlaceCount>> {
+        let conn = self.reader()?;
+        let mut stmt = conn.prepare(
+            "SELECT country_code, country, region, city, COUNT(*) FROM files
+             WHERE (?1 IS NULL OR folder_path = ?1)
+               AND variant_of IS NULL AND country_code IS NOT NULL
+             GROUP BY country_code, country, region, city
+             ORDER BY COUNT(*) DESC, country, region, city",
         )?;
+        let rows = stmt.query_map(params![folder_path], |row| {
+            Ok(PlaceCount {
+                place: Place {
+                    country_code: row.get(0)?,
+                    country: row.get(1)?,
+                    region: row.get(2)?,
+                    city: row.get(3)?,
+                },
+                count: row.get::<_, i64>(4)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
 
-        let rows = stmt.query_map(params![limit, offset], |row| {
-            Ok(FileMeta {
-                id: row.get(0)?,
-                path: row.get(1)?,
-                name: row.get(2)?,
-                size: row.get(3)?,
-                modified: row.get(4)?,
-                file_type: row.get(5)?,
-                thumbnail_path: row.get(6)?,
-                ..Default::default()
+    /// File counts per local calendar year, month or day of capture, newest
+    /// first. Periods without files are left out.
+    pub fn get_date_histogram(
+        &self,
+        folder_path: Option<&str>,
+        filter: &FileFilter,
+        granularity: DateGranularity,
+    ) -> Result<Vec<DateBucket>> {
+        let conn = self.reader()?;
+        let format = match granularity {
+            DateGranularity::Year => "%Y",
+            DateGranularity::Month => "%Y-%m",
+            DateGranularity::Day => "%Y-%m-%d",
+        };
+        let mut sql = format!(
+            "SELECT strftime('{}', {} / 1000, 'unixepoch', 'localtime') AS period, COUNT(*)
+             FROM files WHERE variant_of IS NULL",
+            format, CAPTURE_TIME
+        );
+        let mut args: Vec<&dyn ToSql> = Vec::new();
+        if let Some(folder) = &folder_path {
+            sql.push_str(" AND folder_path = ?");
+            args.push(folder);
+        }
+        let smart = Self::smart_query(&conn, filter)?;
+        push_filter(&mut sql, &mut args, filter, smart.as_ref())?;
+        sql.push_str(" GROUP BY period ORDER BY period DESC");
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(args), |row| {
+            Ok(DateBucket {
+                period: row.get(0)?,
+                count: row.get::<_, i64>(1)? as usize,
             })
         })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Position of `period` (as in [`DateBucket::period`]) in a
+    /// [`SortKey::DateTaken`] listing: newest file of the period first when
+    /// descending, oldest when ascending. A period without files lands where
+    /// it would have been.
+    pub fn jump_to_date(
+        &self,
+        folder_path: Option<&str>,
+        filter: &FileFilter,
+        direction: SortDirection,
+        period: &str,
+    ) -> Result<DateJump> {
+        let (start, end) =
+            period_range(period).ok_or_else(|| anyhow!("Invalid date: {}", period))?;
+        // Rows before the period in listing order, and the order to find the
+        // last of them
+        let (before, bound, last) = match direction {
+            SortDirection::Desc => (">=", end, "ASC"),
+            SortDirection::Asc => ("<", start, "DESC"),
+        };
+        let conn = self.reader()?;
+        let mut sql = String::from("FROM files WHERE variant_of IS NULL");
+        let mut args: Vec<&dyn ToSql> = Vec::new();
+        if let Some(folder) = &folder_path {
+            sql.push_str(" AND folder_path = ?");
+            args.push(folder);
+        }
+        let smart = Self::smart_query(&conn, filter)?;
+        push_filter(&mut sql, &mut args, filter, smart.as_ref())?;
+        sql.push_str(&format!(" AND {} {} ?", CAPTURE_TIME, before));
+        args.push(&bound);
+
+        let offset: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) {}", sql),
+            params_from_iter(args.iter()),
+            |row| row.get(0),
+        )?;
+        let cursor = conn
+            .query_row(
+                &format!(
+                    "SELECT {0}, id {1} ORDER BY {0} {2}, id {2} LIMIT 1",
+                    CAPTURE_TIME, sql, last
+                ),
+                params_from_iter(args.iter()),
+                |row| {
+                    Ok(FileCursor {
+                        key: SortValue::Int(row.get(0)?),
+                        id: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(DateJump {
+            offset: offset as usize,
+            cursor,
+        })
+    }
+
+    /// Keyset pagination on (sort value, id): unlike OFFSET it doesn't rescan
+    /// the skipped rows, and rows inserted above the cursor don't shift the
+    /// next page.
+    fn query_page(
+        conn: &Connection,
+        folder_path: Option<&str>,
+        filter: &FileFilter,
+        sort: &FileSort,
+        after: Option<&FileCursor>,
+        limit: usize,
+    ) -> Result<(Vec<FileMeta>, Option<FileCursor>)> {
+        let expr = sort_expr(sort);
+        let (op, dir) = match sort.direction {
+            SortDirection::Asc => (">", "ASC"),
+            SortDirection::Desc => ("<", "DESC"),
+        };
+        let mut sql = format!(
+            "SELECT {}, {} FROM files WHERE variant_of IS NULL",
+            FILE_COLUMNS, expr
+        );
+        let mut args: Vec<&dyn ToSql> = Vec::new();
+        if let Some(folder) = &folder_path {
+            sql.push_str(" AND folder_path = ?");
+            args.push(folder);
+        }
+        let smart = Self::smart_query(conn, filter)?;
+        push_filter(&mut sql, &mut args, filter, smart.as_ref())?;
+        if let Some(cursor) = after {
+            sql.push_str(&format!(" AND ({}, id) {} (?, ?)", expr, op));
+            args.push(&cursor.key);
+            args.push(&cursor.id);
+        }
+        sql.push_str(&format!(" ORDER BY {0} {1}, id {1} LIMIT ?", expr, dir));
+        args.push(&limit);
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(args), |row| {
+            Ok((
+                Self::file_from_row(row)?,
+                row.get::<_, SortValue>(FILE_COLUMN_COUNT)?,
+            ))
+        })?;
 
         let mut files = Vec::new();
-        for file in rows {
-            files.push(file?);
+        let mut last_key = None;
+        for row in rows {
+            let (file, key) = row?;
+            files.push(file);
+            last_key = Some(key);
         }
-        Ok(files)
+        // A short page is the last one
+        let next_cursor = match (files.last(), last_key) {
+            (Some(last), Some(key)) if files.len() == limit => Some(FileCursor {
+                key,
+                id: last.id.clone(),
+            }),
+            _ => None,
+        };
+        Self::attach_variants(conn, &mut files)?;
+        Self::attach_tags(conn, &mut files)?;
+        Ok((files, next_cursor))
     }
 
-    pub fn get_files_for_folder(
+    /// Files taken on `today`'s month and day in earlier years, newest first.
+    /// Files without a capture date are left out: their modification date is
+    /// usually when they were copied, not when they were taken.
+    pub fn get_on_this_day(
         &self,
-        folder_path: &str,
-        offset: usize,
-        limit: usize,
+        folder_path: Option<&str>,
+        filter: &FileFilter,
+        today: chrono::NaiveDate,
     ) -> Result<Vec<FileMeta>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader()?;
+        let day = today.format("%m-%d").to_string();
+        let year = today.format("%Y").to_string();
+        let mut sql = format!(
+            "SELECT {} FROM files
+             WHERE variant_of IS NULL AND taken_at IS NOT NULL
+               AND strftime('%m-%d', taken_at / 1000, 'unixepoch', 'localtime') = ?
+               AND strftime('%Y', taken_at / 1000, 'unixepoch', 'localtime') < ?",
+            FILE_COLUMNS
+        );
+        let mut args: Vec<&dyn ToSql> = vec![&day, &year];
+        if let Some(folder) = &folder_path {
+            sql.push_str(" AND folder_path = ?");
+            args.push(folder);
+        }
+        let smart = Self::smart_query(&conn, filter)?;
+        push_filter(&mut sql, &mut args, filter, smart.as_ref())?;
+        sql.push_str(" ORDER BY taken_at DESC, id DESC");
+
+        let mut stmt = conn.prepare(&sql)?;
+        let mut files = stmt
+            .query_map(params_from_iter(args), Self::file_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Self::attach_variants(&conn, &mut files)?;
+        Self::attach_tags(&conn, &mut files)?;
+        Ok(files)
+    }
+
+    /// Albums of one kind or all, newest first; events by when they
+    /// happened. Smart albums are counted by running their query.
+    pub fn get_albums(&self, kind: Option<AlbumKind>) -> Result<Vec<Album>> {
+        let conn = self.reader()?;
         let mut stmt = conn.prepare(
-            "SELECT id, path, name, size, modified, file_type, thumbnail_path
-             FROM files
-             WHERE folder_path = ?1
-             ORDER BY modified DESC
-             LIMIT ?2 OFFSET ?3",
+            "SELECT id, name, description, cover_image_id, kind, created_at, starts_at, ends_at,
+                    (SELECT COUNT(*) FROM album_files WHERE album_id = albums.id), query
+             FROM albums WHERE ?1 IS NULL OR kind = ?1
+             ORDER BY COALESCE(starts_at, created_at) DESC, name",
         )?;
-
-        let rows = stmt.query_map(params![folder_path, limit, offset], |row| {
-            Ok(FileMeta {
+        let rows = stmt.query_map(params![kind.map(AlbumKind::as_str)], |row| {
+            let created = optional_rfc3339(row.get(5)?);
+            Ok(Album {
                 id: row.get(0)?,
-                path: row.get(1)?,
-                name: row.get(2)?,
-                size: row.get(3)?,
-                modified: row.get(4)?,
-                file_type: row.get(5)?,
-                thumbnail_path: row.get(6)?,
-                ..Default::default()
+                name: row.get(1)?,
+                description: row.get(2)?,
+                cover_image: row.get(3)?,
+                kind: AlbumKind::from_db(&row.get::<_, String>(4)?),
+                modified: created.clone(),
+                created,
+                starts: row.get::<_, Option<i64>>(6)?.map(rfc3339_from_ms),
+                ends: row.get::<_, Option<i64>>(7)?.map(rfc3339_from_ms),
+                file_count: row.get::<_, i64>(8)? as usize,
+                query: None,
             })
+            .map(|album| (album, row.get::<_, Option<String>>(9)))
         })?;
 
-        let mut files = Vec::new();
-        for file in rows {
-            files.push(file?);
+        let mut albums = Vec::new();
+        for row in rows {
+            let (mut album, query) = row?;
+            if let Some(json) = query? {
+                let query: SearchQuery = serde_json::from_str(&json)?;
+                let filter = FileFilter {
+                    search: Some(query.clone()),
+                    ..Default::default()
+                };
+                album.file_count = Self::count_with(&conn, None, &filter)?;
+                album.query = Some(query);
+            }
+            albums.push(album);
         }
-        Ok(files)
+        Ok(albums)
     }
 
-    pub fn remove_file(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM files WHERE id = ?1", params![id])?;
+    /// The saved query of `filter`'s album, if that is a smart album.
+    fn smart_query(conn: &Connection, filter: &FileFilter) -> Result<Option<SearchQuery>> {
+        let Some(album) = &filter.album_id else {
+            return Ok(None);
+        };
+        let json: Option<String> = conn
+            .query_row(
+                "SELECT query FROM albums WHERE id = ?1 AND kind = ?2",
+                params![album, AlbumKind::Smart.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    /// Save `query` as a smart album. The query is checked by counting its
+    /// matches.
+    pub fn create_smart_album(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        query: SearchQuery,
+    ) -> Result<Album> {
+        let filter = FileFilter {
+            search: Some(query.clone()),
+            ..Default::default()
+        };
+        let file_count = self.count_files(None, &filter)?;
+        let mut album = Album::new(name.to_string(), description.map(str::to_string));
+        album.kind = AlbumKind::Smart;
+        album.file_count = file_count;
+        let now = chrono::Utc::now().timestamp_millis();
+        album.created = rfc3339_from_ms(now);
+        album.modified = album.created.clone();
+
+        self.writer()?.execute(
+            "INSERT INTO albums (id, name, description, kind, created_at, query)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                album.id,
+                album.name,
+                album.description,
+                album.kind.as_str(),
+                now,
+                serde_json::to_string(&query)?
+            ],
+        )?;
+        album.query = Some(query);
+        Ok(album)
+    }
+
+    /// Replace a smart album's name, description and query.
+    pub fn update_smart_album(
+        &self,
+        id: &str,
+        name: &str,
+        description: Option<&str>,
+        query: &SearchQuery,
+    ) -> Result<()> {
+        let filter = FileFilter {
+            search: Some(query.clone()),
+            ..Default::default()
+        };
+        self.count_files(None, &filter)?;
+        let updated = self.writer()?.execute(
+            "UPDATE albums SET name = ?2, description = ?3, query = ?4
+             WHERE id = ?1 AND kind = ?5",
+            params![
+                id,
+                name,
+                description,
+                serde_json::to_string(query)?,
+                AlbumKind::Smart.as_str()
+            ],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("No smart album {}", id));
+        }
         Ok(())
     }
 
-    pub fn remove_file_by_path(&self, path: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+    /// Delete an album of any kind; its files stay in the library.
+    pub fn delete_album(&self, id: &str) -> Result<()> {
+        self.writer()?
+            .execute("DELETE FROM albums WHERE id = ?1", params![id])?;
         Ok(())
     }
 
-    pub fn get_all_file_paths_in_folder(&self, folder_path: &str) -> Result<Vec<(String, String)>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id, path FROM files WHERE folder_path = ?1")?;
-        let rows = stmt.query_map(params![folder_path], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    /// Group files with a capture date into events and store them as
+    /// [`AlbumKind::Event`] albums, replacing the previous run's. A new event
+    /// starts after a pause longer than the gap or, if a distance is set,
+    /// when the photographer moved further than it between geotagged photos.
+    /// Returns the new albums, newest first.
+    pub fn cluster_events(&self, options: &EventOptions) -> Result<Vec<Album>> {
+        struct Photo {
+            id: String,
+            taken_at: i64,
+            position: Option<(f64, f64)>,
+            city: Option<String>,
+        }
 
-        let mut results = Vec::new();
-        for r in rows {
-            results.push(r?);
+        let conn = self.writer()?;
+        let tx = conn.unchecked_transaction()?;
+        let photos = {
+            let mut stmt = tx.prepare(
+                "SELECT id, taken_at, latitude, longitude, city FROM files
+                 WHERE variant_of IS NULL AND taken_at IS NOT NULL
+                 ORDER BY taken_at, id",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let latitude: Option<f64> = row.get(2)?;
+                let longitude: Option<f64> = row.get(3)?;
+                Ok(Photo {
+                    id: row.get(0)?,
+                    taken_at: row.get(1)?,
+                    position: latitude.zip(longitude),
+                    city: row.get(4)?,
+                })
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        let max_gap_ms = (options.max_gap_hours * 3_600_000.0) as i64;
+        let mut events: Vec<&[Photo]> = Vec::new();
+        let mut start = 0;
+        // Last known position in the current event
+        let mut position = None;
+        for (i, photo) in photos.iter().enumerate() {
+            let split = i > 0 && {
+                let paused = photo.taken_at - photos[i - 1].taken_at > max_gap_ms;
+                let moved = match (options.max_distance_km, position, photo.position) {
+                    (Some(max), Some((lat1, lon1)), Some((lat2, lon2))) => {
+                        geocode::distance_km(lat1, lon1, lat2, lon2) > max
+                    }
+                    _ => false,
+                };
+                paused || moved
+            };
+            if split {
+                events.push(&photos[start..i]);
+                start = i;
+                position = None;
+            }
+            position = photo.position.or(position);
+        }
+        if start < photos.len() {
+            events.push(&photos[start..]);
         }
-        Ok(results)
-    }
 
-    // --- Folder/Snapshot Operations ---
+        // Memberships go with the albums through the foreign key
+        tx.execute(
+            "DELETE FROM albums WHERE kind = ?1",
+            [AlbumKind::Event.as_str()],
+        )?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut albums = Vec::new();
+        for event in events.into_iter().filter(|e| e.len() >= options.min_files) {
+            let (first, last) = (&event[0], &event[event.len() - 1]);
+            let city = event.iter().find_map(|p| p.city.as_deref());
+            let mut album = Album::new(event_name(city, first.taken_at, last.taken_at), None);
+            album.kind = AlbumKind::Event;
+            album.cover_image = Some(first.id.clone());
+            album.created = rfc3339_from_ms(now);
+            album.modified = album.created.clone();
+            album.starts = Some(rfc3339_from_ms(first.taken_at));
+            album.ends = Some(rfc3339_from_ms(last.taken_at));
+            album.file_count = event.len();
 
-    pub fn get_snapshot(&self, folder_path: &str) -> Result<Option<(usize, i64)>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt =
-            conn.prepare("SELECT file_count, agg_mtime FROM folder_snapshots WHERE path = ?1")?;
-        stmt.query_row(params![folder_path], |row| Ok((row.get(0)?, row.get(1)?)))
-            .optional()
-            .map_err(Into::into)
+            tx.execute(
+                "INSERT INTO albums (id, name, cover_image_id, kind, created_at, starts_at, ends_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    album.id,
+                    album.name,
+                    album.cover_image,
+                    album.kind.as_str(),
+                    now,
+                    first.taken_at,
+                    last.taken_at
+                ],
+            )?;
+            let mut member =
+                tx.prepare_cached("INSERT INTO album_files (album_id, file_id) VALUES (?1, ?2)")?;
+            for photo in event {
+                member.execute(params![album.id, photo.id])?;
+            }
+            albums.push(album);
+        }
+        tx.commit()?;
+        albums.reverse();
+        Ok(albums)
     }
 
-    pub fn save_snapshot(&self, folder_path: &str, count: usize, agg_mtime: i64) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    pub fn remove_file(&self, id: &str) -> Result<()> {
+        let conn = self.writer()?;
+        // Variants of a removed primary become standalone items again
         conn.execute(
-            "INSERT INTO folder_snapshots (path, file_count, agg_mtime) VALUES (?1, ?2, ?3)
-             ON CONFLICT(path) DO UPDATE SET file_count=excluded.file_count, agg_mtime=excluded.agg_mtime",
-            params![folder_path, count, agg_mtime]
+            "UPDATE files SET variant_of = NULL WHERE variant_of = ?1",
+            params![id],
         )?;
+        conn.execute("DELETE FROM files WHERE id = ?1", params![id])?;
         Ok(())
     }
 
-    pub fn clear_folder(&self, folder_path: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    pub fn remove_file_by_path(&self, path: &str) -> Result<()> {
+        let conn = self.writer()?;
         conn.execute(
-            "DELETE FROM files WHERE folder_path = ?1",
-            params![folder_path],
+            "UPDATE files SET variant_of = NULL
+             WHERE variant_of = (SELECT id FROM files WHERE path = ?1)",
+            params![path],
+        )?;
+        conn.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+        Ok(())
+    }
+
+    /// Link RAW files to a non-RAW file with the same stem in the same folder
+    /// (IMG_0001.CR2 + IMG_0001.JPG), so the pair is listed as one item with
+    /// the RAW as a variant. Re-run after a folder's contents change.
+    pub fn link_raw_variants(&self, folder_path: &str) -> Result<()> {
+        let conn = self.writer()?;
+        let mut stmt =
+            conn.prepare("SELECT id, name, file_type FROM files WHERE folder_path = ?1")?;
+        let rows = stmt.query_map(params![folder_path], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        // stem -> (primary id, raw ids); names are sorted so the primary pick is stable
+        let mut entries = Vec::new();
+        for r in rows {
+            entries.push(r?);
+        }
+        entries.sort_by(|a, b| a.1.cmp(&b.1));
+        let mut groups: HashMap<String, (Option<String>, Vec<String>)> = HashMap::new();
+        for (id, name, file_type) in entries {
+            let stem = Path::new(&name)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let group = groups.entry(stem).or_default();
+            let is_raw = file_type.as_deref().and_then(Format::from_extension) == Some(Format::Raw);
+            if is_raw {
+                group.1.push(id);
+            } else if group.0.is_none() {
+                group.0 = Some(id);
+            }
+        }
+        drop(stmt);
+
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE files SET variant_of = NULL WHERE folder_path = ?1 AND variant_of IS NOT NULL",
+            params![folder_path],
+        )?;
+        for (primary, raws) in groups.values() {
+            if let Some(primary) = primary {
+                for raw_id in raws {
+                    tx.execute(
+                        "UPDATE files SET variant_of = ?1 WHERE id = ?2",
+                        params![primary, raw_id],
+                    )?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Fill `variants` for listed files with one query.
+    pub fn attach_variants(conn: &Connection, files: &mut [FileMeta]) -> Result<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        let placeholders = vec!["?"; files.len()].join(",");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, path, file_type, variant_of FROM files WHERE variant_of IN ({})",
+            placeholders
+        ))?;
+        let rows = stmt.query_map(params_from_iter(files.iter().map(|f| &f.id)), |row| {
+            Ok((
+                FileVariant {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    file_type: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                },
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let index: HashMap<String, usize> = files
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.id.clone(), i))
+            .collect();
+        for r in rows {
+            let (variant, primary) = r?;
+            if let Some(&i) = index.get(&primary) {
+                files[i].variants.push(variant);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill `tags` for listed files with one query.
+    fn attach_tags(conn: &Connection, files: &mut [FileMeta]) -> Result<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        let placeholders = vec!["?"; files.len()].join(",");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT file_tags.file_id, tags.path FROM file_tags
+             JOIN tags ON tags.id = file_tags.tag_id
+             WHERE file_tags.file_id IN ({})
+             ORDER BY tags.path",
+            placeholders
+        ))?;
+        let rows = stmt.query_map(params_from_iter(files.iter().map(|f| &f.id)), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let index: HashMap<String, usize> = files
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.id.clone(), i))
+            .collect();
+        for r in rows {
+            let (file_id, path) = r?;
+            if let Some(&i) = index.get(&file_id) {
+                files[i].tags.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Id of the tag at a normalized `path`, creating it and any missing
+    /// ancestors.
+    fn ensure_tag(conn: &Connection, path: &str) -> Result<String> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut parent: Option<String> = None;
+        let mut current = String::new();
+        for level in path.split(keywords::LEVEL_SEPARATOR) {
+            if !current.is_empty() {
+                current.push(keywords::LEVEL_SEPARATOR);
+            }
+            current.push_str(level);
+            conn.execute(
+                "INSERT INTO tags (id, name, parent_id, path, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(path) DO NOTHING",
+                params![
+                    uuid::Uuid::new_v4().to_string(),
+                    level,
+                    parent,
+                    current,
+                    now
+                ],
+            )?;
+            parent = Some(conn.query_row(
+                "SELECT id FROM tags WHERE path = ?1",
+                params![current],
+                |row| row.get(0),
+            )?);
+        }
+        parent.ok_or_else(|| anyhow!("Empty tag path"))
+    }
+
+    /// Swap a file's imported keywords for `paths`. Tags added in the app
+    /// stay, also when a keyword names the same tag.
+    fn replace_embedded_tags(conn: &Connection, file_id: &str, paths: &[String]) -> Result<()> {
+        conn.execute(
+            "DELETE FROM file_tags WHERE file_id = ?1 AND source = 'embedded'",
+            params![file_id],
+        )?;
+        for path in paths.iter().filter_map(|p| keywords::normalize(p)) {
+            let tag_id = Self::ensure_tag(conn, &path)?;
+            conn.execute(
+                "INSERT INTO file_tags (file_id, tag_id, source) VALUES (?1, ?2, 'embedded')
+                 ON CONFLICT DO NOTHING",
+                params![file_id, tag_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Set or clear the rating of files. Either way the rating becomes the
+    /// user's, so re-indexing no longer takes it from the file.
+    pub fn set_rating(&self, file_ids: &[String], rating: Option<i32>) -> Result<()> {
+        let conn = self.writer()?;
+        let tx = conn.unchecked_transaction()?;
+        for id in file_ids {
+            tx.execute(
+                "UPDATE files SET rating = ?2, rating_source = 'user' WHERE id = ?1",
+                params![id, rating],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Whether the file's rating was cleared in the gallery, as opposed to
+    /// never set.
+    pub fn rating_cleared(&self, file_id: &str) -> Result<bool> {
+        let conn = self.reader()?;
+        let cleared = conn
+            .query_row(
+                "SELECT rating IS NULL AND rating_source IS 'user' FROM files WHERE id = ?1",
+                params![file_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(cleared.unwrap_or(false))
+    }
+
+    /// Store the caption of the file at `path` after its sidecar was
+    /// rewritten. Returns whether the file is indexed.
+    pub fn set_caption(&self, path: &str, caption: Option<&str>) -> Result<bool> {
+        let conn = self.writer()?;
+        let changed = conn.execute(
+            "UPDATE files SET caption = ?2 WHERE path = ?1",
+            params![path, caption],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Every tag in path order, so parents come before their children.
+    pub fn get_tags(&self) -> Result<Vec<Tag>> {
+        let conn = self.reader()?;
+        let mut stmt = conn.prepare(
+            "SELECT t.id, t.name, t.color, t.created_at, t.parent_id, t.path,
+                    (SELECT COUNT(DISTINCT ft.file_id)
+                     FROM file_tags ft
+                     JOIN tags d ON d.id = ft.tag_id
+                     JOIN files f ON f.id = ft.file_id
+                     WHERE f.variant_of IS NULL
+                       AND (d.path = t.path
+                            OR (d.path > t.path || '|' AND d.path < t.path || '}')))
+             FROM tags t ORDER BY t.path",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Tag {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                color: row.get(2)?,
+                created: optional_rfc3339(row.get(3)?),
+                parent_id: row.get(4)?,
+                path: row.get(5)?,
+                file_count: row.get::<_, i64>(6)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Tag files with `path` (levels separated by `|`), creating the tag and
+    /// its ancestors as needed.
+    pub fn tag_files(&self, file_ids: &[String], path: &str) -> Result<()> {
+        let path = keywords::normalize(path).ok_or_else(|| anyhow!("Empty tag path"))?;
+        let conn = self.writer()?;
+        let tx = conn.unchecked_transaction()?;
+        let tag_id = Self::ensure_tag(&tx, &path)?;
+        for file_id in file_ids {
+            // A keyword the file already carries becomes the user's own
+            tx.execute(
+                "INSERT INTO file_tags (file_id, tag_id, source) VALUES (?1, ?2, 'user')
+                 ON CONFLICT DO UPDATE SET source = 'user'",
+                params![file_id, tag_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Remove the tag at `path` from files; tags below it stay.
+    pub fn untag_files(&self, file_ids: &[String], path: &str) -> Result<()> {
+        let conn = self.writer()?;
+        let tx = conn.unchecked_transaction()?;
+        for file_id in file_ids {
+            tx.execute(
+                "DELETE FROM file_tags
+                 WHERE file_id = ?1 AND tag_id = (SELECT id FROM tags WHERE path = ?2)",
+                params![file_id, path],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Delete a tag and everything below it. Returns the files that carried
+    /// any of them.
+    pub fn delete_tag(&self, id: &str) -> Result<Vec<String>> {
+        let conn = self.writer()?;
+        let tx = conn.unchecked_transaction()?;
+        let path: Option<String> = tx
+            .query_row("SELECT path FROM tags WHERE id = ?1", params![id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let Some(path) = path else {
+            return Ok(vec![]);
+        };
+        let file_ids = tx
+            .prepare(&format!(
+                "SELECT DISTINCT file_id FROM file_tags WHERE tag_id IN ({})",
+                TAG_SUBTREE
+            ))?
+            .query_map(params![path, path, path], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        tx.execute("DELETE FROM tags WHERE id = ?1", params![id])?;
+        tx.commit()?;
+        Ok(file_ids)
+    }
+
+    /// Moves an existing row to a new path, keeping its id so album membership
+    /// and tags survive. A row already at `new_path` (rename over an existing
+    /// file) is replaced. Returns false when no row exists for `old_path`.
+    pub fn rename_file(&self, old_path: &str, new_path: &str, folder_path: &str) -> Result<bool> {
+        let conn = self.writer()?;
+        let new = Path::new(new_path);
+        let name = new
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+        let file_type = new
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        let tx = conn.unchecked_transaction()?;
+        let exists: bool = tx
+            .query_row(
+                "SELECT 1 FROM files WHERE path = ?1",
+                params![old_path],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(false);
+        }
+        tx.execute(
+            "DELETE FROM files WHERE path = ?1 AND path != ?2",
+            params![new_path, old_path],
+        )?;
+        tx.execute(
+            "UPDATE files SET path = ?1, name = ?2, file_type = ?3, folder_path = ?4, name_key = ?5
+             WHERE path = ?6",
+            params![
+                new_path,
+                name,
+                file_type,
+                folder_path,
+                natural_sort_key(&name),
+                old_path
+            ],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_all_file_paths_in_folder(&self, folder_path: &str) -> Result<Vec<(String, String)>> {
+        let conn = self.reader()?;
+        let mut stmt = conn.prepare("SELECT id, path FROM files WHERE folder_path = ?1")?;
+        let rows = stmt.query_map(params![folder_path], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut results = Vec::new();
+        for r in rows {
+            results.push(r?);
+        }
+        Ok(results)
+    }
+
+    /// Paths of the indexed files directly inside `dir`, whichever folder
+    /// they were indexed under.
+    pub fn get_file_paths_in_dir(&self, dir: &str) -> Result<Vec<String>> {
+        let conn = self.reader()?;
+        let prefix = format!(
+            "{}{}",
+            dir.trim_end_matches(['/', '\\']),
+            std::path::MAIN_SEPARATOR
+        );
+        let mut stmt =
+            conn.prepare("SELECT path FROM files WHERE substr(path, 1, length(?1)) = ?1")?;
+        let rows = stmt.query_map(params![prefix], |row| row.get::<_, String>(0))?;
+
+        let mut results = Vec::new();
+        for r in rows {
+            let path = r?;
+            if !path[prefix.len()..].contains(['/', '\\']) {
+                results.push(path);
+            }
+        }
+        Ok(results)
+    }
+
+    // --- Folder/Snapshot Operations ---
+
+    pub fn get_snapshot(&self, folder_path: &str) -> Result<Option<(usize, i64)>> {
+        let conn = self.reader()?;
+        let mut stmt =
+            conn.prepare("SELECT file_count, agg_mtime FROM folder_snapshots WHERE path = ?1")?;
+        stmt.query_row(params![folder_path], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn save_snapshot(&self, folder_path: &str, count: usize, agg_mtime: i64) -> Result<()> {
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT INTO folder_snapshots (path, file_count, agg_mtime) VALUES (?1, ?2, ?3)
+             ON CONFLICT(path) DO UPDATE SET file_count=excluded.file_count, agg_mtime=excluded.agg_mtime",
+            params![folder_path, count, agg_mtime]
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_folder(&self, folder_path: &str) -> Result<()> {
+        let conn = self.writer()?;
+        conn.execute(
+            "DELETE FROM files WHERE folder_path = ?1",
+            params![folder_path],
         )?;
         conn.execute(
             "DELETE FROM folder_snapshots WHERE path = ?1",
@@ -279,7 +1702,7 @@ impl Database {
     }
 
     pub fn get_indexed_folders(&self) -> Result<Vec<String>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader()?;
         let mut stmt = conn.prepare("SELECT DISTINCT folder_path FROM files")?;
         let rows = stmt.query_map([], |row| row.get(0))?;
         let mut folders = Vec::new();
@@ -289,10 +1712,41 @@ impl Database {
         Ok(folders)
     }
 
+    // --- Watched Folders ---
+
+    pub fn add_watched_folder(&self, folder_path: &str) -> Result<()> {
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT OR IGNORE INTO watched_folders (path, added_at) VALUES (?1, ?2)",
+            params![folder_path, chrono::Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_watched_folder(&self, folder_path: &str) -> Result<()> {
+        let conn = self.writer()?;
+        conn.execute(
+            "DELETE FROM watched_folders WHERE path = ?1",
+            params![folder_path],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_watched_folders(&self) -> Result<Vec<String>> {
+        let conn = self.reader()?;
+        let mut stmt = conn.prepare("SELECT path FROM watched_folders ORDER BY added_at")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        let mut folders = Vec::new();
+        for r in rows {
+            folders.push(r?);
+        }
+        Ok(folders)
+    }
+
     // --- KV Store (Settings) ---
 
     pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader()?;
         let mut stmt = conn.prepare("SELECT value FROM kv_store WHERE key = ?1")?;
         stmt.query_row(params![key], |row| row.get(0))
             .optional()
@@ -300,7 +1754,7 @@ impl Database {
     }
 
     pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.writer()?;
         conn.execute(
             "INSERT INTO kv_store (key, value) VALUES (?1, ?2)
              ON CONFLICT(key) DO UPDATE SET value=excluded.value",
@@ -310,12 +1764,1432 @@ impl Database {
     }
 
     pub fn clear_library(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.writer()?;
         conn.execute("DELETE FROM files", [])?;
         conn.execute("DELETE FROM albums", [])?;
         conn.execute("DELETE FROM album_files", [])?;
+        conn.execute("DELETE FROM tags", [])?;
         conn.execute("DELETE FROM folder_snapshots", [])?;
         conn.execute("DELETE FROM kv_store", [])?;
+        conn.execute("DELETE FROM watched_folders", [])?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        AnimationMetadata, DateRange, MediaType, PlaceFilter, SizeRange, VideoMetadata,
+    };
+
+    fn file(id: &str, path: &str, modified: &str) -> FileMeta {
+        let p = Path::new(path);
+        FileMeta {
+            id: id.to_string(),
+            path: path.to_string(),
+            name: p.file_name().unwrap().to_string_lossy().to_string(),
+            size: 100,
+            modified: modified.to_string(),
+            file_type: p.extension().unwrap().to_string_lossy().to_lowercase(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(files: &[FileMeta]) -> Vec<&str> {
+        files.iter().map(|f| f.id.as_str()).collect()
+    }
+
+    /// First page of a listing in the default order.
+    fn list(db: &Database, folder: Option<&str>) -> Vec<FileMeta> {
+        db.get_files(
+            folder,
+            &FileFilter::default(),
+            FileSort::default(),
+            None,
+            100,
+        )
+        .unwrap()
+        .files
+    }
+
+    fn sorted(db: &Database, key: SortKey, direction: SortDirection) -> Vec<String> {
+        let sort = FileSort {
+            key,
+            direction,
+            seed: 7,
+        };
+        let page = db
+            .get_files(None, &FileFilter::default(), sort, None, 100)
+            .unwrap();
+        page.files.into_iter().map(|f| f.id).collect()
+    }
+
+    fn variant_ids(variants: &[FileVariant]) -> Vec<&str> {
+        variants.iter().map(|v| v.id.as_str()).collect()
+    }
+
+    fn db_with(files: &[(&str, &str, &str)]) -> Database {
+        let db = Database::in_memory().unwrap();
+        for (id, path, modified) in files {
+            let folder = Path::new(path)
+                .parent()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            db.add_file(&file(id, path, modified), &folder).unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn open_creates_and_reopens_a_library() {
+        let dir = std::env::temp_dir().join(format!("lumous-db-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("nested").join("library.db");
+        {
+            let db = Database::open(&path).unwrap();
+            db.set_setting("k", "v").unwrap();
+        }
+        assert!(path.exists());
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.get_setting("k").unwrap().as_deref(), Some("v"));
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn readers_are_not_blocked_by_an_open_write() {
+        let dir = std::env::temp_dir().join(format!("lumous-db-{}", uuid::Uuid::new_v4()));
+        let db = Database::open(&dir.join("library.db")).unwrap();
+        db.set_setting("k", "before").unwrap();
+
+        let writer = db.writer().unwrap();
+        writer.execute_batch("BEGIN IMMEDIATE").unwrap();
+        writer
+            .execute("UPDATE kv_store SET value = 'after' WHERE key = 'k'", [])
+            .unwrap();
+
+        // A reader on another thread sees the last committed value
+        let other = db.clone();
+        let seen = std::thread::spawn(move || other.get_setting("k").unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(seen.as_deref(), Some("before"));
+
+        writer.execute_batch("COMMIT").unwrap();
+        drop(writer);
+        assert_eq!(db.get_setting("k").unwrap().as_deref(), Some("after"));
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn in_memory_databases_are_independent() {
+        let a = Database::in_memory().unwrap();
+        let b = Database::in_memory().unwrap();
+        a.set_setting("k", "v").unwrap();
+        assert_eq!(b.get_setting("k").unwrap(), None);
+        let version = migrations::user_version(&a.writer().unwrap()).unwrap();
+        assert_eq!(version as usize, migrations::MIGRATIONS.len());
+    }
+
+    #[test]
+    fn default_path_points_at_library_db() {
+        let path = Database::default_path();
+        assert!(path.ends_with(Path::new("local-gallery").join("library.db")));
+    }
+
+    #[test]
+    fn add_file_round_trips_through_get_file() {
+        let db = Database::in_memory().unwrap();
+        let mut video = file("v1", "/pics/clip.mov", "2024-01-02T00:00:00+00:00");
+        video.media_type = MediaType::Video;
+        video.thumbnail_path = Some("/cache/v1.jpg".into());
+        video.video = Some(VideoMetadata {
+            duration_ms: Some(4200),
+            codec: Some("hevc".into()),
+        });
+        db.add_file(&video, "/pics").unwrap();
+
+        let got = db.get_file("v1").unwrap().unwrap();
+        assert_eq!(got.path, "/pics/clip.mov");
+        assert_eq!(got.name, "clip.mov");
+        assert_eq!(got.file_type, "mov");
+        assert_eq!(got.thumbnail_path.as_deref(), Some("/cache/v1.jpg"));
+        assert_eq!(got.media_type, MediaType::Video);
+        let meta = got.video.unwrap();
+        assert_eq!(meta.duration_ms, Some(4200));
+        assert_eq!(meta.codec.as_deref(), Some("hevc"));
+        assert!(!got.is_animated);
+
+        assert!(db.get_file("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn add_file_records_animation() {
+        let db = Database::in_memory().unwrap();
+        let mut gif = file("g1", "/pics/a.gif", "2024-01-01T00:00:00+00:00");
+        gif.is_animated = true;
+        gif.animation = Some(AnimationMetadata {
+            frame_count: 12,
+            duration_ms: 1200,
+        });
+        db.add_file(&gif, "/pics").unwrap();
+
+        let got = db.get_file("g1").unwrap().unwrap();
+        assert!(got.is_animated);
+        let anim = got.animation.unwrap();
+        assert_eq!((anim.frame_count, anim.duration_ms), (12, 1200));
+        assert_eq!(got.media_type, MediaType::Image);
+        assert!(got.video.is_none());
+    }
+
+    #[test]
+    fn sidecar_fields_survive_a_reindex_without_sidecar() {
+        let db = Database::in_memory().unwrap();
+        let mut a = file("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00");
+        a.rating = Some(4);
+        a.labels = vec!["Red".into(), "Blue".into()];
+        a.description = Some("Eiffel & lights".into());
+        db.add_file(&a, "/pics").unwrap();
+        let stored = db.get_file("a").unwrap().unwrap();
+        assert_eq!(stored.labels, ["Red", "Blue"]);
+        assert_eq!(stored.description.as_deref(), Some("Eiffel & lights"));
+
+        db.set_rating(&["a".to_string()], Some(2)).unwrap();
+        // The sidecar is gone: labels go with it, the user's rating and the
+        // description stay
+        let bare = file("a", "/pics/a.jpg", "2024-01-02T00:00:00+00:00");
+        db.add_file(&bare, "/pics").unwrap();
+        let stored = db.get_file("a").unwrap().unwrap();
+        assert_eq!(stored.rating, Some(2));
+        assert!(stored.labels.is_empty());
+        assert_eq!(stored.description.as_deref(), Some("Eiffel & lights"));
+
+        db.set_rating(&["a".to_string()], None).unwrap();
+        assert_eq!(db.get_file("a").unwrap().unwrap().rating, None);
+    }
+
+    #[test]
+    fn user_ratings_win_over_the_sidecar_on_reindex() {
+        let db = Database::in_memory().unwrap();
+        let rated = |id: &str, rating: Option<i32>| {
+            let mut f = file(
+                id,
+                &format!("/pics/{}.jpg", id),
+                "2024-01-01T00:00:00+00:00",
+            );
+            f.rating = rating;
+            f
+        };
+        let rating = |id: &str| db.get_file(id).unwrap().unwrap().rating;
+
+        // A sidecar rating follows the sidecar
+        db.add_file(&rated("a", Some(4)), "/pics").unwrap();
+        db.add_file(&rated("a", Some(3)), "/pics").unwrap();
+        assert_eq!(rating("a"), Some(3));
+        db.add_file(&rated("a", None), "/pics").unwrap();
+        assert_eq!(rating("a"), None);
+
+        // One set in the gallery doesn't, even when it was cleared
+        db.add_file(&rated("b", Some(4)), "/pics").unwrap();
+        db.set_rating(&["b".to_string()], Some(1)).unwrap();
+        db.add_file(&rated("b", Some(5)), "/pics").unwrap();
+        assert_eq!(rating("b"), Some(1));
+        db.set_rating(&["b".to_string()], None).unwrap();
+        db.add_file(&rated("b", Some(5)), "/pics").unwrap();
+        assert_eq!(rating("b"), None);
+
+        assert!(db.rating_cleared("b").unwrap());
+        assert!(!db.rating_cleared("a").unwrap());
+        assert!(!db.rating_cleared("missing").unwrap());
+    }
+
+    #[test]
+    fn add_file_upserts_by_path_and_keeps_the_id() {
+        let db = db_with(&[("f1", "/pics/a.jpg", "2024-01-01T00:00:00+00:00")]);
+        let mut again = file("other-id", "/pics/a.jpg", "2024-02-01T00:00:00+00:00");
+        again.size = 999;
+        db.add_file(&again, "/pics").unwrap();
+
+        let all = list(&db, None);
+        assert_eq!(ids(&all), ["f1"]);
+        assert_eq!(all[0].size, 999);
+        assert_eq!(all[0].modified, "2024-02-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn file_from_row_maps_file_columns() {
+        let db = db_with(&[("f1", "/pics/a.jpg", "2024-01-01T00:00:00+00:00")]);
+        let conn = db.writer().unwrap();
+        let got = conn
+            .query_row(
+                &format!("SELECT {} FROM files", FILE_COLUMNS),
+                [],
+                Database::file_from_row,
+            )
+            .unwrap();
+        assert_eq!(got.id, "f1");
+        assert_eq!(got.size, 100);
+        assert_eq!(got.media_type, MediaType::Image);
+    }
+
+    #[test]
+    fn get_files_pages_newest_first() {
+        let db = db_with(&[
+            ("old", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("mid", "/other/b.jpg", "2024-01-02T00:00:00+00:00"),
+            ("new", "/pics/c.jpg", "2024-01-03T00:00:00+00:00"),
+        ]);
+        assert_eq!(ids(&list(&db, None)), ["new", "mid", "old"]);
+
+        let sort = FileSort::default();
+        let first = db
+            .get_files(None, &FileFilter::default(), sort, None, 2)
+            .unwrap();
+        assert_eq!(ids(&first.files), ["new", "mid"]);
+        assert_eq!(first.total, 3);
+        let second = db
+            .get_files(
+                None,
+                &FileFilter::default(),
+                sort,
+                first.next_cursor.as_ref(),
+                2,
+            )
+            .unwrap();
+        assert_eq!(ids(&second.files), ["old"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[test]
+    fn get_files_cursor_breaks_ties_by_id_and_ignores_new_rows() {
+        let same = "2024-01-01T00:00:00+00:00";
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", same),
+            ("b", "/pics/b.jpg", same),
+            ("c", "/pics/c.jpg", same),
+        ]);
+        let sort = FileSort::default();
+        let first = db
+            .get_files(Some("/pics"), &FileFilter::default(), sort, None, 2)
+            .unwrap();
+        assert_eq!(ids(&first.files), ["c", "b"]);
+
+        // A newer file arriving mid-scroll lands above the cursor
+        db.add_file(
+            &file("d", "/pics/d.jpg", "2024-06-01T00:00:00+00:00"),
+            "/pics",
+        )
+        .unwrap();
+        let next = db
+            .get_files(
+                Some("/pics"),
+                &FileFilter::default(),
+                sort,
+                first.next_cursor.as_ref(),
+                2,
+            )
+            .unwrap();
+        assert_eq!(ids(&next.files), ["a"]);
+        assert_eq!(next.total, 4);
+    }
+
+    #[test]
+    fn get_files_sorts_names_naturally() {
+        let db = db_with(&[
+            ("10", "/pics/IMG10.jpg", "2024-01-01T00:00:00+00:00"),
+            ("2", "/pics/img2.jpg", "2024-01-02T00:00:00+00:00"),
+            ("b", "/pics/b.jpg", "2024-01-03T00:00:00+00:00"),
+            ("002", "/pics/img002b.jpg", "2024-01-04T00:00:00+00:00"),
+        ]);
+        assert_eq!(
+            sorted(&db, SortKey::Name, SortDirection::Asc),
+            ["b", "2", "002", "10"]
+        );
+        assert_eq!(
+            sorted(&db, SortKey::Name, SortDirection::Desc),
+            ["10", "002", "2", "b"]
+        );
+    }
+
+    #[test]
+    fn get_files_sorts_by_size_dimensions_rating_and_date_taken() {
+        let db = Database::in_memory().unwrap();
+        let mut a = file("a", "/pics/a.jpg", "2024-03-01T00:00:00+00:00");
+        a.size = 300;
+        a.dimensions = Some(Dimensions {
+            width: 10,
+            height: 10,
+        });
+        a.rating = Some(5);
+        let mut b = file("b", "/pics/b.jpg", "2024-02-01T00:00:00+00:00");
+        b.size = 100;
+        b.dimensions = Some(Dimensions {
+            width: 40,
+            height: 30,
+        });
+        b.metadata = Some(ImageMetadata {
+            date_taken: Some("2020-01-01T00:00:00+00:00".into()),
+            ..Default::default()
+        });
+        let mut c = file("c", "/pics/c.jpg", "2024-01-01T00:00:00+00:00");
+        c.size = 200;
+        c.rating = Some(2);
+        for f in [&a, &b, &c] {
+            db.add_file(f, "/pics").unwrap();
+        }
+
+        let asc = SortDirection::Asc;
+        assert_eq!(sorted(&db, SortKey::Size, asc), ["b", "c", "a"]);
+        // Files without dimensions or rating sort as zero
+        assert_eq!(sorted(&db, SortKey::Dimensions, asc), ["c", "a", "b"]);
+        assert_eq!(sorted(&db, SortKey::Rating, asc), ["b", "c", "a"]);
+        // Falls back to the modification date when there is no capture date
+        assert_eq!(sorted(&db, SortKey::DateTaken, asc), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn timestamps_are_stored_as_instants() {
+        let db = Database::in_memory().unwrap();
+        // 01:00+02:00 is earlier than 00:30Z even though it sorts later as text
+        let mut east = file("east", "/pics/east.jpg", "2024-05-01T01:00:00+02:00");
+        east.created = "2024-04-30T10:00:00+00:00".into();
+        east.metadata = Some(ImageMetadata {
+            date_taken: Some("2024-04-01T09:00:00+09:00".into()),
+            ..Default::default()
+        });
+        let utc = file("utc", "/pics/utc.jpg", "2024-05-01T00:30:00+00:00");
+        db.add_file(&east, "/pics").unwrap();
+        db.add_file(&utc, "/pics").unwrap();
+
+        assert_eq!(ids(&list(&db, None)), ["utc", "east"]);
+        let got = db.get_file("east").unwrap().unwrap();
+        assert_eq!(got.modified, "2024-04-30T23:00:00+00:00");
+        assert_eq!(got.created, "2024-04-30T10:00:00+00:00");
+        // The capture date keeps the offset it was recorded with
+        assert_eq!(
+            got.metadata.unwrap().date_taken.as_deref(),
+            Some("2024-04-01T09:00:00+09:00")
+        );
+        assert_eq!(db.get_file("utc").unwrap().unwrap().created, "");
+    }
+
+    #[test]
+    fn random_order_is_stable_per_seed_and_pages() {
+        let db = Database::in_memory().unwrap();
+        for i in 0..20 {
+            let id = format!("f{}", i);
+            let path = format!("/pics/{}.jpg", id);
+            db.add_file(&file(&id, &path, "2024-01-01T00:00:00+00:00"), "/pics")
+                .unwrap();
+        }
+        let sort = |seed| FileSort {
+            key: SortKey::Random,
+            direction: SortDirection::Asc,
+            seed,
+        };
+        let all = db
+            .get_files(None, &FileFilter::default(), sort(1), None, 100)
+            .unwrap()
+            .files;
+        let again = db
+            .get_files(None, &FileFilter::default(), sort(1), None, 100)
+            .unwrap()
+            .files;
+        let other = db
+            .get_files(None, &FileFilter::default(), sort(2), None, 100)
+            .unwrap()
+            .files;
+        assert_eq!(ids(&all), ids(&again));
+        assert_ne!(ids(&all), ids(&other));
+
+        let mut paged = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = db
+                .get_files(None, &FileFilter::default(), sort(1), cursor.as_ref(), 6)
+                .unwrap();
+            paged.extend(page.files);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(ids(&paged), ids(&all));
+    }
+
+    #[test]
+    fn natural_sort_key_compares_digit_runs_by_value() {
+        assert!(natural_sort_key("img2.jpg") < natural_sort_key("img10.jpg"));
+        assert!(natural_sort_key("IMG_9") < natural_sort_key("img_10"));
+        assert_eq!(natural_sort_key("a007"), natural_sort_key("A7"));
+    }
+
+    #[test]
+    fn get_files_filters_by_orientation_and_resolution() {
+        let db = Database::in_memory().unwrap();
+        let sizes = [
+            ("wide", 4000, 3000),
+            ("tall", 1080, 1920),
+            ("square", 1000, 1005),
+        ];
+        for (id, width, height) in sizes {
+            let mut f = file(
+                id,
+                &format!("/pics/{}.jpg", id),
+                "2024-01-01T00:00:00+00:00",
+            );
+            f.dimensions = Some(Dimensions { width, height });
+            db.add_file(&f, "/pics").unwrap();
+        }
+        db.add_file(
+            &file("unknown", "/pics/unknown.jpg", "2024-01-01T00:00:00+00:00"),
+            "/pics",
+        )
+        .unwrap();
+
+        let listed = |filter: FileFilter| {
+            let page = db
+                .get_files(None, &filter, FileSort::default(), None, 100)
+                .unwrap();
+            let mut ids: Vec<String> = page.files.into_iter().map(|f| f.id).collect();
+            ids.sort();
+            assert_eq!(page.total, ids.len());
+            ids
+        };
+        let orientation = |o| FileFilter {
+            orientation: Some(o),
+            ..Default::default()
+        };
+        assert_eq!(listed(orientation(Orientation::Landscape)), ["wide"]);
+        assert_eq!(listed(orientation(Orientation::Portrait)), ["tall"]);
+        assert_eq!(listed(orientation(Orientation::Square)), ["square"]);
+        assert_eq!(
+            listed(FileFilter {
+                min_width: Some(1000),
+                min_height: Some(1500),
+                ..Default::default()
+            }),
+            ["tall", "wide"]
+        );
+        assert_eq!(listed(FileFilter::default()).len(), 4);
+
+        let got = db.get_file("tall").unwrap().unwrap().dimensions.unwrap();
+        assert_eq!((got.width, got.height), (1080, 1920));
+    }
+
+    fn geotagged(id: &str, latitude: f64, longitude: f64) -> FileMeta {
+        let mut f = file(
+            id,
+            &format!("/pics/{}.jpg", id),
+            "2024-01-01T00:00:00+00:00",
+        );
+        f.metadata = Some(ImageMetadata {
+            gps_coordinates: Some(GpsCoordinates {
+                latitude,
+                longitude,
+            }),
+            ..Default::default()
+        });
+        f
+    }
+
+    #[test]
+    fn geotagged_files_get_the_nearest_place() {
+        let db = Database::in_memory().unwrap();
+        // Eiffel Tower, central Kyoto, mid-Atlantic
+        db.add_file(&geotagged("paris", 48.8584, 2.2945), "/pics")
+            .unwrap();
+        db.add_file(&geotagged("kyoto", 35.0116, 135.7681), "/pics")
+            .unwrap();
+        db.add_file(&geotagged("ocean", 30.0, -40.0), "/pics")
+            .unwrap();
+
+        let paris = db.get_file("paris").unwrap().unwrap();
+        let place = paris.place.unwrap();
+        assert_eq!(
+            (place.city.as_str(), place.country_code.as_str()),
+            ("Paris", "FR")
+        );
+        assert_eq!(place.region, "Île-de-France");
+        let gps = paris.metadata.unwrap().gps_coordinates.unwrap();
+        assert_eq!((gps.latitude, gps.longitude), (48.8584, 2.2945));
+        assert_eq!(
+            db.get_file("kyoto").unwrap().unwrap().place.unwrap().city,
+            "Kyoto"
+        );
+        assert!(db.get_file("ocean").unwrap().unwrap().place.is_none());
+
+        let places = db.get_places(Some("/pics")).unwrap();
+        let cities: Vec<&str> = places.iter().map(|p| p.place.city.as_str()).collect();
+        // Equal counts fall back to country name
+        assert_eq!(cities, ["Paris", "Kyoto"]);
+
+        let in_japan = FileFilter {
+            place: Some(PlaceFilter {
+                country_code: "JP".into(),
+                region: None,
+                city: Some("Kyoto".into()),
+            }),
+            ..Default::default()
+        };
+        let page = db
+            .get_files(None, &in_japan, FileSort::default(), None, 10)
+            .unwrap();
+        assert_eq!(ids(&page.files), ["kyoto"]);
+    }
+
+    #[test]
+    fn places_reload_when_the_bundled_data_changes() {
+        let db = Database::in_memory().unwrap();
+        let conn = db.writer().unwrap();
+        let count = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM geo_places", [], |row| row.get(0))
+                .unwrap()
+        };
+        let loaded = count(&conn);
+        assert!(loaded > 0);
+        assert!(!geocode::sync_places(&conn).unwrap());
+
+        conn.execute_batch(
+            "DELETE FROM geo_places;
+             UPDATE kv_store SET value = 'stale' WHERE key = 'geo_places_sha256';
+             INSERT INTO folder_snapshots (path, file_count, agg_mtime) VALUES ('/pics', 1, 1);",
+        )
+        .unwrap();
+        assert!(geocode::sync_places(&conn).unwrap());
+        assert_eq!(count(&conn), loaded);
+        // Indexed files are reprocessed to pick up their new places
+        drop(conn);
+        assert_eq!(db.get_snapshot("/pics").unwrap(), None);
+    }
+
+    #[test]
+    fn bounds_filter_handles_the_antimeridian() {
+        let db = Database::in_memory().unwrap();
+        db.add_file(&geotagged("fiji", -18.1, 178.4), "/pics")
+            .unwrap();
+        db.add_file(&geotagged("samoa", -13.8, -171.8), "/pics")
+            .unwrap();
+        db.add_file(&geotagged("sydney", -33.9, 151.2), "/pics")
+            .unwrap();
+        db.add_file(
+            &file("none", "/pics/none.jpg", "2024-01-01T00:00:00+00:00"),
+            "/pics",
+        )
+        .unwrap();
+
+        let within = |bounds| {
+            let filter = FileFilter {
+                bounds: Some(bounds),
+                ..Default::default()
+            };
+            let page = db
+                .get_files(None, &filter, FileSort::default(), None, 10)
+                .unwrap();
+            let mut ids: Vec<String> = page.files.into_iter().map(|f| f.id).collect();
+            ids.sort();
+            ids
+        };
+        let pacific = GeoBounds {
+            south: -25.0,
+            west: 170.0,
+            north: -10.0,
+            east: -165.0,
+        };
+        assert_eq!(within(pacific), ["fiji", "samoa"]);
+        let australia = GeoBounds {
+            south: -45.0,
+            west: 110.0,
+            north: -10.0,
+            east: 155.0,
+        };
+        assert_eq!(within(australia), ["sydney"]);
+    }
+
+    #[test]
+    fn map_clusters_merge_when_zoomed_out() {
+        let db = Database::in_memory().unwrap();
+        db.add_file(&geotagged("louvre", 48.8606, 2.3376), "/pics")
+            .unwrap();
+        db.add_file(&geotagged("eiffel", 48.8584, 2.2945), "/pics")
+            .unwrap();
+        db.add_file(&geotagged("rome", 41.8902, 12.4922), "/pics")
+            .unwrap();
+        let none = FileFilter::default();
+
+        let world = db.get_map_clusters(None, &none, 1).unwrap();
+        assert_eq!(world.len(), 1);
+        assert_eq!(world[0].count, 3);
+        assert_eq!(world[0].bounds.south, 41.8902);
+        assert_eq!(world[0].bounds.east, 12.4922);
+
+        let mut europe: Vec<usize> = db
+            .get_map_clusters(None, &none, 6)
+            .unwrap()
+            .iter()
+            .map(|c| c.count)
+            .collect();
+        europe.sort();
+        assert_eq!(europe, [1, 2]);
+
+        assert_eq!(db.get_map_clusters(None, &none, 18).unwrap().len(), 3);
+    }
+
+    /// Files with local wall-clock times, so buckets don't depend on the zone.
+    fn timeline_db() -> Database {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2023-12-31T12:00:00"),
+            ("b", "/pics/b.jpg", "2024-03-02T12:00:00"),
+            ("c", "/pics/c.jpg", "2024-03-02T18:00:00"),
+            ("d", "/pics/d.jpg", "2024-03-20T12:00:00"),
+            ("e", "/pics/e.jpg", "2025-01-01T12:00:00"),
+        ]);
+        // Taken long before it was copied into the folder
+        let mut f = file("f", "/pics/f.jpg", "2025-06-01T12:00:00");
+        f.metadata = Some(ImageMetadata {
+            date_taken: Some("2024-07-04T12:00:00".into()),
+            ..Default::default()
+        });
+        db.add_file(&f, "/pics").unwrap();
+        db
+    }
+
+    fn histogram(
+        db: &Database,
+        filter: &FileFilter,
+        granularity: DateGranularity,
+    ) -> Vec<(String, usize)> {
+        db.get_date_histogram(None, filter, granularity)
+            .unwrap()
+            .into_iter()
+            .map(|b| (b.period, b.count))
+            .collect()
+    }
+
+    #[test]
+    fn date_histogram_groups_by_capture_date() {
+        let db = timeline_db();
+        let all = FileFilter::default();
+        let buckets = |v: &[(&str, usize)]| -> Vec<(String, usize)> {
+            v.iter().map(|(p, c)| (p.to_string(), *c)).collect()
+        };
+        assert_eq!(
+            histogram(&db, &all, DateGranularity::Year),
+            buckets(&[("2025", 1), ("2024", 4), ("2023", 1)])
+        );
+        assert_eq!(
+            histogram(&db, &all, DateGranularity::Month),
+            buckets(&[
+                ("2025-01", 1),
+                ("2024-07", 1),
+                ("2024-03", 3),
+                ("2023-12", 1)
+            ])
+        );
+        assert_eq!(
+            histogram(&db, &all, DateGranularity::Day)[2..4],
+            buckets(&[("2024-03-20", 1), ("2024-03-02", 2)])
+        );
+
+        db.writer()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO albums (id, name) VALUES ('al', 'Trip');
+                 INSERT INTO album_files (album_id, file_id) VALUES ('al', 'b'), ('al', 'f');",
+            )
+            .unwrap();
+        let album = FileFilter {
+            album_id: Some("al".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            histogram(&db, &album, DateGranularity::Month),
+            buckets(&[("2024-07", 1), ("2024-03", 1)])
+        );
+        assert_eq!(db.count_files(None, &album).unwrap(), 2);
+    }
+
+    #[test]
+    fn jump_to_date_finds_the_page_for_a_period() {
+        let db = timeline_db();
+        let all = FileFilter::default();
+        let page_at = |direction, jump: &DateJump| -> Vec<String> {
+            let sort = FileSort {
+                key: SortKey::DateTaken,
+                direction,
+                seed: 0,
+            };
+            db.get_files(None, &all, sort, jump.cursor.as_ref(), 2)
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|f| f.id)
+                .collect()
+        };
+
+        // Newest first: e, f, d, c, b, a
+        let jump = db
+            .jump_to_date(None, &all, SortDirection::Desc, "2024-03")
+            .unwrap();
+        assert_eq!(jump.offset, 2);
+        assert_eq!(page_at(SortDirection::Desc, &jump), ["d", "c"]);
+
+        let jump = db
+            .jump_to_date(None, &all, SortDirection::Asc, "2024-03-02")
+            .unwrap();
+        assert_eq!(jump.offset, 1);
+        assert_eq!(page_at(SortDirection::Asc, &jump), ["b", "c"]);
+
+        // An empty period lands where it would be; the top has no cursor
+        let jump = db
+            .jump_to_date(None, &all, SortDirection::Desc, "2024-05")
+            .unwrap();
+        assert_eq!(jump.offset, 2);
+        assert_eq!(page_at(SortDirection::Desc, &jump), ["d", "c"]);
+        let jump = db
+            .jump_to_date(None, &all, SortDirection::Desc, "2026")
+            .unwrap();
+        assert_eq!((jump.offset, jump.cursor.is_none()), (0, true));
+
+        assert!(db
+            .jump_to_date(None, &all, SortDirection::Desc, "2024-13")
+            .is_err());
+    }
+
+    /// A file taken at a local wall-clock time, optionally geotagged.
+    fn taken(id: &str, when: &str, position: Option<(f64, f64)>) -> FileMeta {
+        let mut f = file(id, &format!("/pics/{}.jpg", id), "2025-01-01T00:00:00");
+        f.metadata = Some(ImageMetadata {
+            date_taken: Some(when.into()),
+            gps_coordinates: position.map(|(latitude, longitude)| GpsCoordinates {
+                latitude,
+                longitude,
+            }),
+            ..Default::default()
+        });
+        f
+    }
+
+    #[test]
+    fn on_this_day_lists_earlier_years_only() {
+        let db = Database::in_memory().unwrap();
+        for f in [
+            taken("a", "2023-03-02T12:00:00", None),
+            taken("b", "2020-03-02T09:00:00", None),
+            taken("this-year", "2024-03-02T10:00:00", None),
+            taken("next-day", "2023-03-03T12:00:00", None),
+            // No capture date; only modified on the day
+            file("copied", "/pics/copied.jpg", "2022-03-02T12:00:00"),
+        ] {
+            db.add_file(&f, "/pics").unwrap();
+        }
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 3, 2).unwrap();
+        let files = db
+            .get_on_this_day(None, &FileFilter::default(), today)
+            .unwrap();
+        assert_eq!(ids(&files), ["a", "b"]);
+    }
+
+    #[test]
+    fn cluster_events_splits_on_gaps_and_distance() {
+        let db = Database::in_memory().unwrap();
+        let tokyo = Some((35.68, 139.69));
+        let kyoto = Some((35.02, 135.75));
+        for f in [
+            taken("a", "2024-03-02T10:00:00", tokyo),
+            taken("b", "2024-03-02T12:00:00", tokyo),
+            taken("c", "2024-03-02T15:00:00", kyoto),
+            taken("d", "2024-03-02T16:00:00", None),
+            taken("alone", "2024-03-05T10:00:00", None),
+            taken("f", "2024-04-01T20:00:00", None),
+            taken("g", "2024-04-02T02:00:00", None),
+        ] {
+            db.add_file(&f, "/pics").unwrap();
+        }
+        db.writer()
+            .unwrap()
+            .execute("INSERT INTO albums (id, name) VALUES ('mine', 'Mine')", [])
+            .unwrap();
+        let members = |album: &Album| -> Vec<String> {
+            let filter = FileFilter {
+                album_id: Some(album.id.clone()),
+                ..Default::default()
+            };
+            let sort = FileSort {
+                key: SortKey::DateTaken,
+                direction: SortDirection::Asc,
+                seed: 0,
+            };
+            let page = db.get_files(None, &filter, sort, None, 100).unwrap();
+            page.files.into_iter().map(|f| f.id).collect()
+        };
+
+        let mut options = EventOptions {
+            min_files: 2,
+            ..Default::default()
+        };
+        let events = db.cluster_events(&options).unwrap();
+        let names: Vec<&str> = events.iter().map(|a| a.name.as_str()).collect();
+        // The lone photo is too small to be an event
+        assert_eq!(names, ["1–2 April 2024", "Tokyo, 2 March 2024"]);
+        assert_eq!(members(&events[1]), ["a", "b", "c", "d"]);
+        assert_eq!(events[1].cover_image.as_deref(), Some("a"));
+
+        // Re-running replaces the events and leaves manual albums alone
+        options.max_distance_km = Some(100.0);
+        let events = db.cluster_events(&options).unwrap();
+        assert_eq!(events.len(), 3);
+        // A photo without a position stays with the last known place
+        assert_eq!(members(&events[1]), ["c", "d"]);
+        assert_eq!(events[1].name, "Kyoto, 2 March 2024");
+        let stored = db.get_albums(Some(AlbumKind::Event)).unwrap();
+        assert_eq!(
+            stored.iter().map(|a| &a.id).collect::<Vec<_>>(),
+            events.iter().map(|a| &a.id).collect::<Vec<_>>()
+        );
+        assert_eq!(stored[2].file_count, 2);
+        let manual = db.get_albums(Some(AlbumKind::Manual)).unwrap();
+        assert_eq!(manual.len(), 1);
+        assert_eq!(manual[0].created, "");
+        let linked: i64 = db
+            .writer()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM album_files", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(linked, 6);
+    }
+
+    #[test]
+    fn smart_albums_list_files_matching_their_query() {
+        let db = Database::in_memory().unwrap();
+        let mut best = taken("best", "2024-06-01T12:00:00", None);
+        best.rating = Some(5);
+        let mut old_best = taken("old-best", "2023-06-01T12:00:00", None);
+        old_best.rating = Some(5);
+        let mut good = taken("good", "2024-07-01T12:00:00", None);
+        good.rating = Some(4);
+        let mut big = file("big", "/pics/big.PNG", "2024-01-01T12:00:00");
+        big.file_type = "png".into();
+        big.size = 20_000_000;
+        let mut small = file("small", "/pics/small.png", "2024-01-01T12:00:00");
+        small.size = 1_000;
+        for f in [&best, &old_best, &good, &big, &small] {
+            db.add_file(f, "/pics").unwrap();
+        }
+        let listed = |album: &Album| -> Vec<String> {
+            let filter = FileFilter {
+                album_id: Some(album.id.clone()),
+                ..Default::default()
+            };
+            let sort = FileSort {
+                key: SortKey::DateTaken,
+                ..Default::default()
+            };
+            let page = db.get_files(None, &filter, sort, None, 100).unwrap();
+            assert_eq!(page.total, page.files.len());
+            page.files.into_iter().map(|f| f.id).collect()
+        };
+
+        let five_stars_2024 = SearchQuery {
+            rating: Some(5),
+            date_range: Some(DateRange {
+                start: Some("2024".into()),
+                end: Some("2024".into()),
+            }),
+            ..Default::default()
+        };
+        let stars = db
+            .create_smart_album("5 stars from 2024", None, five_stars_2024)
+            .unwrap();
+        assert_eq!((stars.kind, stars.file_count), (AlbumKind::Smart, 1));
+        assert_eq!(listed(&stars), ["best"]);
+
+        let large_pngs = SearchQuery {
+            file_types: Some(vec![".PNG".into()]),
+            size_range: Some(SizeRange {
+                min: Some(10_000_000),
+                max: None,
+            }),
+            ..Default::default()
+        };
+        let pngs = db
+            .create_smart_album("Large PNGs", None, large_pngs)
+            .unwrap();
+        assert_eq!(listed(&pngs), ["big"]);
+
+        // Evaluated on every listing, so new matches show up
+        let mut newer = taken("newer", "2024-12-31T12:00:00", None);
+        newer.rating = Some(5);
+        db.add_file(&newer, "/pics").unwrap();
+        assert_eq!(listed(&stars), ["newer", "best"]);
+
+        let four_stars = SearchQuery {
+            rating: Some(4),
+            ..Default::default()
+        };
+        db.update_smart_album(&stars.id, "4 stars and up", None, &four_stars)
+            .unwrap();
+        assert_eq!(listed(&stars), ["newer", "good", "best", "old-best"]);
+        let albums = db.get_albums(Some(AlbumKind::Smart)).unwrap();
+        let saved = albums.iter().find(|a| a.id == stars.id).unwrap();
+        assert_eq!(saved.name, "4 stars and up");
+        assert_eq!(saved.file_count, 4);
+        assert_eq!(saved.query.as_ref(), Some(&four_stars));
+
+        let bad_date = SearchQuery {
+            date_range: Some(DateRange {
+                start: Some("last week".into()),
+                end: None,
+            }),
+            ..Default::default()
+        };
+        assert!(db.create_smart_album("Bad", None, bad_date).is_err());
+        assert!(db
+            .update_smart_album("missing", "x", None, &four_stars)
+            .is_err());
+
+        db.delete_album(&pngs.id).unwrap();
+        assert_eq!(db.get_albums(None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn file_paths_in_dir_skip_subfolders_and_lookalikes() {
+        let db = db_with(&[
+            ("a", "/pics/IMG_1.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/pics/IMG_1.cr2", "2024-01-02T00:00:00+00:00"),
+            ("c", "/pics/beach/IMG_1.jpg", "2024-01-03T00:00:00+00:00"),
+            ("d", "/pics2/IMG_1.jpg", "2024-01-04T00:00:00+00:00"),
+        ]);
+        let mut paths = db.get_file_paths_in_dir("/pics").unwrap();
+        paths.sort();
+        assert_eq!(paths, ["/pics/IMG_1.cr2", "/pics/IMG_1.jpg"]);
+        assert_eq!(db.get_file_paths_in_dir("/pics/").unwrap().len(), 2);
+        assert!(db.get_file_paths_in_dir("/none").unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_matches_name_and_path_substrings() {
+        let db = db_with(&[
+            ("a", "/pics/beach/one.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/pics/two.jpg", "2024-01-02T00:00:00+00:00"),
+            ("c", "/pics/beach-day.jpg", "2024-01-03T00:00:00+00:00"),
+        ]);
+        let filter = FileFilter {
+            search: Some(SearchQuery {
+                query: Some("beach".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let page = db
+            .get_files(None, &filter, FileSort::default(), None, 100)
+            .unwrap();
+        assert_eq!(ids(&page.files), ["c", "a"]);
+    }
+
+    #[test]
+    fn search_matches_edited_captions() {
+        let db = Database::in_memory().unwrap();
+        let mut a = file("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00");
+        a.caption = Some("a dog on the beach".into());
+        let b = file("b", "/pics/b.jpg", "2024-01-02T00:00:00+00:00");
+        for f in [&a, &b] {
+            db.add_file(f, "/pics").unwrap();
+        }
+        let search = |text: &str| {
+            let filter = FileFilter {
+                search: Some(SearchQuery {
+                    query: Some(text.into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let page = db
+                .get_files(None, &filter, FileSort::default(), None, 100)
+                .unwrap();
+            page.files.into_iter().map(|f| f.id).collect::<Vec<_>>()
+        };
+        assert_eq!(search("dog"), ["a"]);
+
+        assert!(db.set_caption("/pics/b.jpg", Some("a cat")).unwrap());
+        assert!(!db.set_caption("/pics/missing.jpg", Some("a cat")).unwrap());
+        assert_eq!(search("cat"), ["b"]);
+        assert_eq!(
+            db.get_file("b").unwrap().unwrap().caption.as_deref(),
+            Some("a cat")
+        );
+    }
+
+    fn tagged(db: &Database, tags: &[&str]) -> Vec<String> {
+        let filter = FileFilter {
+            search: Some(SearchQuery {
+                tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let page = db
+            .get_files(None, &filter, FileSort::default(), None, 100)
+            .unwrap();
+        page.files.into_iter().map(|f| f.id).collect()
+    }
+
+    #[test]
+    fn embedded_keywords_become_a_tag_tree() {
+        let db = Database::in_memory().unwrap();
+        let mut a = file("a", "/pics/a.jpg", "2024-01-02T00:00:00+00:00");
+        a.tags = vec!["Places|Europe|Paris".into(), "sunset".into()];
+        let mut b = file("b", "/pics/b.jpg", "2024-01-01T00:00:00+00:00");
+        b.tags = vec!["Places | Asia | Tokyo".into()];
+        let c = file("c", "/pics/c.jpg", "2024-01-03T00:00:00+00:00");
+        for f in [&a, &b, &c] {
+            db.add_file(f, "/pics").unwrap();
+        }
+
+        let tags = db.get_tags().unwrap();
+        let tree: Vec<(&str, usize)> = tags
+            .iter()
+            .map(|t| (t.path.as_str(), t.file_count))
+            .collect();
+        assert_eq!(
+            tree,
+            [
+                ("Places", 2),
+                ("Places|Asia", 1),
+                ("Places|Asia|Tokyo", 1),
+                ("Places|Europe", 1),
+                ("Places|Europe|Paris", 1),
+                ("sunset", 1),
+            ]
+        );
+        let paris = tags.iter().find(|t| t.name == "Paris").unwrap();
+        let europe = tags.iter().find(|t| t.path == "Places|Europe").unwrap();
+        assert_eq!(paris.parent_id.as_ref(), Some(&europe.id));
+
+        // A parent matches its descendants, but not tags that merely share
+        // a prefix
+        assert_eq!(tagged(&db, &["Places"]), ["a", "b"]);
+        assert_eq!(tagged(&db, &["Places|Europe"]), ["a"]);
+        assert_eq!(tagged(&db, &["Places", "sunset"]), ["a"]);
+        assert!(tagged(&db, &["Places|Eu"]).is_empty());
+        assert_eq!(
+            db.get_file("a").unwrap().unwrap().tags,
+            ["Places|Europe|Paris", "sunset"]
+        );
+    }
+
+    #[test]
+    fn reindexing_replaces_keywords_but_keeps_user_tags() {
+        let db = Database::in_memory().unwrap();
+        let mut a = file("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00");
+        a.tags = vec!["Places|Europe|Paris".into(), "sunset".into()];
+        db.add_file(&a, "/pics").unwrap();
+        let ids = vec!["a".to_string()];
+        db.tag_files(&ids, "Favorites").unwrap();
+        db.tag_files(&ids, "sunset").unwrap();
+
+        a.tags = vec!["Places|Europe|Lyon".into()];
+        db.add_file(&a, "/pics").unwrap();
+        let tags = |db: &Database| db.get_file("a").unwrap().unwrap().tags;
+        assert_eq!(tags(&db), ["Favorites", "Places|Europe|Lyon", "sunset"]);
+
+        db.untag_files(&ids, "Favorites").unwrap();
+        assert_eq!(tags(&db), ["Places|Europe|Lyon", "sunset"]);
+
+        // Deleting a tag takes its subtree along
+        let places = db
+            .get_tags()
+            .unwrap()
+            .into_iter()
+            .find(|t| t.path == "Places")
+            .unwrap();
+        assert_eq!(db.delete_tag(&places.id).unwrap(), ["a"]);
+        assert_eq!(tags(&db), ["sunset"]);
+        let left: Vec<String> = db.get_tags().unwrap().into_iter().map(|t| t.path).collect();
+        assert_eq!(left, ["Favorites", "sunset"]);
+    }
+
+    #[test]
+    fn count_files_excludes_variants() {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/other/b.jpg", "2024-01-02T00:00:00+00:00"),
+            ("a_raw", "/pics/a.cr2", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.link_raw_variants("/pics").unwrap();
+        assert_eq!(db.count_files(None, &FileFilter::default()).unwrap(), 2);
+        assert_eq!(
+            db.count_files(Some("/pics"), &FileFilter::default())
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            db.count_files(Some("/none"), &FileFilter::default())
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn get_files_for_folder_only_lists_that_folder() {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/other/b.jpg", "2024-01-02T00:00:00+00:00"),
+            ("c", "/pics/c.jpg", "2024-01-03T00:00:00+00:00"),
+        ]);
+        assert_eq!(ids(&list(&db, Some("/pics"))), ["c", "a"]);
+        assert!(list(&db, Some("/none")).is_empty());
+    }
+
+    #[test]
+    fn remove_file_deletes_row_and_album_membership() {
+        let db = db_with(&[("f1", "/pics/a.jpg", "2024-01-01T00:00:00+00:00")]);
+        {
+            let conn = db.writer().unwrap();
+            conn.execute_batch(
+                "INSERT INTO albums (id, name) VALUES ('al', 'Trip');
+                 INSERT INTO album_files (album_id, file_id) VALUES ('al', 'f1');",
+            )
+            .unwrap();
+        }
+        db.remove_file("f1").unwrap();
+        assert!(db.get_file("f1").unwrap().is_none());
+        let members: i64 = db
+            .writer()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM album_files", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(members, 0);
+    }
+
+    #[test]
+    fn remove_file_by_path_deletes_row() {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/pics/b.jpg", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.remove_file_by_path("/pics/a.jpg").unwrap();
+        assert_eq!(ids(&list(&db, None)), ["b"]);
+        // Unknown paths are not an error
+        db.remove_file_by_path("/pics/zzz.jpg").unwrap();
+    }
+
+    #[test]
+    fn link_raw_variants_groups_raw_under_its_jpeg() {
+        let db = db_with(&[
+            ("jpg", "/pics/IMG_1.JPG", "2024-01-01T00:00:00+00:00"),
+            ("raw", "/pics/img_1.cr2", "2024-01-01T00:00:00+00:00"),
+            ("lone", "/pics/IMG_2.nef", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.link_raw_variants("/pics").unwrap();
+
+        let listed = list(&db, Some("/pics"));
+        let mut listed_ids = ids(&listed);
+        listed_ids.sort();
+        assert_eq!(listed_ids, ["jpg", "lone"]);
+        let jpg = listed.iter().find(|f| f.id == "jpg").unwrap();
+        assert_eq!(jpg.variants.len(), 1);
+        assert_eq!(jpg.variants[0].id, "raw");
+        assert_eq!(jpg.variants[0].file_type, "cr2");
+        assert!(listed
+            .iter()
+            .find(|f| f.id == "lone")
+            .unwrap()
+            .variants
+            .is_empty());
+    }
+
+    #[test]
+    fn removing_a_primary_releases_its_variants() {
+        let db = db_with(&[
+            ("jpg", "/pics/IMG_1.jpg", "2024-01-01T00:00:00+00:00"),
+            ("raw", "/pics/IMG_1.dng", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.link_raw_variants("/pics").unwrap();
+        db.remove_file("jpg").unwrap();
+        assert_eq!(ids(&list(&db, None)), ["raw"]);
+
+        let db = db_with(&[
+            ("jpg", "/pics/IMG_1.jpg", "2024-01-01T00:00:00+00:00"),
+            ("raw", "/pics/IMG_1.dng", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.link_raw_variants("/pics").unwrap();
+        db.remove_file_by_path("/pics/IMG_1.jpg").unwrap();
+        assert_eq!(ids(&list(&db, None)), ["raw"]);
+    }
+
+    #[test]
+    fn attach_variants_fills_only_listed_primaries() {
+        let db = db_with(&[
+            ("jpg", "/pics/IMG_1.jpg", "2024-01-01T00:00:00+00:00"),
+            ("raw", "/pics/IMG_1.arw", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.link_raw_variants("/pics").unwrap();
+        let conn = db.writer().unwrap();
+
+        let mut files = vec![
+            file("jpg", "/pics/IMG_1.jpg", ""),
+            file("x", "/pics/x.jpg", ""),
+        ];
+        Database::attach_variants(&conn, &mut files).unwrap();
+        assert_eq!(variant_ids(&files[0].variants), ["raw"]);
+        assert!(files[1].variants.is_empty());
+
+        let mut none: Vec<FileMeta> = Vec::new();
+        Database::attach_variants(&conn, &mut none).unwrap();
+    }
+
+    #[test]
+    fn attach_variants_groups_several_raws() {
+        let db = db_with(&[
+            ("jpg", "/pics/IMG_1.jpg", "2024-01-01T00:00:00+00:00"),
+            ("cr2", "/pics/IMG_1.cr2", "2024-01-01T00:00:00+00:00"),
+            ("dng", "/pics/IMG_1.dng", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.link_raw_variants("/pics").unwrap();
+        let files = list(&db, None);
+        let mut v = variant_ids(&files[0].variants);
+        v.sort();
+        assert_eq!(v, ["cr2", "dng"]);
+    }
+
+    #[test]
+    fn rename_file_keeps_id_and_updates_path_fields() {
+        let db = db_with(&[("f1", "/pics/a.jpg", "2024-01-01T00:00:00+00:00")]);
+        assert!(db
+            .rename_file("/pics/a.jpg", "/moved/b.JPEG", "/moved")
+            .unwrap());
+
+        let got = db.get_file("f1").unwrap().unwrap();
+        assert_eq!(got.path, "/moved/b.JPEG");
+        assert_eq!(got.name, "b.JPEG");
+        assert_eq!(got.file_type, "jpeg");
+        assert_eq!(ids(&list(&db, Some("/moved"))), ["f1"]);
+        assert!(list(&db, Some("/pics")).is_empty());
+    }
+
+    #[test]
+    fn rename_file_replaces_an_existing_target() {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/pics/b.jpg", "2024-01-01T00:00:00+00:00"),
+        ]);
+        assert!(db
+            .rename_file("/pics/a.jpg", "/pics/b.jpg", "/pics")
+            .unwrap());
+        let all = list(&db, None);
+        assert_eq!(ids(&all), ["a"]);
+        assert_eq!(all[0].path, "/pics/b.jpg");
+    }
+
+    #[test]
+    fn rename_file_reports_unknown_source() {
+        let db = db_with(&[("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00")]);
+        assert!(!db
+            .rename_file("/pics/zzz.jpg", "/pics/a.jpg", "/pics")
+            .unwrap());
+        assert!(db.get_file("a").unwrap().is_some());
+    }
+
+    #[test]
+    fn get_all_file_paths_in_folder_lists_ids_and_paths() {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/other/b.jpg", "2024-01-01T00:00:00+00:00"),
+        ]);
+        assert_eq!(
+            db.get_all_file_paths_in_folder("/pics").unwrap(),
+            [("a".to_string(), "/pics/a.jpg".to_string())]
+        );
+    }
+
+    #[test]
+    fn snapshots_save_and_overwrite() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.get_snapshot("/pics").unwrap(), None);
+        db.save_snapshot("/pics", 3, 42).unwrap();
+        assert_eq!(db.get_snapshot("/pics").unwrap(), Some((3, 42)));
+        db.save_snapshot("/pics", 4, 43).unwrap();
+        assert_eq!(db.get_snapshot("/pics").unwrap(), Some((4, 43)));
+    }
+
+    #[test]
+    fn clear_folder_removes_files_and_snapshot() {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/other/b.jpg", "2024-01-01T00:00:00+00:00"),
+        ]);
+        db.save_snapshot("/pics", 1, 1).unwrap();
+        db.save_snapshot("/other", 1, 1).unwrap();
+        db.clear_folder("/pics").unwrap();
+
+        assert_eq!(ids(&list(&db, None)), ["b"]);
+        assert_eq!(db.get_snapshot("/pics").unwrap(), None);
+        assert_eq!(db.get_snapshot("/other").unwrap(), Some((1, 1)));
+    }
+
+    #[test]
+    fn get_indexed_folders_is_distinct() {
+        let db = db_with(&[
+            ("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00"),
+            ("b", "/pics/b.jpg", "2024-01-01T00:00:00+00:00"),
+            ("c", "/other/c.jpg", "2024-01-01T00:00:00+00:00"),
+        ]);
+        let mut folders = db.get_indexed_folders().unwrap();
+        folders.sort();
+        assert_eq!(folders, ["/other", "/pics"]);
+    }
+
+    #[test]
+    fn watched_folders_add_list_remove() {
+        let db = Database::in_memory().unwrap();
+        db.add_watched_folder("/pics").unwrap();
+        db.add_watched_folder("/other").unwrap();
+        // Adding twice is a no-op
+        db.add_watched_folder("/pics").unwrap();
+        let mut folders = db.get_watched_folders().unwrap();
+        folders.sort();
+        assert_eq!(folders, ["/other", "/pics"]);
+
+        db.remove_watched_folder("/pics").unwrap();
+        assert_eq!(db.get_watched_folders().unwrap(), ["/other"]);
+    }
+
+    #[test]
+    fn settings_get_and_overwrite() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.get_setting("theme").unwrap(), None);
+        db.set_setting("theme", "dark").unwrap();
+        db.set_setting("theme", "light").unwrap();
+        assert_eq!(db.get_setting("theme").unwrap().as_deref(), Some("light"));
+    }
+
+    #[test]
+    fn clear_library_empties_every_table() {
+        let db = db_with(&[("a", "/pics/a.jpg", "2024-01-01T00:00:00+00:00")]);
+        db.save_snapshot("/pics", 1, 1).unwrap();
+        db.set_setting("k", "v").unwrap();
+        db.add_watched_folder("/pics").unwrap();
+        db.writer()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO albums (id, name) VALUES ('al', 'Trip');
+                 INSERT INTO album_files (album_id, file_id) VALUES ('al', 'a');",
+            )
+            .unwrap();
+
+        db.clear_library().unwrap();
+        assert!(list(&db, None).is_empty());
+        assert_eq!(db.get_snapshot("/pics").unwrap(), None);
+        assert_eq!(db.get_setting("k").unwrap(), None);
+        assert!(db.get_watched_folders().unwrap().is_empty());
+        let albums: i64 = db
+            .writer()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM albums", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(albums, 0);
+    }
+}
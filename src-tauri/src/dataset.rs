@@ -0,0 +1,761 @@
+//! Export of images and their sidecar captions in common ML training
+//! layouts: kohya-ss folders, HuggingFace `imagefolder` and COCO captions;
+//! and the aspect-ratio bucketing and quality checks run before training.
+
+use crate::formats::{self, Format};
+use crate::models::{
+    BucketAssignment, BucketCount, BucketOptions, BucketReport, DatasetExportOptions,
+    DatasetExportSummary, DatasetLayout, Dimensions, ExportFormat, FileMeta, MediaType, ResizeMode,
+};
+use crate::{sidecar, thumbnail};
+use anyhow::{bail, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use rayon::prelude::*;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const JPEG_QUALITY: u8 = 95;
+
+/// An image once written, with what the layout's index needs.
+struct Exported {
+    stem: String,
+    file_name: String,
+    width: u32,
+    height: u32,
+    caption: Option<String>,
+    metadata: Option<Value>,
+}
+
+/// Write the images among `files` to `options.output_dir`, calling
+/// `progress` with the number done and the total after each one.
+pub fn export(
+    files: &[FileMeta],
+    options: &DatasetExportOptions,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<DatasetExportSummary> {
+    if !(0.0..=1.0).contains(&options.validation_split) {
+        bail!("Validation split must be between 0 and 1");
+    }
+    if options
+        .resolution
+        .as_ref()
+        .is_some_and(|r| r.width == 0 || r.height == 0)
+    {
+        bail!("Resolution must be at least 1x1");
+    }
+    let output = Path::new(&options.output_dir);
+    let mut summary = DatasetExportSummary::default();
+
+    let (mut images, others): (Vec<&FileMeta>, Vec<&FileMeta>) =
+        files.iter().partition(|f| f.media_type == MediaType::Image);
+    summary
+        .skipped
+        .extend(others.into_iter().map(|f| f.path.clone()));
+    // Shuffled by path hash, so the split is the same on every export
+    images.sort_by_cached_key(|f| Sha256::digest(f.path.as_bytes()));
+    let validation = (images.len() as f64 * options.validation_split).round() as usize;
+    let (validation, train) = images.split_at(validation);
+
+    let total = images.len();
+    let mut done = 0;
+    for (split, files) in [("train", train), ("validation", validation)] {
+        if files.is_empty() {
+            continue;
+        }
+        let dir = match options.layout {
+            DatasetLayout::Kohya => output.join(split).join(format!(
+                "{}_{}",
+                options.repeats.unwrap_or(1),
+                options.concept.as_deref().unwrap_or("dataset")
+            )),
+            DatasetLayout::ImageFolder | DatasetLayout::Coco => output.join(split),
+        };
+        fs::create_dir_all(&dir)?;
+
+        let mut stems = HashSet::new();
+        let mut exported = Vec::new();
+        for file in files {
+            match export_image(file, &dir, &mut stems, options)? {
+                Some(image) => exported.push(image),
+                None => summary.skipped.push(file.path.clone()),
+            }
+            done += 1;
+            progress(done, total);
+        }
+        write_index(options.layout, output, &dir, split, &exported)?;
+        match split {
+            "train" => summary.train = exported.len(),
+            _ => summary.validation = exported.len(),
+        }
+    }
+    Ok(summary)
+}
+
+/// Copy or re-encode one image into `dir`; `None` if it can't be decoded.
+fn export_image(
+    file: &FileMeta,
+    dir: &Path,
+    stems: &mut HashSet<String>,
+    options: &DatasetExportOptions,
+) -> Result<Option<Exported>> {
+    let source = Path::new(&file.path);
+    let Some(format) = formats::detect(source).filter(|f| f.capabilities().thumbnail) else {
+        return Ok(None);
+    };
+    let own_format = match format {
+        Format::Jpeg => Some(ExportFormat::Jpeg),
+        Format::Png => Some(ExportFormat::Png),
+        Format::WebP => Some(ExportFormat::WebP),
+        _ => None,
+    };
+    let target = options.format.or(own_format).unwrap_or(ExportFormat::Png);
+    let stem = unique_stem(source, stems);
+    // Files already in the wanted format and size are copied byte for byte
+    let copy = options.resolution.is_none() && own_format == Some(target);
+
+    let (file_name, width, height) = if copy {
+        let file_name = format!("{}.{}", stem, formats::file_type_for(source, format));
+        fs::copy(source, dir.join(&file_name))?;
+        let (width, height) = match &file.dimensions {
+            Some(d) => (d.width, d.height),
+            None => image::image_dimensions(source).unwrap_or_default(),
+        };
+        (file_name, width, height)
+    } else {
+        let Ok(mut img) = thumbnail::decode_image(source, format) else {
+            return Ok(None);
+        };
+        if let Some(r) = &options.resolution {
+            img = match options.resize {
+                ResizeMode::Fit if img.width() <= r.width && img.height() <= r.height => img,
+                ResizeMode::Fit => img.resize(r.width, r.height, FilterType::Lanczos3),
+                ResizeMode::Crop => img.resize_to_fill(r.width, r.height, FilterType::Lanczos3),
+            };
+        }
+        let file_name = format!("{}.{}", stem, extension(target));
+        encode(&img, target, &dir.join(&file_name))?;
+        (file_name, img.width(), img.height())
+    };
+
+    Ok(Some(Exported {
+        stem,
+        file_name,
+        width,
+        height,
+        // The XMP description stands in for a missing text caption
+        caption: sidecar::read_caption(source)
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .or_else(|| file.description.clone()),
+        metadata: sidecar::json_path(source)
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok()),
+    }))
+}
+
+fn encode(img: &DynamicImage, format: ExportFormat, path: &Path) -> Result<()> {
+    match format {
+        ExportFormat::Jpeg => {
+            let mut out = fs::File::create(path)?;
+            let mut encoder = JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
+            encoder.encode_image(&img.to_rgb8())?;
+        }
+        ExportFormat::Png => img.save_with_format(path, ImageFormat::Png)?,
+        // The WebP encoder only takes 8-bit RGB(A)
+        ExportFormat::WebP => {
+            DynamicImage::ImageRgba8(img.to_rgba8()).save_with_format(path, ImageFormat::WebP)?
+        }
+    }
+    Ok(())
+}
+
+fn extension(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Jpeg => "jpg",
+        ExportFormat::Png => "png",
+        ExportFormat::WebP => "webp",
+    }
+}
+
+/// `source`'s stem, numbered when another exported file already has it.
+/// Compared case-insensitively for the sake of macOS and Windows.
+fn unique_stem(source: &Path, taken: &mut HashSet<String>) -> String {
+    let stem = source
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image".to_string());
+    let mut candidate = stem.clone();
+    let mut n = 1;
+    while !taken.insert(candidate.to_lowercase()) {
+        n += 1;
+        candidate = format!("{}_{}", stem, n);
+    }
+    candidate
+}
+
+/// Captions and metadata of one split, in the layout's own files.
+fn write_index(
+    layout: DatasetLayout,
+    output: &Path,
+    dir: &Path,
+    split: &str,
+    exported: &[Exported],
+) -> Result<()> {
+    match layout {
+        DatasetLayout::Kohya => {
+            for image in exported {
+                if let Some(caption) = &image.caption {
+                    fs::write(dir.join(format!("{}.txt", image.stem)), caption)?;
+                }
+            }
+        }
+        DatasetLayout::ImageFolder => {
+            let mut lines = String::new();
+            for image in exported {
+                // Extra columns come from the JSON sidecar
+                let mut row = match &image.metadata {
+                    Some(Value::Object(fields)) => fields.clone(),
+                    _ => Map::new(),
+                };
+                row.insert("file_name".into(), json!(image.file_name));
+                row.insert("text".into(), json!(image.caption.as_deref().unwrap_or("")));
+                lines.push_str(&serde_json::to_string(&row)?);
+                lines.push('\n');
+            }
+            fs::write(dir.join("metadata.jsonl"), lines)?;
+        }
+        DatasetLayout::Coco => {
+            let images: Vec<Value> = exported
+                .iter()
+                .enumerate()
+                .map(|(i, image)| {
+                    json!({
+                        "id": i + 1,
+                        "file_name": image.file_name,
+                        "width": image.width,
+                        "height": image.height,
+                    })
+                })
+                .collect();
+            let annotations: Vec<Value> = exported
+                .iter()
+                .enumerate()
+                .filter_map(|(i, image)| Some((i + 1, image.caption.as_ref()?)))
+                .enumerate()
+                .map(|(n, (image_id, caption))| {
+                    json!({ "id": n + 1, "image_id": image_id, "caption": caption })
+                })
+                .collect();
+            let annotations_dir = output.join("annotations");
+            fs::create_dir_all(&annotations_dir)?;
+            fs::write(
+                annotations_dir.join(format!("captions_{}.json", split)),
+                serde_json::to_string(&json!({
+                    "images": images,
+                    "annotations": annotations,
+                }))?,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// kohya-ss's bucket set: the square, plus every `step`-aligned size between
+/// `min_side` and `max_side` holding at most `resolution` squared pixels,
+/// both ways round. Widest first.
+pub fn bucket_resolutions(options: &BucketOptions) -> Vec<Dimensions> {
+    let step = options.step.max(1);
+    let max_area = options.resolution as u64 * options.resolution as u64;
+    let square = options.resolution / step * step;
+    let mut sizes = vec![(square, square)];
+    let mut width = options.min_side.max(step);
+    while width <= options.max_side {
+        let fit = (max_area / width as u64).min(options.max_side as u64) as u32;
+        let height = fit / step * step;
+        if height >= options.min_side {
+            sizes.push((width, height));
+            sizes.push((height, width));
+        }
+        width += step;
+    }
+    sizes.sort_by(|a, b| (b.0 as u64 * a.1 as u64).cmp(&(a.0 as u64 * b.1 as u64)));
+    sizes.dedup();
+    sizes
+        .into_iter()
+        .filter(|&(w, h)| w > 0 && h > 0)
+        .map(|(width, height)| Dimensions { width, height })
+        .collect()
+}
+
+/// Size of an image for bucketing.
+enum Measured {
+    Size(Dimensions),
+    /// Failed the full decode.
+    Corrupt,
+    /// In a format this build can't decode, with no stored size.
+    Unsupported,
+    /// No stored size, and decoding was skipped.
+    Unknown,
+}
+
+/// Assign the images among `files` to buckets and flag the ones unfit for
+/// training, calling `progress` with the number done and the total.
+pub fn check(
+    files: &[FileMeta],
+    options: &BucketOptions,
+    progress: impl Fn(usize, usize) + Sync,
+) -> Result<BucketReport> {
+    let mut buckets = if options.buckets.is_empty() {
+        bucket_resolutions(options)
+    } else {
+        options.buckets.clone()
+    };
+    buckets.retain(|b| b.width > 0 && b.height > 0);
+    if buckets.is_empty() {
+        bail!("No buckets to assign images to");
+    }
+    buckets.sort_by(|a, b| {
+        (b.width as u64 * a.height as u64).cmp(&(a.width as u64 * b.height as u64))
+    });
+
+    let images: Vec<&FileMeta> = files
+        .iter()
+        .filter(|f| f.media_type == MediaType::Image)
+        .collect();
+    let done = AtomicUsize::new(0);
+    // The decode pass is the slow part, so it runs on every core
+    let measured: Vec<Measured> = images
+        .par_iter()
+        .map(|file| {
+            // The indexer records the detected format as `file_type`
+            let supported = Format::from_extension(&file.file_type)
+                .is_none_or(|format| format.capabilities().thumbnail);
+            let decoded = (options.verify_decode && supported).then(|| {
+                let source = Path::new(&file.path);
+                formats::detect(source)
+                    .and_then(|format| thumbnail::decode_image(source, format).ok())
+                    .map(|img| img.dimensions())
+            });
+            progress(done.fetch_add(1, Ordering::Relaxed) + 1, images.len());
+            // Stored sizes win: a RAW decodes to its smaller embedded preview
+            match (decoded, &file.dimensions) {
+                (Some(None), _) => Measured::Corrupt,
+                (_, Some(d)) if d.width > 0 && d.height > 0 => Measured::Size(d.clone()),
+                (Some(Some((width, height))), _) => Measured::Size(Dimensions { width, height }),
+                (None, _) if !supported => Measured::Unsupported,
+                (None, _) => Measured::Unknown,
+            }
+        })
+        .collect();
+
+    let mut report = BucketReport::default();
+    let mut counts = vec![0; buckets.len()];
+    for (file, measured) in images.into_iter().zip(measured) {
+        let size = match measured {
+            Measured::Size(size) => size,
+            Measured::Corrupt => {
+                report.corrupt.push(file.path.clone());
+                continue;
+            }
+            Measured::Unsupported => {
+                report.unsupported.push(file.path.clone());
+                continue;
+            }
+            Measured::Unknown => {
+                report.unmeasured.push(file.path.clone());
+                continue;
+            }
+        };
+        let (short, long) = (size.width.min(size.height), size.width.max(size.height));
+        if short < options.min_resolution {
+            report.too_small.push(file.path.clone());
+        }
+        if long as f64 / short as f64 > options.max_aspect_ratio {
+            report.extreme_aspect.push(file.path.clone());
+        }
+        // Closest aspect ratio, compared as kohya-ss does
+        let ratio = size.width as f64 / size.height as f64;
+        let distance = |b: &Dimensions| (b.width as f64 / b.height as f64 - ratio).abs();
+        let (index, bucket) = buckets
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+            .expect("buckets is not empty");
+        counts[index] += 1;
+        report.assignments.push(BucketAssignment {
+            file_id: file.id.clone(),
+            path: file.path.clone(),
+            dimensions: size,
+            bucket: bucket.clone(),
+        });
+    }
+    report.buckets = buckets
+        .into_iter()
+        .zip(counts)
+        .map(|(bucket, count)| BucketCount { bucket, count })
+        .collect();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(path: &str, size: Option<(u32, u32)>) -> FileMeta {
+        FileMeta {
+            id: path.to_string(),
+            path: path.to_string(),
+            file_type: Path::new(path)
+                .extension()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            media_type: MediaType::Image,
+            dimensions: size.map(|(width, height)| Dimensions { width, height }),
+            ..Default::default()
+        }
+    }
+
+    /// A folder of test images, removed on drop.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("lumous-dataset-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        /// Write a `width`x`height` PNG at `name` and return it as indexed.
+        fn png(&self, name: &str, (width, height): (u32, u32)) -> FileMeta {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]))
+                .save(&path)
+                .unwrap();
+            image(&path.to_string_lossy(), Some((width, height)))
+        }
+
+        fn write(&self, name: &str, contents: &str) {
+            fs::write(self.0.join(name), contents).unwrap();
+        }
+
+        fn options(&self, layout: DatasetLayout) -> DatasetExportOptions {
+            DatasetExportOptions {
+                layout,
+                output_dir: self.0.join("out").to_string_lossy().into_owned(),
+                resolution: None,
+                resize: ResizeMode::Fit,
+                format: None,
+                validation_split: 0.0,
+                repeats: None,
+                concept: None,
+            }
+        }
+
+        fn read(&self, name: &str) -> String {
+            fs::read_to_string(self.0.join("out").join(name)).unwrap()
+        }
+
+        fn size_of(&self, name: &str) -> (u32, u32) {
+            image::image_dimensions(self.0.join("out").join(name)).unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn sizes(buckets: &[Dimensions]) -> Vec<(u32, u32)> {
+        buckets.iter().map(|b| (b.width, b.height)).collect()
+    }
+
+    fn bucket_of(report: &BucketReport, path: &str) -> (u32, u32) {
+        let assignment = report.assignments.iter().find(|a| a.path == path).unwrap();
+        (assignment.bucket.width, assignment.bucket.height)
+    }
+
+    #[test]
+    fn default_buckets_match_kohya() {
+        let options = BucketOptions::default();
+        let buckets = sizes(&bucket_resolutions(&options));
+        assert_eq!(buckets.first(), Some(&(2048, 256)));
+        assert_eq!(buckets.last(), Some(&(256, 2048)));
+        for size in [
+            (1024, 1024),
+            (1088, 960),
+            (960, 1088),
+            (1344, 768),
+            (768, 1344),
+        ] {
+            assert!(buckets.contains(&size), "{size:?} missing");
+        }
+        for &(width, height) in &buckets {
+            assert_eq!((width % 64, height % 64), (0, 0));
+            assert!(width * height <= 1024 * 1024, "{width}x{height}");
+            assert!((256..=2048).contains(&width) && (256..=2048).contains(&height));
+            assert!(buckets.contains(&(height, width)));
+        }
+        let ratios: Vec<f64> = buckets.iter().map(|&(w, h)| w as f64 / h as f64).collect();
+        assert!(ratios.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn bucket_resolutions_respect_the_limits() {
+        let options = BucketOptions {
+            resolution: 512,
+            min_side: 384,
+            max_side: 640,
+            step: 128,
+            ..Default::default()
+        };
+        assert_eq!(
+            sizes(&bucket_resolutions(&options)),
+            [(640, 384), (512, 512), (384, 640)]
+        );
+    }
+
+    #[test]
+    fn images_go_to_the_closest_aspect_ratio() {
+        let options = BucketOptions {
+            buckets: [(768, 1344), (1024, 1024), (1344, 768)]
+                .map(|(width, height)| Dimensions { width, height })
+                .to_vec(),
+            verify_decode: false,
+            ..Default::default()
+        };
+        let files = [
+            image("/d/square.jpg", Some((2000, 2000))),
+            image("/d/wide.jpg", Some((1600, 900))),
+            image("/d/small.jpg", Some((400, 800))),
+            image("/d/panorama.jpg", Some((4000, 1000))),
+            image("/d/unknown.jpg", None),
+        ];
+        let report = check(&files, &options, |_, _| {}).unwrap();
+
+        assert_eq!(bucket_of(&report, "/d/square.jpg"), (1024, 1024));
+        assert_eq!(bucket_of(&report, "/d/wide.jpg"), (1344, 768));
+        assert_eq!(bucket_of(&report, "/d/small.jpg"), (768, 1344));
+        assert_eq!(bucket_of(&report, "/d/panorama.jpg"), (1344, 768));
+        // Widest first, whatever order they were given in
+        let counts: Vec<_> = report
+            .buckets
+            .iter()
+            .map(|b| (b.bucket.width, b.bucket.height, b.count))
+            .collect();
+        assert_eq!(counts, [(1344, 768, 2), (1024, 1024, 1), (768, 1344, 1)]);
+        assert_eq!(report.too_small, ["/d/small.jpg"]);
+        assert_eq!(report.extreme_aspect, ["/d/panorama.jpg"]);
+        assert_eq!(report.unmeasured, ["/d/unknown.jpg"]);
+        assert!(report.corrupt.is_empty());
+    }
+
+    #[cfg(not(feature = "heif"))]
+    #[test]
+    fn undecodable_formats_are_not_corrupt() {
+        let files = [
+            image("/missing/broken.jpg", None),
+            image("/missing/phone.heic", None),
+            image("/missing/sized.avif", Some((1024, 1024))),
+        ];
+        let report = check(&files, &BucketOptions::default(), |_, _| {}).unwrap();
+
+        assert_eq!(report.corrupt, ["/missing/broken.jpg"]);
+        assert_eq!(report.unsupported, ["/missing/phone.heic"]);
+        assert_eq!(bucket_of(&report, "/missing/sized.avif"), (1024, 1024));
+    }
+
+    #[test]
+    fn kohya_export_copies_images_with_their_captions() {
+        let dir = TempDir::new();
+        let mut files = vec![
+            dir.png("a.png", (40, 30)),
+            dir.png("nested/A.png", (20, 10)),
+            dir.png("b.png", (16, 16)),
+            FileMeta {
+                media_type: MediaType::Video,
+                ..image(&dir.0.join("clip.mp4").to_string_lossy(), None)
+            },
+        ];
+        dir.write("a.txt", " ohwx, man \n");
+        dir.write("nested/A.txt", "ohwx, woman");
+        // The XMP description stands in for a missing caption
+        files[2].description = Some("ohwx, dog".into());
+        let options = DatasetExportOptions {
+            repeats: Some(10),
+            concept: Some("ohwx".into()),
+            ..dir.options(DatasetLayout::Kohya)
+        };
+        let mut calls = Vec::new();
+        let summary = export(&files, &options, |done, total| calls.push((done, total))).unwrap();
+
+        assert_eq!((summary.train, summary.validation), (3, 0));
+        assert_eq!(summary.skipped, [files[3].path.clone()]);
+        assert_eq!(calls, [(1, 3), (2, 3), (3, 3)]);
+        // Copied byte for byte, the clashing stem numbered
+        let folder = "train/10_ohwx";
+        let source = fs::read(&files[2].path).unwrap();
+        assert_eq!(
+            fs::read(dir.0.join("out").join(folder).join("b.png")).unwrap(),
+            source
+        );
+        assert_eq!(dir.read(&format!("{folder}/b.txt")), "ohwx, dog");
+        let mut names: Vec<String> = fs::read_dir(dir.0.join("out").join(folder))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".png") && name != "b.png")
+            .collect();
+        names.sort_by_key(|name| name.to_lowercase());
+        assert!(names[0].eq_ignore_ascii_case("a.png") && names[1].eq_ignore_ascii_case("a_2.png"));
+        let mut stems = Vec::new();
+        for name in &names {
+            let stem = name.trim_end_matches(".png");
+            let caption = dir.read(&format!("{folder}/{stem}.txt"));
+            stems.push((caption, dir.size_of(&format!("{folder}/{name}"))));
+        }
+        stems.sort();
+        assert_eq!(
+            stems,
+            [
+                ("ohwx, man".to_string(), (40, 30)),
+                ("ohwx, woman".to_string(), (20, 10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn imagefolder_export_merges_the_json_sidecar() {
+        let dir = TempDir::new();
+        let files = [
+            dir.png("wide.png", (64, 48)),
+            dir.png("small.png", (16, 16)),
+        ];
+        dir.write("wide.txt", "a wide image");
+        dir.write("wide.json", r#"{"aesthetic": 6.5, "text": "overwritten"}"#);
+        let options = DatasetExportOptions {
+            resolution: Some(Dimensions {
+                width: 32,
+                height: 32,
+            }),
+            format: Some(ExportFormat::Jpeg),
+            ..dir.options(DatasetLayout::ImageFolder)
+        };
+        export(&files, &options, |_, _| {}).unwrap();
+
+        let mut rows: Vec<Value> = dir
+            .read("train/metadata.jsonl")
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        rows.sort_by_key(|row| row["file_name"].as_str().unwrap().to_string());
+        assert_eq!(
+            rows,
+            [
+                json!({ "file_name": "small.jpg", "text": "" }),
+                json!({ "file_name": "wide.jpg", "text": "a wide image", "aesthetic": 6.5 }),
+            ]
+        );
+        // Re-encoded, and Fit scales down but never up
+        let wide = dir.0.join("out/train/wide.jpg");
+        assert_eq!(formats::detect(&wide), Some(Format::Jpeg));
+        assert_eq!(dir.size_of("train/wide.jpg"), (32, 24));
+        assert_eq!(dir.size_of("train/small.jpg"), (16, 16));
+    }
+
+    #[test]
+    fn coco_export_splits_the_same_way_every_time() {
+        let dir = TempDir::new();
+        let files: Vec<FileMeta> = (0..4)
+            .map(|i| dir.png(&format!("{i}.png"), (40 + i, 20)))
+            .collect();
+        dir.write("0.txt", "zero");
+        dir.write("2.txt", "two");
+        let options = DatasetExportOptions {
+            resolution: Some(Dimensions {
+                width: 16,
+                height: 16,
+            }),
+            resize: ResizeMode::Crop,
+            validation_split: 0.5,
+            ..dir.options(DatasetLayout::Coco)
+        };
+        let summary = export(&files, &options, |_, _| {}).unwrap();
+        assert_eq!((summary.train, summary.validation), (2, 2));
+
+        let mut names = Vec::new();
+        let mut captions = Vec::new();
+        for split in ["train", "validation"] {
+            let index: Value =
+                serde_json::from_str(&dir.read(&format!("annotations/captions_{split}.json")))
+                    .unwrap();
+            let images = index["images"].as_array().unwrap();
+            for (i, image) in images.iter().enumerate() {
+                assert_eq!(image["id"], json!(i + 1));
+                assert_eq!(
+                    (image["width"].clone(), image["height"].clone()),
+                    (json!(16), json!(16))
+                );
+                let name = image["file_name"].as_str().unwrap();
+                assert_eq!(dir.size_of(&format!("{split}/{name}")), (16, 16));
+                names.push(format!("{split}/{name}"));
+            }
+            // Numbered on their own, pointing at the captioned images
+            for (n, annotation) in index["annotations"].as_array().unwrap().iter().enumerate() {
+                assert_eq!(annotation["id"], json!(n + 1));
+                let image = &images[annotation["image_id"].as_u64().unwrap() as usize - 1];
+                captions.push((
+                    image["file_name"].as_str().unwrap().to_string(),
+                    annotation["caption"].as_str().unwrap().to_string(),
+                ));
+            }
+        }
+        captions.sort();
+        assert_eq!(
+            captions,
+            [
+                ("0.png".to_string(), "zero".to_string()),
+                ("2.png".to_string(), "two".to_string()),
+            ]
+        );
+
+        // Neither the input order nor the output folder changes the split
+        let reversed: Vec<FileMeta> = files.iter().rev().cloned().collect();
+        let again = DatasetExportOptions {
+            output_dir: dir.0.join("again").to_string_lossy().into_owned(),
+            ..options
+        };
+        export(&reversed, &again, |_, _| {}).unwrap();
+        for name in &names {
+            assert!(dir.0.join("again").join(name).is_file(), "{name} moved");
+        }
+    }
+
+    #[test]
+    fn export_rejects_bad_options() {
+        let dir = TempDir::new();
+        let files = [dir.png("a.png", (8, 8))];
+        let split = DatasetExportOptions {
+            validation_split: 1.5,
+            ..dir.options(DatasetLayout::Kohya)
+        };
+        assert!(export(&files, &split, |_, _| {}).is_err());
+        let empty = DatasetExportOptions {
+            resolution: Some(Dimensions {
+                width: 0,
+                height: 512,
+            }),
+            ..dir.options(DatasetLayout::Kohya)
+        };
+        assert!(export(&files, &empty, |_, _| {}).is_err());
+        assert!(!dir.0.join("out").exists());
+    }
+}
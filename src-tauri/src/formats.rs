@@ -0,0 +1,445 @@
+use crate::isobmff;
+use crate::models::MediaType;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Every media format the library knows about. The indexer, the thumbnailer
+/// and the folder watcher all consult this registry so they agree on what
+/// counts as a supported file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    Png,
+    Gif,
+    Bmp,
+    WebP,
+    Tiff,
+    Ico,
+    Heif,
+    Avif,
+    /// Camera RAW; thumbnails come from the embedded preview JPEG.
+    Raw,
+    /// MPEG-4 video (including 3GPP); thumbnails come from a poster frame.
+    Mp4,
+    QuickTime,
+}
+
+/// What the available decoders can do with a format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Width and height can be read from the header without a full decode.
+    pub dimensions: bool,
+    /// A full decode is available, so thumbnails can be generated.
+    pub thumbnail: bool,
+    /// The container can hold more than one frame.
+    pub animation: bool,
+}
+
+struct FormatSpec {
+    format: Format,
+    /// Lowercase extensions; the first one is the canonical `file_type`.
+    extensions: &'static [&'static str],
+    capabilities: Capabilities,
+}
+
+const STILL: Capabilities = Capabilities {
+    dimensions: true,
+    thumbnail: true,
+    animation: false,
+};
+
+const ANIMATED: Capabilities = Capabilities {
+    dimensions: true,
+    thumbnail: true,
+    animation: true,
+};
+
+/// HEIF and AVIF headers are always readable; decoding needs the optional
+/// `heif` feature (libheif).
+const HEIF: Capabilities = Capabilities {
+    dimensions: true,
+    thumbnail: cfg!(feature = "heif"),
+    animation: false,
+};
+
+/// Size and cover art are read from the container; other poster frames
+/// need `ffmpeg` at runtime.
+const VIDEO: Capabilities = Capabilities {
+    dimensions: true,
+    thumbnail: true,
+    animation: false,
+};
+
+const FORMATS: &[FormatSpec] = &[
+    FormatSpec {
+        format: Format::Jpeg,
+        extensions: &["jpg", "jpeg", "jpe", "jfif"],
+        capabilities: STILL,
+    },
+    FormatSpec {
+        format: Format::Png,
+        extensions: &["png"],
+        capabilities: STILL,
+    },
+    FormatSpec {
+        format: Format::Gif,
+        extensions: &["gif"],
+        capabilities: ANIMATED,
+    },
+    FormatSpec {
+        format: Format::Bmp,
+        extensions: &["bmp"],
+        capabilities: STILL,
+    },
+    FormatSpec {
+        format: Format::WebP,
+        extensions: &["webp"],
+        capabilities: ANIMATED,
+    },
+    FormatSpec {
+        format: Format::Tiff,
+        extensions: &["tiff", "tif"],
+        capabilities: STILL,
+    },
+    FormatSpec {
+        format: Format::Ico,
+        extensions: &["ico"],
+        capabilities: STILL,
+    },
+    FormatSpec {
+        format: Format::Heif,
+        extensions: &["heic", "heif", "hif"],
+        capabilities: HEIF,
+    },
+    FormatSpec {
+        format: Format::Avif,
+        extensions: &["avif"],
+        capabilities: HEIF,
+    },
+    FormatSpec {
+        format: Format::Raw,
+        extensions: &["dng", "cr2", "cr3", "nef", "nrw", "arw", "pef", "srw"],
+        capabilities: STILL,
+    },
+    FormatSpec {
+        format: Format::Mp4,
+        extensions: &["mp4", "m4v", "3gp", "3g2"],
+        capabilities: VIDEO,
+    },
+    FormatSpec {
+        format: Format::QuickTime,
+        extensions: &["mov", "qt"],
+        capabilities: VIDEO,
+    },
+];
+
+/// Number of leading bytes needed to recognise every format in the registry;
+/// ISOBMFF files need room for a few compatible brands after the major one.
+const SNIFF_LEN: usize = 32;
+
+const HEIF_BRANDS: &[&[u8; 4]] = &[
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1", b"mif2",
+];
+const AVIF_BRANDS: &[&[u8; 4]] = &[b"avif", b"avis"];
+const MP4_BRANDS: &[&[u8; 4]] = &[
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"M4VH",
+    b"M4VP", b"3gp4", b"3gp5", b"3gp6", b"3g2a", b"mmp4", b"MSNV", b"XAVC",
+];
+/// MP4 audio/audiobook files share the video brands as compatible brands.
+const AUDIO_BRANDS: &[&[u8; 4]] = &[b"M4A ", b"M4B ", b"M4P ", b"F4A "];
+
+/// Sidecars and text files kept next to images. They are never sniffed: a
+/// caption starting with "BMW" would otherwise pass for a BMP.
+const NEVER_SNIFFED: &[&str] = &["txt", "md", "json", "xmp", "tmp", "bak"];
+
+/// Sizes of the BMP info header versions, from `BITMAPCOREHEADER` to
+/// `BITMAPV5HEADER`.
+const BMP_DIB_SIZES: &[u32] = &[12, 16, 40, 52, 56, 64, 108, 124];
+
+impl Format {
+    fn spec(self) -> &'static FormatSpec {
+        FORMATS
+            .iter()
+            .find(|s| s.format == self)
+            .expect("every format has a registry entry")
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Format> {
+        let ext = ext.to_lowercase();
+        FORMATS
+            .iter()
+            .find(|s| s.extensions.contains(&ext.as_str()))
+            .map(|s| s.format)
+    }
+
+    /// Identify a format from the first bytes of a file.
+    pub fn from_magic(header: &[u8]) -> Option<Format> {
+        match header {
+            [0xFF, 0xD8, 0xFF, ..] => Some(Format::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(Format::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Format::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Format::WebP),
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Some(Format::Tiff),
+            [0x00, 0x00, 0x01, 0x00, ..] if is_ico(header) => Some(Format::Ico),
+            [b'B', b'M', ..] if is_bmp(header) => Some(Format::Bmp),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Self::from_brands(&isobmff::brands(header)),
+            _ => None,
+        }
+    }
+
+    /// HEIF-family files share one container, so tell AVIF apart from HEIC by
+    /// the `ftyp` brands. AVIF wins since AVIF files also list `mif1`. Canon
+    /// CR3 is ISOBMFF too, under its own `crx ` brand. Videos are checked
+    /// last because HEIF sequences also list generic MP4 brands.
+    fn from_brands(brands: &[[u8; 4]]) -> Option<Format> {
+        let major = brands.first()?;
+        if major == b"crx " {
+            Some(Format::Raw)
+        } else if major == b"qt  " {
+            Some(Format::QuickTime)
+        } else if AUDIO_BRANDS.contains(&major) {
+            None
+        } else if brands.iter().any(|b| AVIF_BRANDS.contains(&b)) {
+            Some(Format::Avif)
+        } else if brands.iter().any(|b| HEIF_BRANDS.contains(&b)) {
+            Some(Format::Heif)
+        } else if brands.iter().any(|b| MP4_BRANDS.contains(&b)) {
+            Some(Format::Mp4)
+        } else {
+            None
+        }
+    }
+
+    /// The canonical extension, used as `file_type` when the file's own
+    /// extension is missing or wrong.
+    pub fn canonical_extension(self) -> &'static str {
+        self.spec().extensions[0]
+    }
+
+    pub fn capabilities(self) -> Capabilities {
+        self.spec().capabilities
+    }
+
+    pub fn media_type(self) -> MediaType {
+        match self {
+            Format::Mp4 | Format::QuickTime => MediaType::Video,
+            _ => MediaType::Image,
+        }
+    }
+}
+
+/// Cheap check used when scanning folders: trusts a known extension and only
+/// sniffs the content when the extension is missing or unknown.
+pub fn is_supported(path: &Path) -> bool {
+    match extension_of(path) {
+        Some(ext) if Format::from_extension(&ext).is_some() => true,
+        _ => sniff_file(path).is_some(),
+    }
+}
+
+/// Determine a file's format, preferring the magic bytes over the extension
+/// so mislabelled files are still decoded correctly.
+pub fn detect(path: &Path) -> Option<Format> {
+    let by_ext = extension_of(path).and_then(|e| Format::from_extension(&e));
+    match (sniff_file(path), by_ext) {
+        // Most RAWs are TIFF containers, so their magic alone says TIFF
+        (Some(Format::Tiff), Some(Format::Raw)) => Some(Format::Raw),
+        (sniffed, by_ext) => sniffed.or(by_ext),
+    }
+}
+
+/// The `file_type` to record for a file: its own extension when that matches
+/// the detected format, otherwise the format's canonical extension.
+pub fn file_type_for(path: &Path, format: Format) -> String {
+    match extension_of(path) {
+        Some(ext) if Format::from_extension(&ext) == Some(format) => ext,
+        _ => format.canonical_extension().to_string(),
+    }
+}
+
+pub fn sniff_file(path: &Path) -> Option<Format> {
+    if extension_of(path).is_some_and(|ext| NEVER_SNIFFED.contains(&ext.as_str())) {
+        return None;
+    }
+    let mut header = [0u8; SNIFF_LEN];
+    let mut file = File::open(path).ok()?;
+    let mut read = 0;
+    while read < SNIFF_LEN {
+        match file.read(&mut header[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(_) => return None,
+        }
+    }
+    Format::from_magic(&header[..read])
+}
+
+/// "BM" alone is too common a start for text, so the file header must hold
+/// together: a file size that fits the headers, a known info header size and
+/// pixel data after both headers.
+fn is_bmp(header: &[u8]) -> bool {
+    let u32_at = |i: usize| {
+        header
+            .get(i..i + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let (Some(size), Some(offset), Some(dib)) = (u32_at(2), u32_at(10), u32_at(14)) else {
+        return false;
+    };
+    BMP_DIB_SIZES.contains(&dib) && size >= 14 + dib && offset >= 14 + dib && offset <= size
+}
+
+/// An icon directory lists at least one image, each entry with a zero
+/// reserved byte.
+fn is_ico(header: &[u8]) -> bool {
+    match header {
+        [_, _, _, _, count_lo, count_hi, _, _, _, reserved, ..] => {
+            u16::from_le_bytes([*count_lo, *count_hi]) > 0 && *reserved == 0
+        }
+        _ => false,
+    }
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(brands: &[&[u8; 4]]) -> Vec<u8> {
+        let mut out = ((16 + 4 * brands.len().saturating_sub(1)) as u32)
+            .to_be_bytes()
+            .to_vec();
+        out.extend_from_slice(b"ftyp");
+        out.extend_from_slice(brands[0]);
+        out.extend_from_slice(&[0; 4]);
+        for brand in &brands[1..] {
+            out.extend_from_slice(*brand);
+        }
+        out
+    }
+
+    fn bmp(size: u32, offset: u32, dib: u32) -> Vec<u8> {
+        let mut out = b"BM".to_vec();
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&dib.to_le_bytes());
+        out
+    }
+
+    fn with_file<T>(name: &str, bytes: &[u8], f: impl FnOnce(&Path) -> T) -> T {
+        let dir = std::env::temp_dir().join(format!("lumous-formats-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        let out = f(&path);
+        let _ = std::fs::remove_dir_all(&dir);
+        out
+    }
+
+    #[test]
+    fn recognises_each_signature() {
+        let cases: &[(&[u8], Format)] = &[
+            (&[0xFF, 0xD8, 0xFF, 0xE0], Format::Jpeg),
+            (b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR", Format::Png),
+            (b"GIF87a\x01\0\x01\0", Format::Gif),
+            (b"GIF89a\x01\0\x01\0", Format::Gif),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", Format::WebP),
+            (b"II\x2a\0\x08\0\0\0", Format::Tiff),
+            (b"MM\0\x2a\0\0\0\x08", Format::Tiff),
+            (b"\0\0\x01\0\x01\0\x10\x10\0\0\x01\0", Format::Ico),
+            (&bmp(70, 54, 40), Format::Bmp),
+            (&bmp(26, 26, 12), Format::Bmp),
+        ];
+        for (header, format) in cases {
+            assert_eq!(Format::from_magic(header), Some(*format), "{header:?}");
+        }
+    }
+
+    #[test]
+    fn tells_isobmff_files_apart_by_brand() {
+        let cases: &[(&[&[u8; 4]], Option<Format>)] = &[
+            (&[b"heic", b"mif1", b"heic"], Some(Format::Heif)),
+            (&[b"mif1", b"heic"], Some(Format::Heif)),
+            // AVIF files list mif1 too
+            (&[b"avif", b"mif1", b"miaf"], Some(Format::Avif)),
+            (&[b"mif1", b"avif"], Some(Format::Avif)),
+            (&[b"crx ", b"isom"], Some(Format::Raw)),
+            (&[b"qt  "], Some(Format::QuickTime)),
+            (&[b"isom", b"iso2", b"mp41"], Some(Format::Mp4)),
+            (&[b"3gp5", b"isom"], Some(Format::Mp4)),
+            // Audio shares the video brands
+            (&[b"M4A ", b"isom", b"mp42"], None),
+            (&[b"zzzz"], None),
+        ];
+        for (brands, format) in cases {
+            assert_eq!(Format::from_magic(&ftyp(brands)), *format, "{brands:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_text_that_starts_like_bmp_or_ico() {
+        assert_eq!(Format::from_magic(b"BMW 320i, blue, 2004"), None);
+        assert_eq!(Format::from_magic(b"BM"), None);
+        // Unknown info header size
+        assert_eq!(Format::from_magic(&bmp(70, 54, 41)), None);
+        // Pixel data before the headers end, or past the end of the file
+        assert_eq!(Format::from_magic(&bmp(70, 20, 40)), None);
+        assert_eq!(Format::from_magic(&bmp(60, 64, 40)), None);
+        // An icon directory with no images, or a set reserved byte
+        assert_eq!(Format::from_magic(b"\0\0\x01\0\0\0\x10\x10\0\0"), None);
+        assert_eq!(Format::from_magic(b"\0\0\x01\0\x01\0\x10\x10\0\x07"), None);
+        assert_eq!(Format::from_magic(b"\0\0\x01\0"), None);
+        assert_eq!(Format::from_magic(b""), None);
+    }
+
+    #[test]
+    fn never_sniffs_sidecars() {
+        let header = bmp(70, 54, 40);
+        for name in ["caption.txt", "notes.md", "a.json", "a.xmp", "a.TXT"] {
+            with_file(name, &header, |path| {
+                assert_eq!(sniff_file(path), None, "{name}");
+                assert!(!is_supported(path), "{name}");
+                assert_eq!(detect(path), None, "{name}");
+            });
+        }
+        // The same bytes without an extension are a BMP
+        with_file("image", &header, |path| {
+            assert_eq!(detect(path), Some(Format::Bmp));
+            assert!(is_supported(path));
+        });
+    }
+
+    #[test]
+    fn content_wins_over_a_wrong_extension() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        with_file("photo.jpg", png, |path| {
+            assert_eq!(detect(path), Some(Format::Png));
+            assert_eq!(file_type_for(path, Format::Png), "png");
+        });
+        with_file("photo.JPEG", &[0xFF, 0xD8, 0xFF, 0xE1], |path| {
+            assert_eq!(file_type_for(path, detect(path).unwrap()), "jpeg");
+        });
+        // Most RAWs are TIFF inside, so the extension decides
+        with_file("shot.nef", b"II\x2a\0\x08\0\0\0", |path| {
+            assert_eq!(detect(path), Some(Format::Raw));
+        });
+        with_file("scan.tif", b"II\x2a\0\x08\0\0\0", |path| {
+            assert_eq!(detect(path), Some(Format::Tiff));
+        });
+        // Unrecognised content falls back to the extension
+        with_file("empty.webp", b"", |path| {
+            assert_eq!(detect(path), Some(Format::WebP));
+        });
+        with_file("notes.bin", b"hello", |path| {
+            assert_eq!(detect(path), None);
+            assert!(!is_supported(path));
+        });
+    }
+}
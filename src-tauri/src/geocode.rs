@@ -0,0 +1,128 @@
+//! Offline reverse geocoding: GPS coordinates to the nearest bundled place.
+
+use crate::models::Place;
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+/// Bundled places; see the header of the file for the column layout.
+const PLACES: &str = include_str!("../data/cities.tsv");
+
+/// `kv_store` key holding the hash of the places last loaded.
+const PLACES_HASH_KEY: &str = "geo_places_sha256";
+
+/// Photos further than this from every bundled place get no place.
+pub const MAX_PLACE_DISTANCE_KM: f64 = 100.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const KM_PER_DEGREE_LATITUDE: f64 = 111.0;
+
+/// Reload `geo_places` when the bundled places differ from the ones last
+/// loaded, so a build with new data refreshes the table without a schema
+/// migration. Folder snapshots are cleared too, so the next index pass
+/// assigns files their places from the new data. Returns whether it
+/// reloaded.
+pub fn sync_places(conn: &Connection) -> Result<bool> {
+    let hash = format!("{:x}", Sha256::digest(PLACES.as_bytes()));
+    let loaded: Option<String> = conn
+        .query_row(
+            "SELECT value FROM kv_store WHERE key = ?1",
+            params![PLACES_HASH_KEY],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if loaded.as_deref() == Some(hash.as_str()) {
+        return Ok(false);
+    }
+    let tx = conn.unchecked_transaction()?;
+    load_places(&tx)?;
+    tx.execute("DELETE FROM folder_snapshots", [])?;
+    tx.execute(
+        "INSERT INTO kv_store (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value=excluded.value",
+        params![PLACES_HASH_KEY, hash],
+    )?;
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Replace the contents of `geo_places` with the bundled places. Returns the
+/// number loaded; malformed lines are skipped.
+fn load_places(conn: &Connection) -> Result<usize> {
+    conn.execute("DELETE FROM geo_places", [])?;
+    let mut insert = conn.prepare(
+        "INSERT INTO geo_places (name, region, country_code, country, latitude, longitude, population)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    let mut loaded = 0;
+    for line in PLACES
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [name, region, country_code, country, lat, lon, population] = fields[..] else {
+            continue;
+        };
+        let (Ok(lat), Ok(lon)) = (lat.parse::<f64>(), lon.parse::<f64>()) else {
+            continue;
+        };
+        insert.execute(params![
+            name,
+            region,
+            country_code,
+            country,
+            lat,
+            lon,
+            population.parse::<i64>().unwrap_or_default()
+        ])?;
+        loaded += 1;
+    }
+    Ok(loaded)
+}
+
+/// The closest place to a coordinate, if one lies within
+/// [`MAX_PLACE_DISTANCE_KM`].
+pub fn nearest_place(conn: &Connection, latitude: f64, longitude: f64) -> Result<Option<Place>> {
+    // Narrow to a latitude band through the index, then measure exactly;
+    // longitude degrees shrink toward the poles so they can't be banded as simply
+    let band = MAX_PLACE_DISTANCE_KM / KM_PER_DEGREE_LATITUDE;
+    let mut stmt = conn.prepare_cached(
+        "SELECT name, region, country_code, country, latitude, longitude
+         FROM geo_places WHERE latitude BETWEEN ?1 AND ?2",
+    )?;
+    let rows = stmt.query_map(params![latitude - band, latitude + band], |row| {
+        Ok((
+            Place {
+                city: row.get(0)?,
+                region: row.get(1)?,
+                country_code: row.get(2)?,
+                country: row.get(3)?,
+            },
+            row.get::<_, f64>(4)?,
+            row.get::<_, f64>(5)?,
+        ))
+    })?;
+
+    let mut nearest: Option<(f64, Place)> = None;
+    for row in rows {
+        let (place, lat, lon) = row?;
+        let distance = distance_km(latitude, longitude, lat, lon);
+        let closer = match &nearest {
+            Some((best, _)) => distance < *best,
+            None => true,
+        };
+        if distance <= MAX_PLACE_DISTANCE_KM && closer {
+            nearest = Some((distance, place));
+        }
+    }
+    Ok(nearest.map(|(_, place)| place))
+}
+
+/// Great-circle distance between two coordinates.
+pub fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
@@ -1,14 +1,12 @@
 // Added FolderSnapshot to imports
-use crate::models::{FileMeta, Dimensions, FolderSnapshot};
+use crate::models::{AnimationMetadata, FileMeta, Dimensions, FolderSnapshot, ImageMetadata, MediaType, VideoMetadata};
+use crate::formats::{self, Format};
+use crate::{animation, isobmff, keywords, metadata, raw, sidecar, video, xmp};
 use std::path::Path;
 use std::fs;
 use walkdir::WalkDir;
 use uuid::Uuid;
 
-const SUPPORTED_EXTENSIONS: &[&str] = &[
-    "jpg", "jpeg", "png", "gif", "bmp", "webp", "tiff", "tif", "ico"
-];
-
 pub async fn scan_directory(root: &Path, _recursive: bool) -> Result<Vec<FileMeta>, Box<dyn std::error::Error + Send + Sync>> {
     let mut files = Vec::new();
 
@@ -43,16 +41,15 @@ pub async fn scan_directory_shallow(root: &Path, _recursive: bool) -> Result<Vec
     for entry in walker.filter_map(|e| e.ok()) {
         if entry.file_type().is_file() {
             let path = entry.path();
+            if !formats::is_supported(path) {
+                continue;
+            }
             let extension = path
                 .extension()
                 .and_then(|ext| ext.to_str())
                 .unwrap_or("")
                 .to_lowercase();
 
-            if !SUPPORTED_EXTENSIONS.contains(&extension.as_str()) {
-                continue;
-            }
-
             let metadata = fs::metadata(path)?;
             let file_name = path
                 .file_name()
@@ -90,14 +87,10 @@ pub async fn scan_directory_shallow(root: &Path, _recursive: bool) -> Result<Vec
 }
 
 pub async fn process_file(path: &Path) -> Result<Option<FileMeta>, Box<dyn std::error::Error + Send + Sync>> {
-    let extension = path.extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("")
-        .to_lowercase();
-
-    if !SUPPORTED_EXTENSIONS.contains(&extension.as_str()) {
-        return Ok(None);
-    }
+    let format = match formats::detect(path) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
 
     let metadata = fs::metadata(path)?;
     let file_name = path.file_name()
@@ -105,12 +98,35 @@ pub async fn process_file(path: &Path) -> Result<Option<FileMeta>, Box<dyn std::
         .unwrap_or("unknown")
         .to_string();
 
-    // Ensure you have the `image` crate in Cargo.toml for this to work
-    let dimensions = match image::image_dimensions(path) {
-        Ok((w, h)) => Some(Dimensions { width: w as u32, height: h as u32 }),
-        Err(_) => None,
+    // Videos get size, duration and codec from one pass over the container.
+    // A file with no video track (audio-only MP4) is not a library item; an
+    // unreadable container (e.g. still being copied) is indexed without details.
+    let video_info = match format.media_type() {
+        MediaType::Video => match video::probe(path) {
+            Ok(Some(info)) => Some(info),
+            Ok(None) => return Ok(None),
+            Err(_) => Some(video::VideoInfo::default()),
+        },
+        MediaType::Image => None,
+    };
+
+    // Frame count and duration come from the block structure, no decode needed
+    let animation_info = if format.capabilities().animation {
+        animation::probe(path, format).ok().flatten()
+    } else {
+        None
     };
 
+    // Ensure you have the `image` crate in Cargo.toml for this to work
+    let dimensions = if let Some(info) = &video_info {
+        info.dimensions
+    } else if format.capabilities().dimensions {
+        read_dimensions(path, format)
+    } else {
+        None
+    }
+    .map(|(w, h)| Dimensions { width: w, height: h });
+
     let modified = metadata.modified()
         .map(|time| {
             let datetime: chrono::DateTime<chrono::Utc> = time.into();
@@ -125,6 +141,19 @@ pub async fn process_file(path: &Path) -> Result<Option<FileMeta>, Box<dyn std::
         })
         .unwrap_or_else(|_| chrono::Utc::now().to_rfc3339());
 
+    // Lightroom and darktable keep ratings and keywords in an XMP sidecar;
+    // embedded keywords come first, the sidecar's are added
+    let xmp_sidecar = xmp::read(path).unwrap_or_default();
+    let mut tags = match format.media_type() {
+        MediaType::Image => keywords::read(path),
+        MediaType::Video => vec![],
+    };
+    for keyword in xmp_sidecar.keywords {
+        if !tags.contains(&keyword) {
+            tags.push(keyword);
+        }
+    }
+
     Ok(Some(FileMeta {
         id: Uuid::new_v4().to_string(),
         path: path.to_string_lossy().to_string(),
@@ -132,16 +161,53 @@ pub async fn process_file(path: &Path) -> Result<Option<FileMeta>, Box<dyn std::
         size: metadata.len() as i64,
         modified,
         created,
-        file_type: extension,
+        file_type: formats::file_type_for(path, format),
+        media_type: format.media_type(),
         dimensions,
         thumbnail_path: None,
-        tags: vec![],
+        // Imported as tags when the file is stored
+        tags,
         albums: vec![],
-        rating: None,
-        metadata: None,
+        rating: xmp_sidecar.rating,
+        metadata: match (&video_info, format) {
+            // The container's creation date stands in for EXIF DateTimeOriginal
+            (Some(info), _) => info.creation_date.clone().map(|date_taken| ImageMetadata {
+                date_taken: Some(date_taken),
+                ..Default::default()
+            }),
+            (None, Format::Raw) => raw::read_metadata(path),
+            (None, _) => metadata::read_exif_metadata(path),
+        },
+        variants: vec![],
+        video: video_info.map(|info| VideoMetadata {
+            duration_ms: info.duration_ms,
+            codec: info.codec,
+        }),
+        is_animated: animation_info.is_some(),
+        animation: animation_info.map(|info| AnimationMetadata {
+            frame_count: info.frame_count,
+            duration_ms: info.duration_ms,
+        }),
+        // Resolved from the GPS position when the file is stored
+        place: None,
+        labels: xmp_sidecar.labels,
+        description: xmp_sidecar.description,
+        caption: sidecar::read_caption(path),
     }))
 }
 
+fn read_dimensions(path: &Path, format: Format) -> Option<(u32, u32)> {
+    match format {
+        // The image crate can't parse HEIF containers; read the `ispe` box instead
+        Format::Heif | Format::Avif => isobmff::heif_dimensions(path).ok().flatten(),
+        Format::Raw => raw::dimensions(path),
+        _ => image::ImageReader::open(path)
+            .and_then(|r| r.with_guessed_format())
+            .ok()
+            .and_then(|r| r.into_dimensions().ok()),
+    }
+}
+
 // CHANGED: Return type is now crate::models::FolderSnapshot
 pub async fn compute_folder_snapshot(root: &Path) -> Result<crate::models::FolderSnapshot, Box<dyn std::error::Error + Send + Sync>> {
     let shallow = scan_directory_shallow(root, false).await?;
@@ -0,0 +1,386 @@
+//! Minimal reader for ISO base media file format boxes (HEIF/AVIF, MP4,
+//! QuickTime). Only walks the box tree; callers pull the fields they need.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoxHeader {
+    pub kind: [u8; 4],
+    /// Absolute offset of the box payload (after the header).
+    pub start: u64,
+    /// Absolute offset one past the end of the box.
+    pub end: u64,
+}
+
+impl BoxHeader {
+    pub fn is(&self, kind: &[u8; 4]) -> bool {
+        &self.kind == kind
+    }
+}
+
+/// Read the headers of all boxes laid out back to back in `[start, end)`.
+pub fn read_boxes<R: Read + Seek>(r: &mut R, start: u64, end: u64) -> io::Result<Vec<BoxHeader>> {
+    let mut boxes = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end {
+        r.seek(SeekFrom::Start(pos))?;
+        let size = read_u32(r)? as u64;
+        let mut kind = [0u8; 4];
+        r.read_exact(&mut kind)?;
+        let (header_len, size) = match size {
+            0 => (8, end - pos),
+            1 => (16, read_u64(r)?),
+            n => (8, n),
+        };
+        // A 64-bit size comes straight from the file; stop rather than
+        // overflow or step backwards on a corrupt one
+        let next = match pos.checked_add(size) {
+            Some(next) if size >= header_len && next > pos && next <= end => next,
+            _ => break,
+        };
+        boxes.push(BoxHeader {
+            kind,
+            start: pos + header_len,
+            end: next,
+        });
+        pos = next;
+    }
+    Ok(boxes)
+}
+
+/// Children of a container box. `skip` is 4 for full boxes (version + flags).
+pub fn children<R: Read + Seek>(
+    r: &mut R,
+    parent: &BoxHeader,
+    skip: u64,
+) -> io::Result<Vec<BoxHeader>> {
+    read_boxes(r, parent.start + skip, parent.end)
+}
+
+pub fn find<'a>(boxes: &'a [BoxHeader], kind: &[u8; 4]) -> Option<&'a BoxHeader> {
+    boxes.iter().find(|b| b.is(kind))
+}
+
+/// Follow a path of plain container boxes, e.g. `[b"moov", b"trak"]`.
+pub fn find_path<R: Read + Seek>(
+    r: &mut R,
+    boxes: &[BoxHeader],
+    path: &[&[u8; 4]],
+) -> io::Result<Option<BoxHeader>> {
+    let mut current = match path.first().and_then(|k| find(boxes, k)) {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+    for kind in &path[1..] {
+        let kids = children(r, &current, 0)?;
+        current = match find(&kids, kind) {
+            Some(b) => *b,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(current))
+}
+
+/// Width and height from the first visual sample entry of a `trak`.
+pub fn track_sample_size<R: Read + Seek>(
+    r: &mut R,
+    trak: &BoxHeader,
+) -> io::Result<Option<(u32, u32)>> {
+    let kids = children(r, trak, 0)?;
+    let stsd = match find_path(r, &kids, &[b"mdia", b"minf", b"stbl", b"stsd"])? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    // stsd is a full box followed by a 4-byte entry count
+    let entries = read_boxes(r, stsd.start + 8, stsd.end)?;
+    let entry = match entries.first() {
+        Some(e) => *e,
+        None => return Ok(None),
+    };
+    // VisualSampleEntry: 6 reserved, data_reference_index, 16 bytes of
+    // pre_defined/reserved, then width and height.
+    if entry.end < entry.start + 28 {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::Start(entry.start + 24))?;
+    let width = read_u16(r)? as u32;
+    let height = read_u16(r)? as u32;
+    if width == 0 || height == 0 {
+        return Ok(None);
+    }
+    Ok(Some((width, height)))
+}
+
+/// Open a file and list its top-level boxes.
+pub fn open(path: &Path) -> io::Result<(BufReader<File>, Vec<BoxHeader>)> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut r = BufReader::new(file);
+    let boxes = read_boxes(&mut r, 0, len)?;
+    Ok((r, boxes))
+}
+
+/// Major and compatible brands from the `ftyp` box at the start of a file.
+pub fn brands(header: &[u8]) -> Vec<[u8; 4]> {
+    if header.len() < 12 || &header[4..8] != b"ftyp" {
+        return Vec::new();
+    }
+    let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let end = size.min(header.len());
+    let mut out = vec![header[8..12].try_into().unwrap()];
+    // Compatible brands follow the 4-byte minor version.
+    let mut pos = 16;
+    while pos + 4 <= end {
+        out.push(header[pos..pos + 4].try_into().unwrap());
+        pos += 4;
+    }
+    out
+}
+
+/// Display size of the primary item of a HEIF/AVIF file, read from its `ispe`
+/// property and rotated by `irot`. Falls back to the largest `ispe` when the
+/// item association can't be resolved.
+pub fn heif_dimensions(path: &Path) -> io::Result<Option<(u32, u32)>> {
+    let (mut r, top) = open(path)?;
+    let meta = match find(&top, b"meta") {
+        Some(m) => *m,
+        None => return Ok(None),
+    };
+    let meta_kids = children(&mut r, &meta, 4)?;
+
+    let primary = match find(&meta_kids, b"pitm") {
+        Some(pitm) => {
+            r.seek(SeekFrom::Start(pitm.start))?;
+            let version = read_u32(&mut r)? >> 24;
+            Some(if version == 0 {
+                read_u16(&mut r)? as u32
+            } else {
+                read_u32(&mut r)?
+            })
+        }
+        None => None,
+    };
+
+    let iprp = match find(&meta_kids, b"iprp") {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+    let iprp_kids = children(&mut r, &iprp, 0)?;
+    let ipco = match find(&iprp_kids, b"ipco") {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+    let properties = children(&mut r, &ipco, 0)?;
+
+    // Property indices associated with the primary item (1-based into ipco).
+    let mut associated: Vec<usize> = Vec::new();
+    if let (Some(primary), Some(ipma)) = (primary, find(&iprp_kids, b"ipma")) {
+        r.seek(SeekFrom::Start(ipma.start))?;
+        let version_flags = read_u32(&mut r)?;
+        let version = version_flags >> 24;
+        let wide_index = version_flags & 1 == 1;
+        let entries = read_u32(&mut r)?;
+        for _ in 0..entries {
+            let item = if version < 1 {
+                read_u16(&mut r)? as u32
+            } else {
+                read_u32(&mut r)?
+            };
+            let mut count = [0u8; 1];
+            r.read_exact(&mut count)?;
+            for _ in 0..count[0] {
+                let index = if wide_index {
+                    (read_u16(&mut r)? & 0x7FFF) as usize
+                } else {
+                    let mut b = [0u8; 1];
+                    r.read_exact(&mut b)?;
+                    (b[0] & 0x7F) as usize
+                };
+                if item == primary {
+                    associated.push(index);
+                }
+            }
+            if item == primary {
+                break;
+            }
+        }
+    }
+
+    let mut primary_size = None;
+    let mut largest: Option<(u32, u32)> = None;
+    let mut quarter_turns = 0u8;
+    for (i, prop) in properties.iter().enumerate() {
+        let is_primary = associated.contains(&(i + 1));
+        if prop.is(b"ispe") {
+            r.seek(SeekFrom::Start(prop.start + 4))?;
+            let size = (read_u32(&mut r)?, read_u32(&mut r)?);
+            if is_primary {
+                primary_size = Some(size);
+            }
+            let area = |(w, h): (u32, u32)| w as u64 * h as u64;
+            if largest.is_none_or(|l| area(size) > area(l)) {
+                largest = Some(size);
+            }
+        } else if prop.is(b"irot") && is_primary {
+            r.seek(SeekFrom::Start(prop.start))?;
+            let mut b = [0u8; 1];
+            r.read_exact(&mut b)?;
+            quarter_turns = b[0] & 0x3;
+        }
+    }
+
+    Ok(primary_size.or(largest).map(|(w, h)| {
+        if quarter_turns % 2 == 1 {
+            (h, w)
+        } else {
+            (w, h)
+        }
+    }))
+}
+
+pub fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_be_bytes(b))
+}
+
+pub fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_be_bytes(b))
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_be_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn bx(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// A full box: version and flags, then the payload.
+    fn full(kind: &[u8; 4], version: u8, payload: &[u8]) -> Vec<u8> {
+        bx(kind, &[&[version, 0, 0, 0][..], payload].concat())
+    }
+
+    fn ispe(width: u32, height: u32) -> Vec<u8> {
+        full(
+            b"ispe",
+            0,
+            &[width.to_be_bytes(), height.to_be_bytes()].concat(),
+        )
+    }
+
+    /// A HEIC whose item 1 is associated with the given 1-based properties.
+    fn heic(properties: &[Vec<u8>], associated: &[u8]) -> Vec<u8> {
+        let mut ipma = vec![0, 0, 0, 1, 0, 1, associated.len() as u8];
+        ipma.extend_from_slice(associated);
+        let iprp = bx(
+            b"iprp",
+            &[bx(b"ipco", &properties.concat()), full(b"ipma", 0, &ipma)].concat(),
+        );
+        let meta = full(b"meta", 0, &[full(b"pitm", 0, &[0, 1]), iprp].concat());
+        [bx(b"ftyp", b"heic\0\0\0\0mif1heic"), meta].concat()
+    }
+
+    fn dimensions(bytes: &[u8]) -> io::Result<Option<(u32, u32)>> {
+        let path =
+            std::env::temp_dir().join(format!("lumous-isobmff-{}.heic", uuid::Uuid::new_v4()));
+        std::fs::write(&path, bytes).unwrap();
+        let size = heif_dimensions(&path);
+        let _ = std::fs::remove_file(&path);
+        size
+    }
+
+    #[test]
+    fn reads_brands_from_ftyp() {
+        let ftyp = bx(b"ftyp", b"avif\0\0\0\0mif1miaf");
+        assert_eq!(brands(&ftyp), [*b"avif", *b"mif1", *b"miaf"]);
+        // Only the brands within the sniffed bytes
+        assert_eq!(brands(&ftyp[..20]), [*b"avif", *b"mif1"]);
+        assert!(brands(&bx(b"free", b"avif\0\0\0\0")).is_empty());
+        assert!(brands(b"\0\0\0\x10ftyp").is_empty());
+    }
+
+    #[test]
+    fn walks_boxes_back_to_back() {
+        let bytes = [bx(b"free", b"abc"), bx(b"mdat", &[0; 10])].concat();
+        let boxes = read_boxes(&mut Cursor::new(&bytes), 0, bytes.len() as u64).unwrap();
+        let kinds: Vec<_> = boxes.iter().map(|b| b.kind).collect();
+        assert_eq!(kinds, [*b"free", *b"mdat"]);
+        assert_eq!((boxes[1].start, boxes[1].end), (19, 29));
+    }
+
+    #[test]
+    fn size_zero_runs_to_the_end() {
+        let mut bytes = bx(b"mdat", &[0; 10]);
+        bytes[..4].copy_from_slice(&0u32.to_be_bytes());
+        let boxes = read_boxes(&mut Cursor::new(&bytes), 0, 18).unwrap();
+        assert_eq!(boxes[0].end, 18);
+    }
+
+    #[test]
+    fn stops_at_corrupt_box_sizes() {
+        let ok = bx(b"free", b"");
+        // 64-bit size that would overflow the offset
+        let huge = [&1u32.to_be_bytes()[..], b"mdat", &u64::MAX.to_be_bytes()].concat();
+        // Smaller than its own header
+        let tiny = [&4u32.to_be_bytes()[..], b"mdat"].concat();
+        // Longer than the data
+        let long = [&100u32.to_be_bytes()[..], b"mdat"].concat();
+        for bad in [huge, tiny, long] {
+            let bytes = [ok.clone(), bad].concat();
+            let boxes = read_boxes(&mut Cursor::new(&bytes), 0, bytes.len() as u64).unwrap();
+            assert_eq!(boxes.len(), 1);
+        }
+    }
+
+    #[test]
+    fn heif_size_comes_from_the_primary_item() {
+        // The larger ispe belongs to another item, e.g. a thumbnail grid
+        let bytes = heic(&[ispe(8000, 6000), ispe(4032, 3024)], &[2]);
+        assert_eq!(dimensions(&bytes).unwrap(), Some((4032, 3024)));
+    }
+
+    #[test]
+    fn heif_size_is_rotated_by_irot() {
+        let irot = bx(b"irot", &[1]);
+        let bytes = heic(&[ispe(4032, 3024), irot], &[1, 2]);
+        assert_eq!(dimensions(&bytes).unwrap(), Some((3024, 4032)));
+        // Rotation of another item is ignored
+        let bytes = heic(&[ispe(4032, 3024), bx(b"irot", &[1])], &[1]);
+        assert_eq!(dimensions(&bytes).unwrap(), Some((4032, 3024)));
+    }
+
+    #[test]
+    fn heif_size_falls_back_to_the_largest_ispe() {
+        let bytes = heic(&[ispe(640, 480), ispe(4032, 3024)], &[]);
+        assert_eq!(dimensions(&bytes).unwrap(), Some((4032, 3024)));
+    }
+
+    #[test]
+    fn truncated_heif_has_no_size() {
+        let bytes = heic(&[ispe(4032, 3024)], &[1]);
+        for len in [0, 10, 40, bytes.len() - 1] {
+            assert_eq!(
+                dimensions(&bytes[..len]).unwrap_or(None),
+                None,
+                "{len} bytes"
+            );
+        }
+        // Without a meta box
+        assert_eq!(dimensions(&bx(b"ftyp", b"heic\0\0\0\0")).unwrap(), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! Keywords embedded in images: XMP `lr:hierarchicalSubject` and
+//! `dc:subject`, and IPTC IIM Keywords (2:25) in a JPEG's Photoshop block.
+//! Keywords come back as tag paths with `|` between levels
+//! (`Places|Europe|Paris`).
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Separator between the levels of a tag path, as Lightroom writes it.
+pub const LEVEL_SEPARATOR: char = '|';
+
+/// Metadata blocks past this point of a file are not found. JPEG, PNG and
+/// WebP writers put them before the image data.
+const HEAD_BYTES: u64 = 1 << 20;
+
+const XMP_START: &[u8] = b"<x:xmpmeta";
+const XMP_END: &[u8] = b"</x:xmpmeta>";
+const PHOTOSHOP: &[u8] = b"Photoshop 3.0\0";
+const IPTC_RESOURCE: u16 = 0x0404;
+const IIM_KEYWORDS: (u8, u8) = (2, 25);
+
+static HIERARCHICAL: Lazy<Regex> = Lazy::new(|| bag("lr:hierarchicalSubject"));
+static SUBJECT: Lazy<Regex> = Lazy::new(|| bag("dc:subject"));
+static ITEM: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<rdf:li\b[^>]*>(.*?)</rdf:li>").unwrap());
+
+/// Pattern capturing the content of an XMP property element.
+pub fn bag(property: &str) -> Regex {
+    Regex::new(&format!(r"(?s)<{0}\b[^>]*>(.*?)</{0}>", property)).unwrap()
+}
+
+/// Keywords embedded in the file at `path`; empty when there are none or the
+/// file can't be read.
+pub fn read(path: &Path) -> Vec<String> {
+    let mut head = Vec::new();
+    let read = File::open(path).and_then(|f| f.take(HEAD_BYTES).read_to_end(&mut head));
+    if read.is_err() {
+        return Vec::new();
+    }
+    let xmp = find(&head, XMP_START).and_then(|start| {
+        let len = find(&head[start..], XMP_END)? + XMP_END.len();
+        Some(String::from_utf8_lossy(&head[start..start + len]).into_owned())
+    });
+    let iptc = find(&head, PHOTOSHOP)
+        .map(|start| iptc_keywords(&head[start + PHOTOSHOP.len()..]))
+        .unwrap_or_default();
+    let (hierarchical, subjects) = match &xmp {
+        Some(packet) => (items(&HIERARCHICAL, packet), items(&SUBJECT, packet)),
+        None => (Vec::new(), Vec::new()),
+    };
+    merge(hierarchical, subjects.into_iter().chain(iptc))
+}
+
+/// Keywords of an XMP packet, e.g. a sidecar.
+pub fn from_xmp(packet: &str) -> Vec<String> {
+    merge(items(&HIERARCHICAL, packet), items(&SUBJECT, packet))
+}
+
+/// Hierarchical paths plus the flat keywords that aren't already a level of
+/// one: Lightroom writes every ancestor of a hierarchical keyword into
+/// `dc:subject` as well.
+fn merge(hierarchical: Vec<String>, flat: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut keywords: Vec<String> = Vec::new();
+    for path in hierarchical {
+        if let Some(path) = normalize(&path) {
+            if !keywords.contains(&path) {
+                keywords.push(path);
+            }
+        }
+    }
+    let levels: Vec<String> = keywords
+        .iter()
+        .flat_map(|p| p.split(LEVEL_SEPARATOR).map(str::to_string))
+        .collect();
+    for keyword in flat {
+        let keyword = keyword.trim().replace(LEVEL_SEPARATOR, " ");
+        if !keyword.is_empty() && !levels.contains(&keyword) && !keywords.contains(&keyword) {
+            keywords.push(keyword);
+        }
+    }
+    keywords
+}
+
+/// `path` with whitespace around each level trimmed and empty levels
+/// dropped; `None` if nothing is left.
+pub fn normalize(path: &str) -> Option<String> {
+    let levels: Vec<&str> = path
+        .split(LEVEL_SEPARATOR)
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    (!levels.is_empty()).then(|| levels.join(&LEVEL_SEPARATOR.to_string()))
+}
+
+/// The `rdf:li` values of an XMP bag or sequence property.
+pub fn items(property: &Regex, packet: &str) -> Vec<String> {
+    property
+        .captures_iter(packet)
+        .flat_map(|bag| {
+            ITEM.captures_iter(bag.get(1).map_or("", |m| m.as_str()))
+                .map(|li| unescape(&li[1]))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Keywords from the image resource blocks of a Photoshop APP13 segment.
+fn iptc_keywords(mut data: &[u8]) -> Vec<String> {
+    let mut keywords = Vec::new();
+    // Each block: "8BIM", id, even-padded Pascal name, size, even-padded data
+    while data.len() >= 12 && data.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([data[4], data[5]]);
+        let name_len = (1 + data[6] as usize + 1) & !1;
+        let Some(size) = data.get(6 + name_len..10 + name_len) else {
+            break;
+        };
+        let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let start = 10 + name_len;
+        let Some(block) = data.get(start..start + size) else {
+            break;
+        };
+        if id == IPTC_RESOURCE {
+            keywords.extend(iim_keywords(block));
+        }
+        data = data.get(start + ((size + 1) & !1)..).unwrap_or_default();
+    }
+    keywords
+}
+
+/// Keywords datasets of an IPTC IIM stream.
+fn iim_keywords(mut data: &[u8]) -> Vec<String> {
+    let mut keywords = Vec::new();
+    while data.len() >= 5 && data[0] == 0x1C {
+        let (record, dataset) = (data[1], data[2]);
+        let size = u16::from_be_bytes([data[3], data[4]]) as usize;
+        // Extended lengths only occur for large binary datasets
+        if size & 0x8000 != 0 {
+            break;
+        }
+        let Some(value) = data.get(5..5 + size) else {
+            break;
+        };
+        if (record, dataset) == IIM_KEYWORDS {
+            // UTF-8 in practice; older writers used Latin-1
+            keywords.push(match std::str::from_utf8(value) {
+                Ok(s) => s.to_string(),
+                Err(_) => value.iter().map(|&b| b as char).collect(),
+            });
+        }
+        data = &data[5 + size..];
+    }
+    keywords
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset(record: u8, number: u8, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1C, record, number];
+        out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        out.extend_from_slice(value);
+        out
+    }
+
+    /// An image resource block with a Pascal `name`, padded as Photoshop does.
+    fn resource(id: u16, name: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = b"8BIM".to_vec();
+        out.extend_from_slice(&id.to_be_bytes());
+        out.push(name.len() as u8);
+        out.extend_from_slice(name.as_bytes());
+        if name.len() % 2 == 0 {
+            out.push(0);
+        }
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn strings(keywords: &[&str]) -> Vec<String> {
+        keywords.iter().map(|k| k.to_string()).collect()
+    }
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+        xmlns:dc="http://purl.org/dc/elements/1.1/"
+        xmlns:lr="http://ns.adobe.com/lightroom/1.0/">
+      <dc:subject><rdf:Bag>
+        <rdf:li>Places</rdf:li><rdf:li>Europe</rdf:li><rdf:li>Paris</rdf:li>
+        <rdf:li>Tom &amp; Jerry</rdf:li>
+      </rdf:Bag></dc:subject>
+      <lr:hierarchicalSubject><rdf:Bag>
+        <rdf:li>Places| Europe |Paris</rdf:li><rdf:li>Places|Europe|Paris</rdf:li>
+      </rdf:Bag></lr:hierarchicalSubject>
+    </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    #[test]
+    fn reads_iim_keywords() {
+        let stream = [
+            dataset(1, 90, b"\x1B%G"),
+            dataset(2, 25, "café".as_bytes()),
+            dataset(2, 5, b"Title"),
+            // Latin-1 from an older writer
+            dataset(2, 25, b"caf\xE9 cr\xE8me"),
+        ]
+        .concat();
+        assert_eq!(iim_keywords(&stream), ["café", "café crème"]);
+    }
+
+    #[test]
+    fn stops_at_malformed_iim_datasets() {
+        let good = dataset(2, 25, b"kept");
+        // Cut inside the value
+        let cut = [good.clone(), dataset(2, 25, b"lost")[..7].to_vec()].concat();
+        assert_eq!(iim_keywords(&cut), ["kept"]);
+        // Extended length
+        let extended = [good.clone(), vec![0x1C, 2, 25, 0x80, 4, 0, 0, 0, 4]].concat();
+        assert_eq!(iim_keywords(&extended), ["kept"]);
+        // Not a tag marker
+        let garbage = [good, vec![0x00, 2, 25, 0, 1, b'x']].concat();
+        assert_eq!(iim_keywords(&garbage), ["kept"]);
+    }
+
+    #[test]
+    fn reads_keywords_from_photoshop_resources() {
+        let iptc = [dataset(2, 25, b"dog"), dataset(2, 25, b"beach")].concat();
+        let data = [
+            // Odd-sized block with an odd-length name, both padded
+            resource(0x0425, "abc", &[1, 2, 3]),
+            resource(IPTC_RESOURCE, "", &iptc),
+            resource(IPTC_RESOURCE, "more", &dataset(2, 25, b"sun")),
+        ]
+        .concat();
+        assert_eq!(iptc_keywords(&data), ["dog", "beach", "sun"]);
+
+        // A block claiming more data than is left
+        let mut truncated = resource(IPTC_RESOURCE, "", &iptc);
+        truncated.truncate(truncated.len() - 3);
+        assert!(iptc_keywords(&truncated).is_empty());
+        assert!(iptc_keywords(b"8BIM\x04").is_empty());
+        assert!(iptc_keywords(b"not a resource block").is_empty());
+    }
+
+    #[test]
+    fn merge_drops_flat_ancestors_of_hierarchical_keywords() {
+        let merged = merge(
+            strings(&["Places|Europe|Paris", " People | Anna ", "||"]),
+            strings(&["Places", "Paris", "Anna", "dog", "dog", " ", "a|b"]),
+        );
+        assert_eq!(merged, ["Places|Europe|Paris", "People|Anna", "dog", "a b"]);
+    }
+
+    #[test]
+    fn reads_keywords_from_an_xmp_packet() {
+        assert_eq!(from_xmp(PACKET), ["Places|Europe|Paris", "Tom & Jerry"]);
+        assert!(from_xmp("<x:xmpmeta></x:xmpmeta>").is_empty());
+    }
+
+    #[test]
+    fn reads_xmp_and_iptc_from_a_jpeg() {
+        let iptc = [dataset(2, 25, b"Paris"), dataset(2, 25, b"holiday")].concat();
+        let app13 = [PHOTOSHOP, &resource(IPTC_RESOURCE, "", &iptc)].concat();
+        let mut jpeg = vec![0xFF, 0xD8];
+        for (marker, payload) in [
+            (
+                0xE1,
+                [&b"http://ns.adobe.com/xap/1.0/\0"[..], PACKET.as_bytes()].concat(),
+            ),
+            (0xED, app13),
+        ] {
+            jpeg.extend_from_slice(&[0xFF, marker]);
+            jpeg.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+            jpeg.extend(payload);
+        }
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+
+        let path =
+            std::env::temp_dir().join(format!("lumous-keywords-{}.jpg", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &jpeg).unwrap();
+        let keywords = read(&path);
+        let _ = std::fs::remove_file(&path);
+        // IPTC keywords already in the hierarchy are not repeated
+        assert_eq!(keywords, ["Places|Europe|Paris", "Tom & Jerry", "holiday"]);
+        assert!(read(Path::new("/missing/file.jpg")).is_empty());
+    }
+}
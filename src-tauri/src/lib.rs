@@ -3,7 +3,20 @@ mod models;
 mod indexer;
 mod thumbnail;
 mod database;
+mod formats;
+mod isobmff;
+mod metadata;
+mod raw;
+mod video;
+mod animation;
+mod migrations;
+mod geocode;
+mod keywords;
+mod xmp;
+mod sidecar;
+mod dataset;
 use commands::*;
+use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -11,8 +24,11 @@ pub fn run() {
     .plugin(tauri_plugin_dialog::init())
     .plugin(tauri_plugin_fs::init())
     .setup(|app| {
-  // Load persisted DB (best-effort) at startup
-  initialize_persistent_db();
+      let db = database::Database::open(&database::Database::default_path())
+        .map_err(|e| format!("Failed to open library database: {}", e))?;
+      app.manage(db);
+      // Re-create watchers persisted from previous sessions (best-effort)
+      restore_watchers(app.handle());
       if cfg!(debug_assertions) {
         app.handle().plugin(
           tauri_plugin_log::Builder::default()
@@ -28,9 +44,27 @@ pub fn run() {
       index_folder,
       index_folder_streaming,
       get_files,
+      get_map_clusters,
+      get_places,
+      get_date_histogram,
+      jump_to_date,
+      get_on_this_day,
+      get_albums,
+      cluster_events,
+      create_smart_album,
+      update_smart_album,
+      delete_album,
+      get_tags,
+      tag_files,
+      untag_files,
+      delete_tag,
+      set_rating,
+      get_xmp_write_back,
+      set_xmp_write_back,
       is_folder_indexed,
       get_indexed_folders,
       get_thumbnail,
+      get_animated_preview,
       create_album,
       add_to_album,
       search_files,
@@ -38,13 +72,21 @@ pub fn run() {
       export_metadata,
       open_in_explorer,
       watch_folder,
+      unwatch_folder,
+      list_watched_folders,
       get_library_state,
       update_last_selected_folder,
       reset_library,
       reset_folder,
       get_sidecar_caption,
       get_sidecar_json,
-      get_sidecar_data
+      get_sidecar_data,
+      set_sidecar_caption,
+      set_sidecar_json,
+      edit_captions,
+      get_caption_stats,
+      export_dataset,
+      check_dataset
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -0,0 +1,180 @@
+use crate::models::{GpsCoordinates, ImageMetadata, Resolution};
+use exif::{Exif, In, Tag, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Read EXIF metadata from any container kamadak-exif understands (JPEG,
+/// TIFF, HEIF/AVIF, PNG, WebP). Returns `None` when the file has no EXIF.
+pub fn read_exif_metadata(path: &Path) -> Option<ImageMetadata> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    Some(metadata_from_exif(&[exif]))
+}
+
+/// Metadata from raw TIFF-structured EXIF blocks, e.g. the CMT boxes of a CR3
+/// or the head of a TIFF-based RAW. Blocks may be truncated; whatever parsed
+/// is kept. Earlier blocks take precedence when a tag appears twice.
+pub fn read_exif_blocks(blocks: Vec<Vec<u8>>) -> Option<ImageMetadata> {
+    let exifs: Vec<Exif> = blocks
+        .into_iter()
+        .filter_map(|data| {
+            exif::Reader::new()
+                .continue_on_error(true)
+                .read_raw(data)
+                .or_else(|e| e.distill_partial_result(|_| {}))
+                .ok()
+        })
+        .collect();
+    if exifs.is_empty() {
+        return None;
+    }
+    Some(metadata_from_exif(&exifs))
+}
+
+fn metadata_from_exif(exif: &[Exif]) -> ImageMetadata {
+    ImageMetadata {
+        camera_make: ascii(exif, Tag::Make),
+        camera_model: ascii(exif, Tag::Model),
+        lens_model: ascii(exif, Tag::LensModel),
+        focal_length: rational(exif, Tag::FocalLength).map(|v| v as f32),
+        aperture: rational(exif, Tag::FNumber).map(|v| v as f32),
+        shutter_speed: rational(exif, Tag::ExposureTime).map(format_shutter),
+        iso: uint(exif, Tag::PhotographicSensitivity),
+        date_taken: date_taken(exif),
+        gps_coordinates: gps(exif),
+        color_space: uint(exif, Tag::ColorSpace).map(|v| {
+            match v {
+                1 => "sRGB",
+                2 => "Adobe RGB",
+                0xFFFF => "Uncalibrated",
+                _ => "Unknown",
+            }
+            .to_string()
+        }),
+        resolution: resolution(exif),
+    }
+}
+
+/// Look a tag up across blocks. Standalone blocks (such as CR3's CMT2, which
+/// is an Exif IFD stored as its own TIFF) surface Exif tags under the TIFF
+/// context, so fall back to matching on the tag number alone.
+fn field(exif: &[Exif], tag: Tag) -> Option<&Value> {
+    exif.iter().find_map(|e| {
+        e.get_field(tag, In::PRIMARY)
+            .or_else(|| {
+                e.fields()
+                    .find(|f| f.ifd_num == In::PRIMARY && f.tag.number() == tag.number())
+            })
+            .map(|f| &f.value)
+    })
+}
+
+fn ascii(exif: &[Exif], tag: Tag) -> Option<String> {
+    match field(exif, tag)? {
+        Value::Ascii(parts) => {
+            let s = String::from_utf8_lossy(parts.first()?).trim().to_string();
+            if s.is_empty() {
+                None
+            } else {
+                Some(s)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn rational(exif: &[Exif], tag: Tag) -> Option<f64> {
+    match field(exif, tag)? {
+        Value::Rational(v) if v.first()?.denom != 0 => Some(v[0].to_f64()),
+        Value::SRational(v) if v.first()?.denom != 0 => Some(v[0].to_f64()),
+        _ => None,
+    }
+}
+
+fn uint(exif: &[Exif], tag: Tag) -> Option<u32> {
+    field(exif, tag)?.get_uint(0)
+}
+
+fn format_shutter(seconds: f64) -> String {
+    if seconds > 0.0 && seconds < 1.0 {
+        format!("1/{}", (1.0 / seconds).round() as u32)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// DateTimeOriginal as ISO 8601, with the EXIF 2.31 offset appended when the
+/// camera recorded one. Without an offset the value is local wall-clock time.
+fn date_taken(exif: &[Exif]) -> Option<String> {
+    let raw = ascii(exif, Tag::DateTimeOriginal).or_else(|| ascii(exif, Tag::DateTime))?;
+    let naive = chrono::NaiveDateTime::parse_from_str(&raw, "%Y:%m:%d %H:%M:%S").ok()?;
+    let with_offset = ascii(exif, Tag::OffsetTimeOriginal).and_then(|offset| {
+        chrono::DateTime::parse_from_str(&format!("{} {}", raw, offset), "%Y:%m:%d %H:%M:%S %:z")
+            .ok()
+    });
+    match with_offset {
+        Some(dt) => Some(dt.to_rfc3339()),
+        None => Some(naive.format("%Y-%m-%dT%H:%M:%S").to_string()),
+    }
+}
+
+/// Milliseconds since the Unix epoch for an ISO 8601 timestamp as found in
+/// `FileMeta` and [`ImageMetadata::date_taken`]. A value without an offset is
+/// wall-clock time and is read in the local time zone, falling back to UTC
+/// for times the zone skips.
+pub fn timestamp_ms(value: &str) -> Option<i64> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(dt.timestamp_millis());
+    }
+    let naive = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    let local = naive.and_local_timezone(chrono::Local).earliest();
+    Some(local.map_or_else(
+        || naive.and_utc().timestamp_millis(),
+        |dt| dt.timestamp_millis(),
+    ))
+}
+
+fn gps(exif: &[Exif]) -> Option<GpsCoordinates> {
+    let lat = dms(field(exif, Tag::GPSLatitude)?)?;
+    let lon = dms(field(exif, Tag::GPSLongitude)?)?;
+    let lat_sign = match ascii(exif, Tag::GPSLatitudeRef).as_deref() {
+        Some("S") => -1.0,
+        _ => 1.0,
+    };
+    let lon_sign = match ascii(exif, Tag::GPSLongitudeRef).as_deref() {
+        Some("W") => -1.0,
+        _ => 1.0,
+    };
+    Some(GpsCoordinates {
+        latitude: lat * lat_sign,
+        longitude: lon * lon_sign,
+    })
+}
+
+/// Degrees/minutes/seconds rationals to decimal degrees.
+fn dms(value: &Value) -> Option<f64> {
+    match value {
+        Value::Rational(v) if v.len() >= 3 && v.iter().all(|r| r.denom != 0) => {
+            Some(v[0].to_f64() + v[1].to_f64() / 60.0 + v[2].to_f64() / 3600.0)
+        }
+        _ => None,
+    }
+}
+
+fn resolution(exif: &[Exif]) -> Option<Resolution> {
+    let x = rational(exif, Tag::XResolution)?;
+    let y = rational(exif, Tag::YResolution).unwrap_or(x);
+    let unit = match uint(exif, Tag::ResolutionUnit) {
+        Some(3) => "cm",
+        Some(1) => "none",
+        _ => "inch",
+    };
+    Some(Resolution {
+        x: x.round() as u32,
+        y: y.round() as u32,
+        unit: unit.to_string(),
+    })
+}
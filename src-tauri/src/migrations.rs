@@ -0,0 +1,840 @@
+//! Versioned schema migrations for `library.db`.
+//!
+//! `PRAGMA user_version` holds the number of migrations applied. Each
+//! migration runs in its own transaction together with the version bump, so a
+//! failure leaves the database at the previous version. Migrations are only
+//! ever appended; never edit one that has shipped.
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy)]
+pub struct Migration {
+    pub description: &'static str,
+    /// Drops or rebuilds tables or columns. The database file is backed up
+    /// before the first pending destructive migration runs, and foreign keys
+    /// are suspended so tables can be rebuilt.
+    pub destructive: bool,
+    pub up: fn(&Connection) -> Result<()>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "initial schema",
+        destructive: false,
+        up: initial_schema,
+    },
+    Migration {
+        description: "watched folders",
+        destructive: false,
+        up: watched_folders,
+    },
+    Migration {
+        description: "RAW+JPEG variants",
+        destructive: false,
+        up: file_variants,
+    },
+    Migration {
+        description: "video files",
+        destructive: false,
+        up: video_columns,
+    },
+    Migration {
+        description: "animated images",
+        destructive: false,
+        up: animation_columns,
+    },
+    Migration {
+        description: "keyset pagination indexes",
+        destructive: false,
+        up: pagination_indexes,
+    },
+    Migration {
+        description: "sort orders",
+        destructive: false,
+        up: sort_columns,
+    },
+    Migration {
+        description: "integer timestamps",
+        destructive: true,
+        up: integer_timestamps,
+    },
+    Migration {
+        description: "aspect ratio",
+        destructive: false,
+        up: aspect_ratio,
+    },
+    Migration {
+        description: "places",
+        destructive: false,
+        up: places,
+    },
+    Migration {
+        description: "album kinds",
+        destructive: false,
+        up: album_kinds,
+    },
+    Migration {
+        description: "smart albums",
+        destructive: false,
+        up: smart_albums,
+    },
+    Migration {
+        description: "hierarchical tags",
+        destructive: false,
+        up: hierarchical_tags,
+    },
+    Migration {
+        description: "xmp sidecars",
+        destructive: false,
+        up: sidecar_columns,
+    },
+    Migration {
+        description: "caption index",
+        destructive: false,
+        up: caption_column,
+    },
+];
+
+/// Bring `conn` up to the latest schema. `db_path` is where backups are
+/// written next to; pass `None` for in-memory databases.
+pub fn run(conn: &mut Connection, db_path: Option<&Path>) -> Result<()> {
+    run_migrations(conn, MIGRATIONS, db_path)
+}
+
+pub fn user_version(conn: &Connection) -> Result<u32> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+fn run_migrations(
+    conn: &mut Connection,
+    migrations: &[Migration],
+    db_path: Option<&Path>,
+) -> Result<()> {
+    let current = user_version(conn)?;
+    let latest = migrations.len() as u32;
+    if current > latest {
+        bail!(
+            "library.db is at schema version {} but this build only knows {}; \
+             it was written by a newer version of the app",
+            current,
+            latest
+        );
+    }
+
+    let pending = &migrations[current as usize..];
+    if let Some(path) = db_path {
+        if pending.iter().any(|m| m.destructive) {
+            backup(conn, path, current)?;
+        }
+    }
+
+    for (i, migration) in pending.iter().enumerate() {
+        let version = current + i as u32 + 1;
+        let foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+        // foreign_keys can't change inside a transaction
+        if migration.destructive && foreign_keys {
+            conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+        }
+        let result = apply(conn, migration, version);
+        if migration.destructive && foreign_keys {
+            conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        }
+        result.with_context(|| {
+            format!(
+                "schema migration {} ({}) failed",
+                version, migration.description
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn apply(conn: &mut Connection, migration: &Migration, version: u32) -> Result<()> {
+    let tx = conn.transaction()?;
+    (migration.up)(&tx)?;
+    if migration.destructive {
+        let violations: i64 =
+            tx.query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| {
+                row.get(0)
+            })?;
+        if violations > 0 {
+            bail!("{} foreign key violations after rebuild", violations);
+        }
+    }
+    tx.pragma_update(None, "user_version", version)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Consistent copy of the database (WAL included) as
+/// `library.db.v<version>.bak`, replacing an older backup of the same version.
+fn backup(conn: &Connection, db_path: &Path, version: u32) -> Result<PathBuf> {
+    let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    let backup_path = db_path.with_file_name(name);
+    if backup_path.exists() {
+        std::fs::remove_file(&backup_path)?;
+    }
+    conn.execute(
+        "VACUUM INTO ?1",
+        params![backup_path.to_string_lossy().to_string()],
+    )
+    .with_context(|| format!("failed to back up library to {}", backup_path.display()))?;
+    Ok(backup_path)
+}
+
+/// Adds a column when it is missing.
+fn ensure_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|c| c.ok())
+        .any(|c| c == column);
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Forget every folder's snapshot so the next index pass reprocesses all
+/// files, backfilling columns only the indexer can fill.
+fn reindex_all(conn: &Connection) -> Result<()> {
+    conn.execute_batch("DELETE FROM folder_snapshots;")?;
+    Ok(())
+}
+
+// --- Migrations ---
+
+fn initial_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS files (
+            id TEXT PRIMARY KEY,
+            path TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            modified TEXT NOT NULL,
+            file_type TEXT,
+            thumbnail_path TEXT,
+            folder_path TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_folder_path ON files(folder_path);
+
+        CREATE TABLE IF NOT EXISTS folder_snapshots (
+            path TEXT PRIMARY KEY,
+            file_count INTEGER NOT NULL,
+            agg_mtime INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS kv_store (
+            key TEXT PRIMARY KEY,
+            value TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS albums (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            cover_image_id TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS album_files (
+            album_id TEXT NOT NULL,
+            file_id TEXT NOT NULL,
+            PRIMARY KEY (album_id, file_id),
+            FOREIGN KEY(album_id) REFERENCES albums(id) ON DELETE CASCADE,
+            FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+        );",
+    )?;
+    Ok(())
+}
+
+fn watched_folders(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS watched_folders (
+            path TEXT PRIMARY KEY,
+            added_at INTEGER NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+fn file_variants(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "variant_of", "TEXT")?;
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_variant_of ON files(variant_of);")?;
+    Ok(())
+}
+
+fn video_columns(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "media_type", "TEXT NOT NULL DEFAULT 'image'")?;
+    ensure_column(conn, "files", "duration_ms", "INTEGER")?;
+    ensure_column(conn, "files", "video_codec", "TEXT")?;
+    Ok(())
+}
+
+fn animation_columns(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "is_animated", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(conn, "files", "frame_count", "INTEGER")?;
+    Ok(())
+}
+
+/// Listings page on (modified, id); the folder index also serves every
+/// lookup `idx_folder_path` did.
+fn pagination_indexes(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_files_modified ON files(modified, id);
+         CREATE INDEX IF NOT EXISTS idx_files_folder_modified ON files(folder_path, modified, id);
+         DROP INDEX IF EXISTS idx_folder_path;",
+    )?;
+    Ok(())
+}
+
+/// Columns behind the non-default sort orders, with folder-scoped indexes on
+/// the same expressions `database::sort_expr` orders by. Whole-library
+/// listings in these orders sort without an index.
+fn sort_columns(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "name_key", "TEXT NOT NULL DEFAULT ''")?;
+    ensure_column(conn, "files", "shuffle_key", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(conn, "files", "date_taken", "TEXT")?;
+    ensure_column(conn, "files", "width", "INTEGER")?;
+    ensure_column(conn, "files", "height", "INTEGER")?;
+    ensure_column(conn, "files", "rating", "INTEGER")?;
+
+    let rows: Vec<(String, String)> = conn
+        .prepare("SELECT id, name FROM files")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut update =
+        conn.prepare("UPDATE files SET name_key = ?1, shuffle_key = ?2 WHERE id = ?3")?;
+    for (id, name) in rows {
+        update.execute(params![natural_sort_key(&name), shuffle_key(&id), id])?;
+    }
+
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_files_folder_name ON files(folder_path, name_key, id);
+         CREATE INDEX IF NOT EXISTS idx_files_folder_size ON files(folder_path, size, id);
+         CREATE INDEX IF NOT EXISTS idx_files_folder_taken
+             ON files(folder_path, COALESCE(date_taken, modified), id);
+         CREATE INDEX IF NOT EXISTS idx_files_folder_pixels
+             ON files(folder_path, COALESCE(width * height, 0), id);
+         CREATE INDEX IF NOT EXISTS idx_files_folder_rating
+             ON files(folder_path, COALESCE(rating, 0), id);",
+    )?;
+    Ok(())
+}
+
+/// Replace the RFC 3339 `modified` text, which doesn't order across offsets,
+/// with epoch milliseconds, and add creation and capture times. `date_taken`
+/// keeps the original text, offset or not, for display.
+fn integer_timestamps(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "modified_at", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(conn, "files", "created_at", "INTEGER")?;
+    ensure_column(conn, "files", "taken_at", "INTEGER")?;
+
+    let rows: Vec<(String, String, Option<String>)> = conn
+        .prepare("SELECT id, modified, date_taken FROM files")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut update =
+        conn.prepare("UPDATE files SET modified_at = ?1, taken_at = ?2 WHERE id = ?3")?;
+    for (id, modified, date_taken) in rows {
+        update.execute(params![
+            timestamp_ms(&modified).unwrap_or_default(),
+            date_taken.as_deref().and_then(timestamp_ms),
+            id
+        ])?;
+    }
+
+    // SQLite won't drop a column that an index still uses
+    conn.execute_batch(
+        "DROP INDEX IF EXISTS idx_files_modified;
+         DROP INDEX IF EXISTS idx_files_folder_modified;
+         DROP INDEX IF EXISTS idx_files_folder_taken;
+         ALTER TABLE files DROP COLUMN modified;
+         CREATE INDEX idx_files_modified ON files(modified_at, id);
+         CREATE INDEX idx_files_folder_modified ON files(folder_path, modified_at, id);
+         CREATE INDEX idx_files_folder_taken
+             ON files(folder_path, COALESCE(taken_at, modified_at), id);",
+    )?;
+    Ok(())
+}
+
+fn aspect_ratio(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "aspect_ratio", "REAL")?;
+    conn.execute_batch(
+        "UPDATE files SET aspect_ratio = CAST(width AS REAL) / height
+         WHERE width IS NOT NULL AND height > 0;",
+    )?;
+    Ok(())
+}
+
+/// Reverse-geocoding table and the GPS and place columns on files. Place
+/// names are copied onto files so listings don't join. The table is filled
+/// at open by `geocode::sync_places`, so new place data needs no migration.
+/// Existing files are reindexed to read their positions.
+fn places(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS geo_places (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            region TEXT NOT NULL,
+            country_code TEXT NOT NULL,
+            country TEXT NOT NULL,
+            latitude REAL NOT NULL,
+            longitude REAL NOT NULL,
+            population INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS idx_geo_places_latitude ON geo_places(latitude);",
+    )?;
+
+    ensure_column(conn, "files", "latitude", "REAL")?;
+    ensure_column(conn, "files", "longitude", "REAL")?;
+    ensure_column(conn, "files", "country_code", "TEXT")?;
+    ensure_column(conn, "files", "country", "TEXT")?;
+    ensure_column(conn, "files", "region", "TEXT")?;
+    ensure_column(conn, "files", "city", "TEXT")?;
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_files_position ON files(latitude, longitude);
+         CREATE INDEX IF NOT EXISTS idx_files_place ON files(country_code, region, city);",
+    )?;
+    reindex_all(conn)
+}
+
+/// Generated albums live next to manual ones, told apart by `kind`. Times are
+/// epoch milliseconds; existing albums keep a NULL creation time. Event
+/// clustering walks files in capture order, hence the `taken_at` index.
+fn album_kinds(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "albums", "kind", "TEXT NOT NULL DEFAULT 'manual'")?;
+    ensure_column(conn, "albums", "created_at", "INTEGER")?;
+    ensure_column(conn, "albums", "starts_at", "INTEGER")?;
+    ensure_column(conn, "albums", "ends_at", "INTEGER")?;
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_albums_kind ON albums(kind);
+         CREATE INDEX IF NOT EXISTS idx_files_taken ON files(taken_at);",
+    )?;
+    Ok(())
+}
+
+/// Saved `SearchQuery` of a smart album, as JSON.
+fn smart_albums(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "albums", "query", "TEXT")
+}
+
+/// Tags form a tree through `parent_id`; `path` spells out the ancestry
+/// (`Places|Europe|Paris`) so a subtree is one prefix match. `source` on a
+/// file's tag tells keywords imported from the file, which re-indexing
+/// replaces, from tags added in the app. Existing files are reindexed to
+/// import their keywords.
+fn hierarchical_tags(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tags (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            parent_id TEXT REFERENCES tags(id) ON DELETE CASCADE,
+            path TEXT NOT NULL UNIQUE,
+            color TEXT,
+            created_at INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_tags_parent ON tags(parent_id);
+
+        CREATE TABLE IF NOT EXISTS file_tags (
+            file_id TEXT NOT NULL,
+            tag_id TEXT NOT NULL,
+            source TEXT NOT NULL DEFAULT 'user',
+            PRIMARY KEY (file_id, tag_id),
+            FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE,
+            FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_file_tags_tag ON file_tags(tag_id);",
+    )?;
+    reindex_all(conn)
+}
+
+/// Color labels (a JSON array) and description read from XMP sidecars.
+/// `rating_source` tells a rating read from the sidecar (`embedded`), which
+/// re-indexing replaces, from one set in the app (`user`); every rating so
+/// far was set in the app. Existing files are reindexed to read their
+/// sidecars.
+fn sidecar_columns(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "labels", "TEXT")?;
+    ensure_column(conn, "files", "description", "TEXT")?;
+    ensure_column(conn, "files", "rating_source", "TEXT")?;
+    conn.execute_batch("UPDATE files SET rating_source = 'user' WHERE rating IS NOT NULL;")?;
+    reindex_all(conn)
+}
+
+/// Caption sidecar text, for search. Existing files are reindexed to read
+/// their captions.
+fn caption_column(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "files", "caption", "TEXT")?;
+    reindex_all(conn)
+}
+
+// --- Frozen helpers ---
+//
+// Copies of application code as it was when the migrations using it shipped.
+// The live versions are free to change; these must not, or databases
+// migrated by different builds would disagree.
+
+/// `database::natural_sort_key` as of "sort orders".
+fn natural_sort_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len() + 8);
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() {
+            let mut run = String::from(c);
+            while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+                run.push(d);
+            }
+            let digits = run.trim_start_matches('0');
+            key.push_str(&format!("{:02}{}", digits.len(), digits));
+        } else {
+            key.extend(c.to_lowercase());
+        }
+    }
+    key
+}
+
+/// `database::shuffle_key` as of "sort orders".
+fn shuffle_key(id: &str) -> i64 {
+    let digest = Sha256::digest(id.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    (u64::from_le_bytes(bytes) & ((1 << 48) - 1)) as i64
+}
+
+/// `metadata::timestamp_ms` as of "integer timestamps".
+fn timestamp_ms(value: &str) -> Option<i64> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(dt.timestamp_millis());
+    }
+    let naive = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    let local = naive.and_local_timezone(chrono::Local).earliest();
+    Some(local.map_or_else(
+        || naive.and_utc().timestamp_millis(),
+        |dt| dt.timestamp_millis(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::OptionalExtension;
+
+    /// The last release before versioning.
+    const LEGACY_UNVERSIONED: &str = include_str!("../tests/fixtures/legacy_unversioned.sql");
+
+    fn latest() -> u32 {
+        MIGRATIONS.len() as u32
+    }
+
+    /// Columns (sorted, so ALTER-added and CREATE-declared columns compare
+    /// equal) and index names of every table.
+    fn schema(conn: &Connection) -> Vec<(String, Vec<String>)> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT type, name FROM sqlite_master
+                 WHERE name NOT LIKE 'sqlite_%' ORDER BY type, name",
+            )
+            .unwrap();
+        let objects: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        objects
+            .into_iter()
+            .map(|(kind, name)| {
+                let mut columns = Vec::new();
+                if kind == "table" {
+                    let mut info = conn
+                        .prepare(&format!("PRAGMA table_info({})", name))
+                        .unwrap();
+                    columns = info
+                        .query_map([], |row| {
+                            Ok(format!(
+                                "{} {} notnull={} default={:?} pk={}",
+                                row.get::<_, String>(1)?,
+                                row.get::<_, String>(2)?,
+                                row.get::<_, i64>(3)?,
+                                row.get::<_, Option<String>>(4)?,
+                                row.get::<_, i64>(5)?
+                            ))
+                        })
+                        .unwrap()
+                        .map(|r| r.unwrap())
+                        .collect();
+                    columns.sort();
+                }
+                (format!("{} {}", kind, name), columns)
+            })
+            .collect()
+    }
+
+    fn fresh_schema() -> Vec<(String, Vec<String>)> {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run(&mut conn, None).unwrap();
+        schema(&conn)
+    }
+
+    fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+        conn.prepare(&format!("SELECT {} FROM {}", column, table))
+            .is_ok()
+    }
+
+    /// Rows every schema version can hold.
+    fn seed(conn: &Connection) {
+        let modified = if has_column(conn, "files", "modified") {
+            ("modified", "'2024-01-01T00:00:00+00:00'")
+        } else {
+            ("modified_at", "1704067200000")
+        };
+        conn.execute_batch(&format!(
+            "INSERT INTO files (id, path, name, size, {}, file_type, thumbnail_path, folder_path)
+             VALUES ('f1', '/pics/a.jpg', 'a.jpg', 10, {}, 'jpg', NULL, '/pics');",
+            modified.0, modified.1
+        ))
+        .unwrap();
+        conn.execute_batch(
+            "INSERT INTO albums (id, name) VALUES ('al1', 'Trip');
+             INSERT INTO album_files (album_id, file_id) VALUES ('al1', 'f1');
+             INSERT INTO kv_store (key, value) VALUES ('last_selected_folder', '/pics');
+             INSERT INTO folder_snapshots (path, file_count, agg_mtime) VALUES ('/pics', 1, 42);",
+        )
+        .unwrap();
+    }
+
+    fn assert_seed_intact(conn: &Connection) {
+        let file: (String, String, i64) = conn
+            .query_row(
+                "SELECT path, media_type, modified_at FROM files WHERE id = 'f1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            file,
+            (
+                "/pics/a.jpg".to_string(),
+                "image".to_string(),
+                1704067200000
+            )
+        );
+        let in_album: Option<i64> = conn
+            .query_row(
+                "SELECT 1 FROM album_files WHERE album_id = 'al1' AND file_id = 'f1'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap();
+        assert!(in_album.is_some());
+        let setting: String = conn
+            .query_row(
+                "SELECT value FROM kv_store WHERE key = 'last_selected_folder'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(setting, "/pics");
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run(&mut conn, None).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), latest());
+        // Running again is a no-op
+        run(&mut conn, None).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), latest());
+    }
+
+    #[test]
+    fn migrates_from_every_prior_version() {
+        let expected = fresh_schema();
+        for version in 1..latest() {
+            let mut conn = Connection::open_in_memory().unwrap();
+            run_migrations(&mut conn, &MIGRATIONS[..version as usize], None).unwrap();
+            assert_eq!(user_version(&conn).unwrap(), version);
+            seed(&conn);
+
+            run(&mut conn, None).unwrap();
+            assert_eq!(user_version(&conn).unwrap(), latest(), "from v{}", version);
+            assert_eq!(schema(&conn), expected, "from v{}", version);
+            assert_seed_intact(&conn);
+        }
+    }
+
+    #[test]
+    fn migrates_legacy_unversioned_databases() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_UNVERSIONED).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), 0);
+
+        run(&mut conn, None).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), latest());
+        assert_eq!(schema(&conn), fresh_schema());
+        assert_seed_intact(&conn);
+    }
+
+    #[test]
+    fn legacy_rows_keep_their_values() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_UNVERSIONED).unwrap();
+        run(&mut conn, None).unwrap();
+        let (size, thumbnail, modified_at): (i64, Option<String>, i64) = conn
+            .query_row(
+                "SELECT size, thumbnail_path, modified_at FROM files WHERE id = 'f2'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(size, 2000);
+        assert_eq!(thumbnail.as_deref(), Some("/thumbs/f2.jpg"));
+        assert_eq!(modified_at, 1717236000000);
+        let cover: Option<String> = conn
+            .query_row(
+                "SELECT cover_image_id FROM albums WHERE id = 'al1'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(cover.as_deref(), Some("f2"));
+    }
+
+    #[test]
+    fn sort_keys_are_backfilled() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_UNVERSIONED).unwrap();
+        run(&mut conn, None).unwrap();
+        let (name_key, shuffle): (String, i64) = conn
+            .query_row(
+                "SELECT name_key, shuffle_key FROM files WHERE id = 'f2'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        // Literal values: the keys of migrated and newly indexed files must
+        // keep agreeing
+        assert_eq!(name_key, "img_0210.jpg");
+        assert_eq!(shuffle, 161715103640548);
+    }
+
+    #[test]
+    fn backfills_force_a_reindex() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_UNVERSIONED).unwrap();
+        run(&mut conn, None).unwrap();
+        let snapshots: i64 = conn
+            .query_row("SELECT COUNT(*) FROM folder_snapshots", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(snapshots, 0);
+    }
+
+    #[test]
+    fn refuses_database_from_newer_build() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest() + 1)
+            .unwrap();
+        assert!(run(&mut conn, None).is_err());
+    }
+
+    fn broken(conn: &Connection) -> Result<()> {
+        conn.execute_batch("CREATE TABLE half_done (x INTEGER);")?;
+        bail!("boom")
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run(&mut conn, None).unwrap();
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push(Migration {
+            description: "broken",
+            destructive: false,
+            up: broken,
+        });
+
+        assert!(run_migrations(&mut conn, &migrations, None).is_err());
+        assert_eq!(user_version(&conn).unwrap(), latest());
+        let table: Option<String> = conn
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE name = 'half_done'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap();
+        assert!(table.is_none());
+    }
+
+    /// Rebuild `albums` without `cover_image_id`, the way a real destructive
+    /// migration would.
+    fn drop_album_cover(conn: &Connection) -> Result<()> {
+        conn.execute_batch(
+            "CREATE TABLE albums_new (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                description TEXT
+            );
+            INSERT INTO albums_new (id, name, description)
+                SELECT id, name, description FROM albums;
+            DROP TABLE albums;
+            ALTER TABLE albums_new RENAME TO albums;",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn backs_up_before_destructive_migration() {
+        let dir = std::env::temp_dir().join(format!("lumous-migrations-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("library.db");
+
+        let mut conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        run(&mut conn, Some(&db_path)).unwrap();
+        seed(&conn);
+        // Nothing destructive is pending, so no backup is taken
+        assert!(!dir.join(format!("library.db.v{}.bak", latest())).exists());
+
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push(Migration {
+            description: "drop album cover",
+            destructive: true,
+            up: drop_album_cover,
+        });
+        run_migrations(&mut conn, &migrations, Some(&db_path)).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), latest() + 1);
+        // Memberships survive the rebuild and foreign keys are back on
+        assert_seed_intact(&conn);
+        let fk: bool = conn
+            .query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+            .unwrap();
+        assert!(fk);
+
+        let backup_path = dir.join(format!("library.db.v{}.bak", latest()));
+        let backup = Connection::open(&backup_path).unwrap();
+        assert_eq!(user_version(&backup).unwrap(), latest());
+        assert_seed_intact(&backup);
+        let cover_column: i64 = backup
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('albums') WHERE name = 'cover_image_id'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(cover_column, 1);
+
+        drop(conn);
+        drop(backup);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
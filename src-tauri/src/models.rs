@@ -16,12 +16,253 @@ pub struct FileMeta {
     pub modified: String,
     pub created: String,
     pub file_type: String,
+    /// Photo or video; `file_type` keeps the specific extension.
+    #[serde(default)]
+    pub media_type: MediaType,
     pub dimensions: Option<Dimensions>,
     pub thumbnail_path: Option<String>,
+    /// Tag paths such as `Places|Europe|Paris`. The indexer fills it with
+    /// the keywords embedded in the file.
     pub tags: Vec<String>,
     pub albums: Vec<String>,
     pub rating: Option<i32>,
     pub metadata: Option<ImageMetadata>,
+    /// Other files of the same shot (e.g. the RAW next to a JPEG with the
+    /// same stem). Variants are not listed as items of their own.
+    #[serde(default)]
+    pub variants: Vec<FileVariant>,
+    /// Container details for videos; `None` for images.
+    #[serde(default)]
+    pub video: Option<VideoMetadata>,
+    /// GIF or WebP with more than one frame.
+    #[serde(default)]
+    pub is_animated: bool,
+    #[serde(default)]
+    pub animation: Option<AnimationMetadata>,
+    /// Nearest known place to the GPS position, if any.
+    #[serde(default)]
+    pub place: Option<Place>,
+    /// Color labels such as `Red`, from an XMP sidecar.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Text of the `<stem>.txt` caption sidecar.
+    #[serde(default)]
+    pub caption: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Place {
+    pub city: String,
+    pub region: String,
+    pub country_code: String,
+    pub country: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlaceCount {
+    pub place: Place,
+    pub count: usize,
+}
+
+/// Latitude/longitude box. `west` greater than `east` means the box crosses
+/// the antimeridian.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct GeoBounds {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+/// Geotagged files grouped for a map marker.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MapCluster {
+    /// Mean position of the files in the cluster.
+    pub latitude: f64,
+    pub longitude: f64,
+    pub count: usize,
+    /// Extent of the cluster, for zooming into it.
+    pub bounds: GeoBounds,
+    /// A file to show as the marker's thumbnail.
+    pub sample_id: String,
+}
+
+/// Order of a file listing.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileSort {
+    #[serde(default)]
+    pub key: SortKey,
+    #[serde(default)]
+    pub direction: SortDirection,
+    /// Seed for [`SortKey::Random`]; the same seed gives the same order.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    /// Case-insensitive, with digit runs compared by value (`img2` < `img10`).
+    Name,
+    Size,
+    /// EXIF or container capture date; files without one use their
+    /// modification date.
+    DateTaken,
+    #[default]
+    Modified,
+    /// Pixel count.
+    Dimensions,
+    Rating,
+    Random,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Restricts a file listing. Files without known dimensions never match a
+/// dimension filter.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FileFilter {
+    #[serde(default)]
+    pub orientation: Option<Orientation>,
+    #[serde(default)]
+    pub min_width: Option<u32>,
+    #[serde(default)]
+    pub min_height: Option<u32>,
+    /// Only geotagged files inside the box.
+    #[serde(default)]
+    pub bounds: Option<GeoBounds>,
+    #[serde(default)]
+    pub place: Option<PlaceFilter>,
+    /// Only members of this album; for a smart album, files matching its
+    /// query.
+    #[serde(default)]
+    pub album_id: Option<String>,
+    #[serde(default)]
+    pub search: Option<SearchQuery>,
+}
+
+/// Files reverse-geocoded to a country, optionally narrowed to a region and
+/// city within it.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct PlaceFilter {
+    pub country_code: String,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub city: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    /// Within 1% of square.
+    Square,
+}
+
+/// Position in a file listing: the sort value and id of the last item shown.
+/// Only meaningful with the sort it was returned for.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FileCursor {
+    pub key: SortValue,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SortValue {
+    Int(i64),
+    Text(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilePage {
+    pub files: Vec<FileMeta>,
+    /// `None` once the last page has been returned.
+    pub next_cursor: Option<FileCursor>,
+    /// Items in the whole listing, not just this page.
+    pub total: usize,
+}
+
+/// Period a timeline groups files by.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DateGranularity {
+    Year,
+    #[default]
+    Month,
+    Day,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DateBucket {
+    /// Local calendar period: `2024`, `2024-03` or `2024-03-15`.
+    pub period: String,
+    pub count: usize,
+}
+
+/// Where a date-taken listing reaches a given date.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DateJump {
+    /// Items listed before the date.
+    pub offset: usize,
+    /// Pass to `get_files` to start the page at the date; `None` when it
+    /// starts the listing.
+    pub cursor: Option<FileCursor>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    #[default]
+    Image,
+    Video,
+}
+
+impl MediaType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaType::Image => "image",
+            MediaType::Video => "video",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "video" => MediaType::Video,
+            _ => MediaType::Image,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VideoMetadata {
+    pub duration_ms: Option<u64>,
+    /// Short codec name such as `h264`, `hevc` or `prores`.
+    pub codec: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FileVariant {
+    pub id: String,
+    pub path: String,
+    pub file_type: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AnimationMetadata {
+    pub frame_count: u32,
+    /// Total play time of one loop.
+    pub duration_ms: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -30,6 +271,13 @@ pub struct Dimensions {
     pub height: u32,
 }
 
+impl Dimensions {
+    /// Width over height; `None` for a zero height.
+    pub fn aspect_ratio(&self) -> Option<f64> {
+        (self.height > 0).then(|| self.width as f64 / self.height as f64)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ImageMetadata {
     pub camera_make: Option<String>,
@@ -58,6 +306,215 @@ pub struct Resolution {
     pub unit: String,
 }
 
+/// What an XMP sidecar says about its image.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct XmpSidecar {
+    pub rating: Option<i32>,
+    pub labels: Vec<String>,
+    /// Tag paths, as in [`FileMeta::tags`].
+    pub keywords: Vec<String>,
+    pub description: Option<String>,
+}
+
+/// One step of a bulk caption edit. Steps run in order; the tag steps treat
+/// a caption as comma-separated tags.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum CaptionEdit {
+    /// Put `text`, e.g. a trigger word, in front, joined with `separator`
+    /// (`", "` if unset). Captions whose first tags already are `text` are
+    /// left alone.
+    Prepend {
+        text: String,
+        separator: Option<String>,
+    },
+    /// Like `Prepend`, at the end.
+    Append {
+        text: String,
+        separator: Option<String>,
+    },
+    /// Regex find-and-replace; `replacement` can refer to groups as `$1`.
+    Replace {
+        pattern: String,
+        replacement: String,
+    },
+    /// Drop repeated tags, keeping the first of each.
+    DedupeTags,
+    /// Sort tags alphabetically, leaving the first `keep_first` (say, a
+    /// trigger word) where they are.
+    SortTags {
+        #[serde(default)]
+        keep_first: usize,
+    },
+}
+
+/// A caption a bulk edit changes, or would change in a dry run.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CaptionChange {
+    pub file_id: String,
+    pub sidecar_path: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// Caption statistics of a training-dataset folder, from its caption
+/// sidecars read as comma-separated tags.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CaptionStats {
+    pub images: usize,
+    pub captioned: usize,
+    /// Tags by the number of captions using them, most used first.
+    pub tags: Vec<CaptionTagCount>,
+    /// Images with no caption sidecar, or an empty one.
+    pub uncaptioned: Vec<String>,
+    /// Images whose caption is over the token limit.
+    pub too_long: Vec<LongCaption>,
+    /// Caption sidecars without an image next to them.
+    pub orphans: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CaptionTagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LongCaption {
+    pub path: String,
+    pub tokens: usize,
+}
+
+/// Training layout a dataset export writes.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DatasetLayout {
+    /// kohya-ss `<repeats>_<concept>` folders with a `.txt` caption next to
+    /// each image.
+    Kohya,
+    /// HuggingFace `imagefolder` with a `metadata.jsonl` per split.
+    ImageFolder,
+    /// Images plus `annotations/captions_<split>.json` in COCO captions
+    /// format.
+    Coco,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeMode {
+    /// Scale down to fit inside the resolution, keeping the whole image.
+    #[default]
+    Fit,
+    /// Scale to cover the resolution and crop the overflow around the center.
+    Crop,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+/// Where and how a dataset export writes its files.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DatasetExportOptions {
+    pub layout: DatasetLayout,
+    pub output_dir: String,
+    /// Target size; unset keeps each image's own size.
+    #[serde(default)]
+    pub resolution: Option<Dimensions>,
+    #[serde(default)]
+    pub resize: ResizeMode,
+    /// Re-encode every image; unset keeps the source format where it can.
+    #[serde(default)]
+    pub format: Option<ExportFormat>,
+    /// Share of the files, from 0 to 1, held out for validation.
+    #[serde(default)]
+    pub validation_split: f64,
+    /// kohya repeats per epoch (1 if unset).
+    #[serde(default)]
+    pub repeats: Option<u32>,
+    /// kohya concept name (`dataset` if unset).
+    #[serde(default)]
+    pub concept: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DatasetExportSummary {
+    pub train: usize,
+    pub validation: usize,
+    /// Files left out: videos, and images that failed to decode.
+    pub skipped: Vec<String>,
+}
+
+/// Aspect-ratio buckets and quality limits for a dataset check.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct BucketOptions {
+    /// Buckets to assign to; when empty they are generated from
+    /// `resolution`, `min_side`, `max_side` and `step` the way kohya-ss does.
+    pub buckets: Vec<Dimensions>,
+    /// Generated buckets hold at most `resolution` squared pixels.
+    pub resolution: u32,
+    pub min_side: u32,
+    pub max_side: u32,
+    pub step: u32,
+    /// Images with a shorter side below this are too small.
+    pub min_resolution: u32,
+    /// Long side over short side beyond this is an extreme aspect ratio.
+    pub max_aspect_ratio: f64,
+    /// Decode every image in full to find corrupt files.
+    pub verify_decode: bool,
+}
+
+impl Default for BucketOptions {
+    fn default() -> Self {
+        Self {
+            buckets: Vec::new(),
+            resolution: 1024,
+            min_side: 256,
+            max_side: 2048,
+            step: 64,
+            min_resolution: 512,
+            max_aspect_ratio: 3.0,
+            verify_decode: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BucketCount {
+    pub bucket: Dimensions,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BucketAssignment {
+    pub file_id: String,
+    pub path: String,
+    pub dimensions: Dimensions,
+    pub bucket: Dimensions,
+}
+
+/// Where a dataset's images fall into aspect-ratio buckets, and which ones
+/// are unfit for training. Flagged images are listed by path.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BucketReport {
+    /// Every bucket, widest first, with the images assigned to it.
+    pub buckets: Vec<BucketCount>,
+    pub assignments: Vec<BucketAssignment>,
+    pub too_small: Vec<String>,
+    pub extreme_aspect: Vec<String>,
+    pub corrupt: Vec<String>,
+    /// Images with no stored size, when decoding was skipped.
+    pub unmeasured: Vec<String>,
+    /// Images with no stored size in a format this build can't decode
+    /// (HEIF and AVIF without the `heif` feature).
+    pub unsupported: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Album {
     pub id: String,
@@ -67,6 +524,16 @@ pub struct Album {
     pub created: String,
     pub modified: String,
     pub file_count: usize,
+    #[serde(default)]
+    pub kind: AlbumKind,
+    /// Capture time span of an event album.
+    #[serde(default)]
+    pub starts: Option<String>,
+    #[serde(default)]
+    pub ends: Option<String>,
+    /// What a smart album contains.
+    #[serde(default)]
+    pub query: Option<SearchQuery>,
 }
 
 impl Album {
@@ -80,6 +547,63 @@ impl Album {
             created: now.clone(),
             modified: now,
             file_count: 0,
+            kind: AlbumKind::Manual,
+            starts: None,
+            ends: None,
+            query: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AlbumKind {
+    /// Curated by the user.
+    #[default]
+    Manual,
+    /// Generated by event clustering; replaced on every run.
+    Event,
+    /// Files matching a saved [`SearchQuery`], evaluated on every listing.
+    Smart,
+}
+
+impl AlbumKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlbumKind::Manual => "manual",
+            AlbumKind::Event => "event",
+            AlbumKind::Smart => "smart",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "event" => AlbumKind::Event,
+            "smart" => AlbumKind::Smart,
+            _ => AlbumKind::Manual,
+        }
+    }
+}
+
+/// How event clustering splits the photo stream.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct EventOptions {
+    /// A longer pause between consecutive photos starts a new event.
+    pub max_gap_hours: f64,
+    /// Also start a new event when consecutive geotagged photos are further
+    /// apart than this.
+    pub max_distance_km: Option<f64>,
+    /// Smaller groups don't become events.
+    pub min_files: usize,
+}
+
+impl Default for EventOptions {
+    fn default() -> Self {
+        Self {
+            max_gap_hours: 8.0,
+            max_distance_km: None,
+            min_files: 5,
         }
     }
 }
@@ -87,9 +611,18 @@ impl Album {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Tag {
     pub id: String,
+    /// Last level of `path`.
     pub name: String,
     pub color: Option<String>,
     pub created: String,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Levels from the root joined by `|`, e.g. `Places|Europe|Paris`.
+    #[serde(default)]
+    pub path: String,
+    /// Files with this tag or one below it.
+    #[serde(default)]
+    pub file_count: usize,
 }
 
 // REMOVED "Default" from derive to avoid conflict with manual impl below
@@ -112,27 +645,41 @@ impl Default for AppSettings {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Conditions a file must all meet; unset fields match everything.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct SearchQuery {
+    /// Substring of the name, path or caption.
     pub query: Option<String>,
+    /// Extensions such as `png`, case-insensitive.
     pub file_types: Option<Vec<String>>,
+    /// Capture date, falling back to the modification date.
     pub date_range: Option<DateRange>,
     pub size_range: Option<SizeRange>,
+    /// Tag paths the file must all have, itself or a tag below them.
     pub tags: Option<Vec<String>>,
+    /// Member of any of these albums.
     pub albums: Option<Vec<String>>,
+    /// At least this many stars.
     pub rating: Option<i32>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Inclusive bounds, each either a timestamp or a whole local period as in
+/// [`DateBucket::period`]: `{"start": "2024", "end": "2024"}` is all of 2024.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct DateRange {
-    pub start: String,
-    pub end: String,
+    #[serde(default)]
+    pub start: Option<String>,
+    #[serde(default)]
+    pub end: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Inclusive bounds in bytes.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct SizeRange {
-    pub min: i64,
-    pub max: i64,
+    #[serde(default)]
+    pub min: Option<i64>,
+    #[serde(default)]
+    pub max: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -174,4 +721,4 @@ pub struct CropOperation {
     pub y: i32,
     pub width: i32,
     pub height: i32,
-}
\ No newline at end of file
+}
@@ -0,0 +1,580 @@
+//! Camera RAW support. Sensor data is never decoded: thumbnails come from the
+//! JPEG preview cameras embed, and dimensions/EXIF from the TIFF structure
+//! (CR2, NEF, ARW, DNG, ...) or the CR3 box tree.
+
+use crate::isobmff::{self, BoxHeader};
+use crate::metadata;
+use crate::models::ImageMetadata;
+use anyhow::{anyhow, Result};
+use image::metadata::Orientation;
+use image::DynamicImage;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Previews and EXIF blocks larger than this are not embedded previews.
+const MAX_PREVIEW_LEN: u64 = 64 * 1024 * 1024;
+/// TIFF-based RAWs keep their EXIF near the start; parse only this much.
+const EXIF_PREFIX_LEN: u64 = 4 * 1024 * 1024;
+/// Guard against IFD loops in malformed files.
+const MAX_IFDS: usize = 32;
+
+/// Canon CR3 box holding CMT1..4 (EXIF) and THMB.
+const CR3_META_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+/// Canon CR3 box holding the PRVW preview JPEG.
+const CR3_PREVIEW_UUID: [u8; 16] = [
+    0xea, 0xf4, 0x2b, 0x5e, 0x1c, 0x98, 0x4b, 0x88, 0xb9, 0xfb, 0xb7, 0xdc, 0x40, 0x6e, 0x4d, 0x16,
+];
+
+/// What we learn from walking a RAW file without decoding it.
+#[derive(Debug, Default)]
+struct RawInfo {
+    /// (offset, length) of embedded JPEGs, largest first.
+    previews: Vec<(u64, u64)>,
+    dimensions: Option<(u32, u32)>,
+    orientation: Option<u8>,
+}
+
+/// Decode the largest embedded preview that the JPEG decoder accepts and
+/// apply the RAW's orientation to it.
+pub fn decode_preview(path: &Path) -> Result<DynamicImage> {
+    let info = inspect(path)?;
+    let mut file = File::open(path)?;
+    for &(offset, len) in &info.previews {
+        let mut buf = vec![0u8; len as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        if file.read_exact(&mut buf).is_err() || !buf.starts_with(&[0xFF, 0xD8]) {
+            continue;
+        }
+        if let Ok(mut img) = image::load_from_memory_with_format(&buf, image::ImageFormat::Jpeg) {
+            if let Some(o) = info.orientation.and_then(Orientation::from_exif) {
+                img.apply_orientation(o);
+            }
+            return Ok(img);
+        }
+    }
+    Err(anyhow!("No decodable preview in {}", path.display()))
+}
+
+/// Full sensor output size, already rotated for display.
+pub fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    inspect(path).ok()?.dimensions
+}
+
+pub fn read_metadata(path: &Path) -> Option<ImageMetadata> {
+    if is_cr3(path) {
+        return metadata::read_exif_blocks(cr3_exif_blocks(path).ok()?);
+    }
+    let mut prefix = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(EXIF_PREFIX_LEN)
+        .read_to_end(&mut prefix)
+        .ok()?;
+    metadata::read_exif_blocks(vec![prefix])
+}
+
+fn inspect(path: &Path) -> Result<RawInfo> {
+    let mut info = if is_cr3(path) {
+        inspect_cr3(path)?
+    } else {
+        inspect_tiff(path)?
+    };
+    info.previews
+        .retain(|&(_, len)| len > 2 && len <= MAX_PREVIEW_LEN);
+    info.previews
+        .sort_by_key(|&(offset, len)| (Reverse(len), offset));
+    info.previews.dedup();
+    if let (Some((w, h)), Some(5..=8)) = (info.dimensions, info.orientation) {
+        info.dimensions = Some((h, w));
+    }
+    Ok(info)
+}
+
+fn is_cr3(path: &Path) -> bool {
+    let mut header = [0u8; 12];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .is_ok()
+        && isobmff::brands(&header).first() == Some(b"crx ")
+}
+
+// --- TIFF-based RAW (CR2, NEF, NRW, ARW, DNG, PEF, SRW) ---
+
+struct TiffReader<R> {
+    r: R,
+    little_endian: bool,
+}
+
+struct IfdEntry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// Absolute offset of the value, inline or not.
+    value_at: u64,
+}
+
+impl<R: Read + Seek> TiffReader<R> {
+    fn u16(&mut self) -> io::Result<u16> {
+        let mut b = [0u8; 2];
+        self.r.read_exact(&mut b)?;
+        Ok(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut b = [0u8; 4];
+        self.r.read_exact(&mut b)?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    /// Returns the entries of the IFD at `offset` and the offset of the next one.
+    fn read_ifd(&mut self, offset: u64) -> io::Result<(Vec<IfdEntry>, u32)> {
+        self.r.seek(SeekFrom::Start(offset))?;
+        let count = self.u16()?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for i in 0..count as u64 {
+            let entry_at = offset + 2 + i * 12;
+            self.r.seek(SeekFrom::Start(entry_at))?;
+            let tag = self.u16()?;
+            let kind = self.u16()?;
+            let count = self.u32()?;
+            let size = type_size(kind) as u64 * count as u64;
+            let value_at = if size <= 4 {
+                entry_at + 8
+            } else {
+                self.u32()? as u64
+            };
+            entries.push(IfdEntry {
+                tag,
+                kind,
+                count,
+                value_at,
+            });
+        }
+        self.r
+            .seek(SeekFrom::Start(offset + 2 + count as u64 * 12))?;
+        let next = self.u32()?;
+        Ok((entries, next))
+    }
+
+    /// Integer values of a SHORT/LONG/IFD (or RATIONAL, as num/den) entry.
+    fn values(&mut self, e: &IfdEntry) -> io::Result<Vec<u32>> {
+        let mut out = Vec::new();
+        self.r.seek(SeekFrom::Start(e.value_at))?;
+        for _ in 0..e.count.min(64) {
+            out.push(match e.kind {
+                3 => self.u16()? as u32,
+                4 | 9 | 13 => self.u32()?,
+                5 | 10 => {
+                    let num = self.u32()?;
+                    let den = self.u32()?;
+                    num.checked_div(den).unwrap_or(0)
+                }
+                _ => return Ok(out),
+            });
+        }
+        Ok(out)
+    }
+
+    fn first(&mut self, entries: &[IfdEntry], tag: u16) -> io::Result<Option<u32>> {
+        match entries.iter().find(|e| e.tag == tag) {
+            Some(e) => Ok(self.values(e)?.first().copied()),
+            None => Ok(None),
+        }
+    }
+}
+
+fn type_size(kind: u16) -> u32 {
+    match kind {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+fn inspect_tiff(path: &Path) -> Result<RawInfo> {
+    let mut r = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 8];
+    r.read_exact(&mut header)?;
+    let little_endian = match &header[..4] {
+        [b'I', b'I', 0x2A, 0x00] => true,
+        [b'M', b'M', 0x00, 0x2A] => false,
+        _ => return Err(anyhow!("Not a TIFF-based RAW: {}", path.display())),
+    };
+    let mut t = TiffReader { r, little_endian };
+    let ifd0 = if little_endian {
+        u32::from_le_bytes([header[4], header[5], header[6], header[7]])
+    } else {
+        u32::from_be_bytes([header[4], header[5], header[6], header[7]])
+    };
+
+    let mut info = RawInfo::default();
+    let mut pixel_size: Option<(u32, u32)> = None;
+    let mut largest_full: Option<(u32, u32)> = None;
+    let mut queue = vec![ifd0 as u64];
+    let mut seen = HashSet::new();
+
+    while let Some(offset) = queue.pop() {
+        if offset == 0 || !seen.insert(offset) || seen.len() > MAX_IFDS {
+            continue;
+        }
+        let (entries, next) = match t.read_ifd(offset) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        queue.push(next as u64);
+        if offset == ifd0 as u64 {
+            info.orientation = t.first(&entries, 0x0112)?.map(|o| o as u8);
+        }
+
+        // SubIFDs (0x014A) hold NEF/ARW/DNG previews and raw data; the Exif
+        // IFD (0x8769) holds the pixel dimensions.
+        for tag in [0x014A, 0x8769] {
+            if let Some(e) = entries.iter().find(|e| e.tag == tag) {
+                queue.extend(t.values(e)?.into_iter().map(|o| o as u64));
+            }
+        }
+        if let (Some(w), Some(h)) = (t.first(&entries, 0xA002)?, t.first(&entries, 0xA003)?) {
+            pixel_size = Some((w, h));
+        }
+
+        // JPEGInterchangeFormat / JPEGInterchangeFormatLength
+        if let (Some(off), Some(len)) = (t.first(&entries, 0x0201)?, t.first(&entries, 0x0202)?) {
+            info.previews.push((off as u64, len as u64));
+        }
+
+        let compression = t.first(&entries, 0x0103)?;
+        let photometric = t.first(&entries, 0x0106)?;
+        // Single-strip JPEG images that are not CFA/linear raw data (CR2's IFD0, DNG previews)
+        let is_raw_data = matches!(photometric, Some(32803) | Some(34892));
+        if matches!(compression, Some(6) | Some(7)) && !is_raw_data {
+            let offsets = match entries.iter().find(|e| e.tag == 0x0111) {
+                Some(e) => t.values(e)?,
+                None => Vec::new(),
+            };
+            let counts = match entries.iter().find(|e| e.tag == 0x0117) {
+                Some(e) => t.values(e)?,
+                None => Vec::new(),
+            };
+            if let ([off], [len]) = (&offsets[..], &counts[..]) {
+                info.previews.push((*off as u64, *len as u64));
+            }
+        }
+
+        // Full-resolution image size; DNG's DefaultCropSize trims the margins
+        let subfile_type = t.first(&entries, 0x00FE)?.unwrap_or(0);
+        if subfile_type == 0 {
+            let crop = match entries.iter().find(|e| e.tag == 0xC620) {
+                Some(e) => t.values(e)?,
+                None => Vec::new(),
+            };
+            let size = match crop[..] {
+                [w, h] => Some((w, h)),
+                _ => match (t.first(&entries, 0x0100)?, t.first(&entries, 0x0101)?) {
+                    (Some(w), Some(h)) => Some((w, h)),
+                    _ => None,
+                },
+            };
+            if let Some((w, h)) = size {
+                if largest_full.is_none_or(|(lw, lh)| w as u64 * h as u64 > lw as u64 * lh as u64) {
+                    largest_full = Some((w, h));
+                }
+            }
+        }
+    }
+
+    info.dimensions = pixel_size.or(largest_full);
+    Ok(info)
+}
+
+// --- Canon CR3 ---
+
+fn uuid_box<R: Read + Seek>(
+    r: &mut R,
+    boxes: &[BoxHeader],
+    uuid: &[u8; 16],
+) -> io::Result<Option<BoxHeader>> {
+    for b in boxes.iter().filter(|b| b.is(b"uuid")) {
+        let mut id = [0u8; 16];
+        r.seek(SeekFrom::Start(b.start))?;
+        r.read_exact(&mut id)?;
+        if &id == uuid {
+            return Ok(Some(*b));
+        }
+    }
+    Ok(None)
+}
+
+fn cr3_meta_boxes(path: &Path) -> Result<(BufReader<File>, Vec<BoxHeader>, Vec<BoxHeader>)> {
+    let (mut r, top) = isobmff::open(path)?;
+    let moov = *isobmff::find(&top, b"moov").ok_or_else(|| anyhow!("CR3 without moov"))?;
+    let moov_kids = isobmff::children(&mut r, &moov, 0)?;
+    let meta = uuid_box(&mut r, &moov_kids, &CR3_META_UUID)?
+        .map(|b| isobmff::children(&mut r, &b, 16))
+        .transpose()?
+        .unwrap_or_default();
+    Ok((r, moov_kids, meta))
+}
+
+fn cr3_exif_blocks(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let (mut r, _, meta) = cr3_meta_boxes(path)?;
+    let mut blocks = Vec::new();
+    // CMT1 is IFD0 (make, model, orientation), CMT2 the Exif IFD, CMT4 GPS
+    for kind in [b"CMT1", b"CMT2", b"CMT4"] {
+        if let Some(b) = isobmff::find(&meta, kind) {
+            let len = b.end - b.start;
+            if len > MAX_PREVIEW_LEN {
+                continue;
+            }
+            let mut buf = vec![0u8; len as usize];
+            r.seek(SeekFrom::Start(b.start))?;
+            r.read_exact(&mut buf)?;
+            blocks.push(buf);
+        }
+    }
+    Ok(blocks)
+}
+
+fn inspect_cr3(path: &Path) -> Result<RawInfo> {
+    let (mut r, moov_kids, meta) = cr3_meta_boxes(path)?;
+    let mut info = RawInfo::default();
+
+    // The first track is the full-size JPEG; its sample entry carries the size
+    if let Some(trak) = isobmff::find(&moov_kids, b"trak") {
+        info.dimensions = isobmff::track_sample_size(&mut r, trak)?;
+    }
+
+    if let Some(cmt1) = isobmff::find(&meta, b"CMT1") {
+        let len = (cmt1.end - cmt1.start).min(MAX_PREVIEW_LEN);
+        let mut buf = vec![0u8; len as usize];
+        r.seek(SeekFrom::Start(cmt1.start))?;
+        r.read_exact(&mut buf)?;
+        let little_endian = buf.starts_with(b"II");
+        let mut t = TiffReader {
+            r: io::Cursor::new(buf),
+            little_endian,
+        };
+        t.r.seek(SeekFrom::Start(4))?;
+        let ifd0 = t.u32()? as u64;
+        if let Ok((entries, _)) = t.read_ifd(ifd0) {
+            info.orientation = t.first(&entries, 0x0112)?.map(|o| o as u8);
+        }
+    }
+
+    // THMB: 160x120 thumbnail in the metadata box
+    if let Some(thmb) = isobmff::find(&meta, b"THMB") {
+        // version/flags, width, height, then the JPEG length
+        r.seek(SeekFrom::Start(thmb.start + 8))?;
+        let len = isobmff::read_u32(&mut r)? as u64;
+        info.previews.push((thmb.start + 16, len));
+    }
+
+    // PRVW: ~1620px preview in a top-level uuid box after moov
+    let file_len = r.get_ref().metadata()?.len();
+    let top = isobmff::read_boxes(&mut r, 0, file_len)?;
+    if let Some(b) = uuid_box(&mut r, &top, &CR3_PREVIEW_UUID)? {
+        // uuid, then 8 bytes before the PRVW box
+        let inner = isobmff::read_boxes(&mut r, b.start + 24, b.end)?;
+        if let Some(prvw) = isobmff::find(&inner, b"PRVW") {
+            // unknown u32 + u16, width, height, unknown u16, then the JPEG length
+            r.seek(SeekFrom::Start(prvw.start + 12))?;
+            let len = isobmff::read_u32(&mut r)? as u64;
+            info.previews.push((prvw.start + 16, len));
+        }
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian IFD, each entry holding one inline value.
+    fn ifd(entries: &[(u16, u16, u32)], next: u32) -> Vec<u8> {
+        let mut out = (entries.len() as u16).to_le_bytes().to_vec();
+        for &(tag, kind, value) in entries {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&next.to_le_bytes());
+        out
+    }
+
+    fn ifd_len(entries: usize) -> u32 {
+        2 + 12 * entries as u32 + 4
+    }
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut io::Cursor::new(&mut out), image::ImageFormat::Jpeg)
+            .unwrap();
+        out
+    }
+
+    /// A NEF-like file: IFD0 with the orientation and a preview, and a
+    /// SubIFD with the full size that points at the same preview.
+    fn tiff_raw(orientation: u32, preview: &[u8]) -> Vec<u8> {
+        let sub_at = 8 + ifd_len(4);
+        let preview_at = sub_at + ifd_len(5);
+        let len = preview.len() as u32;
+        [
+            b"II\x2A\x00\x08\x00\x00\x00".to_vec(),
+            ifd(
+                &[
+                    (0x0112, 3, orientation),
+                    (0x014A, 4, sub_at),
+                    (0x0201, 4, preview_at),
+                    (0x0202, 4, len),
+                ],
+                0,
+            ),
+            ifd(
+                &[
+                    (0x00FE, 4, 0),
+                    (0x0100, 4, 6000),
+                    (0x0101, 4, 4000),
+                    (0x0201, 4, preview_at),
+                    (0x0202, 4, len),
+                ],
+                0,
+            ),
+            preview.to_vec(),
+        ]
+        .concat()
+    }
+
+    fn with_file<T>(ext: &str, bytes: &[u8], f: impl FnOnce(&Path) -> T) -> T {
+        let path =
+            std::env::temp_dir().join(format!("lumous-raw-{}.{}", uuid::Uuid::new_v4(), ext));
+        std::fs::write(&path, bytes).unwrap();
+        let out = f(&path);
+        let _ = std::fs::remove_file(&path);
+        out
+    }
+
+    #[test]
+    fn tiff_raw_size_is_rotated_and_previews_deduplicated() {
+        let preview = jpeg(8, 4);
+        let info = with_file("nef", &tiff_raw(6, &preview), inspect).unwrap();
+        assert_eq!(info.dimensions, Some((4000, 6000)));
+        assert_eq!(info.orientation, Some(6));
+        assert_eq!(info.previews.len(), 1);
+
+        let img = with_file("nef", &tiff_raw(6, &preview), decode_preview).unwrap();
+        assert_eq!((img.width(), img.height()), (4, 8));
+    }
+
+    #[test]
+    fn previews_are_largest_first() {
+        let mut bytes = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        bytes.extend(ifd(
+            &[
+                (0x0201, 4, 100),
+                (0x0202, 4, 10),
+                (0x014A, 4, 8 + ifd_len(3)),
+            ],
+            0,
+        ));
+        bytes.extend(ifd(&[(0x0201, 4, 200), (0x0202, 4, 50)], 0));
+        let info = with_file("nef", &bytes, inspect).unwrap();
+        assert_eq!(info.previews, [(200, 50), (100, 10)]);
+    }
+
+    #[test]
+    fn zero_denominators_read_as_zero() {
+        // 7/0, then 9/3
+        let bytes = [7u32, 0, 9, 3].map(u32::to_le_bytes).concat();
+        let mut t = TiffReader {
+            r: io::Cursor::new(bytes),
+            little_endian: true,
+        };
+        let entry = IfdEntry {
+            tag: 0x829A,
+            kind: 5,
+            count: 2,
+            value_at: 0,
+        };
+        assert_eq!(t.values(&entry).unwrap(), [0, 3]);
+    }
+
+    #[test]
+    fn ifd_loops_and_truncated_files_end_the_walk() {
+        // IFD0 names itself as the next IFD and as its own SubIFD
+        let mut bytes = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        bytes.extend(ifd(
+            &[(0x014A, 4, 8), (0x0100, 4, 640), (0x0101, 4, 480)],
+            8,
+        ));
+        let info = with_file("nef", &bytes, inspect).unwrap();
+        assert_eq!(info.dimensions, Some((640, 480)));
+
+        let full = tiff_raw(1, &jpeg(8, 4));
+        for len in [8, 20, 60, 100] {
+            let info = with_file("nef", &full[..len], inspect).unwrap();
+            assert_eq!(info.dimensions, None, "{len} bytes");
+        }
+        // The preview is cut off, so there is nothing to decode
+        let cut = &full[..full.len() - 10];
+        assert!(with_file("nef", cut, decode_preview).is_err());
+        assert!(with_file("nef", b"not a raw", inspect).is_err());
+    }
+
+    fn bx(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[test]
+    fn cr3_orientation_and_thumbnail_come_from_the_meta_box() {
+        let thumb = jpeg(16, 12);
+        let cmt1 = [
+            b"II\x2A\x00\x08\x00\x00\x00".to_vec(),
+            ifd(&[(0x0112, 3, 8)], 0),
+        ]
+        .concat();
+        let mut thmb = vec![0u8; 8];
+        thmb.extend_from_slice(&(thumb.len() as u32).to_be_bytes());
+        thmb.extend_from_slice(&[0; 4]);
+        thmb.extend_from_slice(&thumb);
+        let meta = [
+            CR3_META_UUID.to_vec(),
+            bx(b"CMT1", &cmt1),
+            bx(b"THMB", &thmb),
+        ]
+        .concat();
+        let bytes = [
+            bx(b"ftyp", b"crx \0\0\0\x01crx isom"),
+            bx(b"moov", &bx(b"uuid", &meta)),
+        ]
+        .concat();
+
+        let info = with_file("cr3", &bytes, inspect).unwrap();
+        assert_eq!(info.orientation, Some(8));
+        assert_eq!(info.previews.len(), 1);
+        let img = with_file("cr3", &bytes, decode_preview).unwrap();
+        assert_eq!((img.width(), img.height()), (12, 16));
+
+        // No moov box
+        let bytes = bx(b"ftyp", b"crx \0\0\0\x01crx isom");
+        assert!(with_file("cr3", &bytes, inspect).is_err());
+    }
+}
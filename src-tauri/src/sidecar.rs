@@ -0,0 +1,438 @@
+//! Caption and JSON sidecars next to images, as kept by ML dataset tools:
+//! `IMG_1.txt` (or `IMG_1.caption.txt`, `IMG_1.md`) and `IMG_1.json`.
+
+use crate::formats;
+use crate::models::{CaptionEdit, CaptionStats, CaptionTagCount, LongCaption, MediaType};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Caption sidecar suffixes, in lookup order. New captions use the first.
+const CAPTION_SUFFIXES: [&str; 3] = [".txt", ".caption.txt", ".md"];
+
+/// CLIP's 77-token context less its start and end tokens.
+pub const DEFAULT_MAX_TOKENS: usize = 75;
+
+static TOKEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\w+|[^\w\s]").unwrap());
+
+/// The caption sidecar of `image`, if there is one.
+pub fn caption_path(image: &Path) -> Option<PathBuf> {
+    let stem = image.file_stem()?.to_string_lossy();
+    CAPTION_SUFFIXES
+        .iter()
+        .map(|suffix| image.with_file_name(format!("{}{}", stem, suffix)))
+        .find(|p| p.is_file())
+}
+
+/// Text of the caption sidecar of `image`; `None` if it has none or it can't
+/// be read.
+pub fn read_caption(image: &Path) -> Option<String> {
+    fs::read_to_string(caption_path(image)?).ok()
+}
+
+/// Replace the caption of `image`, keeping the existing sidecar's naming or
+/// creating `<stem>.txt`. Returns the sidecar's path.
+pub fn write_caption(image: &Path, caption: &str, backup: bool) -> Result<PathBuf> {
+    let path = match caption_path(image) {
+        Some(path) => path,
+        None => with_suffix(image, CAPTION_SUFFIXES[0])?,
+    };
+    write_atomic(&path, caption.as_bytes(), backup)?;
+    Ok(path)
+}
+
+/// [`CaptionEdit`]s with their patterns compiled, ready to run over many
+/// captions.
+pub struct CaptionEditor {
+    steps: Vec<Step>,
+}
+
+enum Step {
+    Prepend(String, String),
+    Append(String, String),
+    Replace(Regex, String),
+    DedupeTags,
+    SortTags(usize),
+}
+
+impl CaptionEditor {
+    pub fn new(edits: &[CaptionEdit]) -> Result<Self> {
+        let separator = |s: &Option<String>| s.clone().unwrap_or_else(|| ", ".to_string());
+        let steps = edits
+            .iter()
+            .map(|edit| {
+                Ok(match edit {
+                    CaptionEdit::Prepend { text, separator: s } => {
+                        Step::Prepend(text.clone(), separator(s))
+                    }
+                    CaptionEdit::Append { text, separator: s } => {
+                        Step::Append(text.clone(), separator(s))
+                    }
+                    CaptionEdit::Replace {
+                        pattern,
+                        replacement,
+                    } => Step::Replace(
+                        Regex::new(pattern)
+                            .map_err(|e| anyhow!("Invalid pattern {:?}: {}", pattern, e))?,
+                        replacement.clone(),
+                    ),
+                    CaptionEdit::DedupeTags => Step::DedupeTags,
+                    CaptionEdit::SortTags { keep_first } => Step::SortTags(*keep_first),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { steps })
+    }
+
+    /// `caption` with every step applied. Trailing whitespace, such as a
+    /// final newline, is kept as it was.
+    pub fn apply(&self, caption: &str) -> String {
+        let body = caption.trim_end();
+        let mut text = body.to_string();
+        for step in &self.steps {
+            text = match step {
+                Step::Prepend(prefix, _) if split_tags(&text).starts_with(&split_tags(prefix)) => {
+                    text
+                }
+                Step::Prepend(prefix, _) if text.is_empty() => prefix.clone(),
+                Step::Prepend(prefix, separator) => format!("{}{}{}", prefix, separator, text),
+                Step::Append(suffix, _) if split_tags(&text).ends_with(&split_tags(suffix)) => text,
+                Step::Append(suffix, _) if text.is_empty() => suffix.clone(),
+                Step::Append(suffix, separator) => format!("{}{}{}", text, separator, suffix),
+                Step::Replace(pattern, replacement) => pattern
+                    .replace_all(&text, replacement.as_str())
+                    .into_owned(),
+                Step::DedupeTags => {
+                    let mut tags: Vec<&str> = Vec::new();
+                    for tag in split_tags(&text) {
+                        if !tags.contains(&tag) {
+                            tags.push(tag);
+                        }
+                    }
+                    tags.join(", ")
+                }
+                Step::SortTags(keep_first) => {
+                    let mut tags = split_tags(&text);
+                    let start = (*keep_first).min(tags.len());
+                    tags[start..].sort_by_cached_key(|t| t.to_lowercase());
+                    tags.join(", ")
+                }
+            };
+        }
+        text + &caption[body.len()..]
+    }
+}
+
+/// Non-empty comma-separated tags of `caption`, trimmed.
+fn split_tags(caption: &str) -> Vec<&str> {
+    caption
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Rough token count of `caption` for a CLIP-style text encoder: each word
+/// and punctuation mark counts once. Real tokenizers split rare words
+/// further, so this errs low.
+pub fn token_count(caption: &str) -> usize {
+    TOKEN.find_iter(caption).count()
+}
+
+/// Caption statistics of the images directly in `folder`; see
+/// [`CaptionStats`]. A tag used twice in one caption counts once.
+pub fn caption_stats(folder: &Path, max_tokens: usize) -> Result<CaptionStats> {
+    let mut images = Vec::new();
+    // Each sidecar with the image stems it could belong to: `a.caption.txt`
+    // is the `.caption.txt` of `a` or the `.txt` of `a.caption`
+    let mut sidecars: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let stems: Vec<String> = CAPTION_SUFFIXES
+            .iter()
+            .filter_map(|suffix| name.strip_suffix(suffix))
+            .filter(|stem| !stem.is_empty())
+            .map(str::to_string)
+            .collect();
+        if !stems.is_empty() {
+            sidecars.push((path, stems));
+        } else if formats::detect(&path).is_some_and(|f| f.media_type() == MediaType::Image) {
+            images.push(path);
+        }
+    }
+    images.sort();
+
+    let image_stems: HashSet<String> = images
+        .iter()
+        .filter_map(|p| p.file_stem())
+        .map(|s| s.to_string_lossy().into_owned())
+        .collect();
+    let mut orphans: Vec<String> = sidecars
+        .into_iter()
+        .filter(|(_, stems)| !stems.iter().any(|s| image_stems.contains(s)))
+        .map(|(path, _)| path.to_string_lossy().into_owned())
+        .collect();
+    orphans.sort();
+
+    let mut stats = CaptionStats {
+        images: images.len(),
+        orphans,
+        ..Default::default()
+    };
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for image in &images {
+        let path = image.to_string_lossy().into_owned();
+        let Some(caption) = read_caption(image).filter(|c| !c.trim().is_empty()) else {
+            stats.uncaptioned.push(path);
+            continue;
+        };
+        stats.captioned += 1;
+        let tags: HashSet<&str> = split_tags(&caption).into_iter().collect();
+        for tag in tags {
+            *counts.entry(tag.to_string()).or_default() += 1;
+        }
+        let tokens = token_count(&caption);
+        if tokens > max_tokens {
+            stats.too_long.push(LongCaption { path, tokens });
+        }
+    }
+    stats.tags = counts
+        .into_iter()
+        .map(|(tag, count)| CaptionTagCount { tag, count })
+        .collect();
+    stats
+        .tags
+        .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    Ok(stats)
+}
+
+/// Where the JSON sidecar of `image` is, whether or not it exists.
+pub fn json_path(image: &Path) -> Result<PathBuf> {
+    with_suffix(image, ".json")
+}
+
+/// Replace the JSON sidecar of `image`. A file that was on one line stays on
+/// one line; anything else is pretty-printed. Returns the sidecar's path.
+pub fn write_json(image: &Path, value: &serde_json::Value, backup: bool) -> Result<PathBuf> {
+    let path = json_path(image)?;
+    let compact = fs::read_to_string(&path).is_ok_and(|old| !old.trim_end().contains('\n'));
+    let mut contents = if compact {
+        serde_json::to_string(value)?
+    } else {
+        serde_json::to_string_pretty(value)?
+    };
+    contents.push('\n');
+    write_atomic(&path, contents.as_bytes(), backup)?;
+    Ok(path)
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it over
+/// `path`, so readers never see a half-written file. With `backup`, the
+/// previous version is kept as `<file>.bak`.
+pub fn write_atomic(path: &Path, contents: &[u8], backup: bool) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!("{}.tmp", name));
+    fs::write(&tmp, contents)?;
+    if backup && path.is_file() {
+        if let Err(e) = fs::copy(path, path.with_file_name(format!("{}.bak", name))) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+    }
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+fn with_suffix(image: &Path, suffix: &str) -> Result<PathBuf> {
+    let stem = image
+        .file_stem()
+        .ok_or_else(|| anyhow!("No file name in {}", image.display()))?;
+    Ok(image.with_file_name(format!("{}{}", stem.to_string_lossy(), suffix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepend(text: &str) -> CaptionEdit {
+        CaptionEdit::Prepend {
+            text: text.into(),
+            separator: None,
+        }
+    }
+
+    fn append(text: &str) -> CaptionEdit {
+        CaptionEdit::Append {
+            text: text.into(),
+            separator: None,
+        }
+    }
+
+    fn replace(pattern: &str, replacement: &str) -> CaptionEdit {
+        CaptionEdit::Replace {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+        }
+    }
+
+    #[test]
+    fn caption_editor_applies_each_edit() {
+        let cases: Vec<(Vec<CaptionEdit>, &str, &str)> = vec![
+            (vec![prepend("ohwx")], "man, beach", "ohwx, man, beach"),
+            (vec![prepend("ohwx")], "ohwx, man", "ohwx, man"),
+            (vec![prepend("ohwx")], " ohwx ,man", " ohwx ,man"),
+            // A tag that merely starts with the trigger word isn't it
+            (vec![prepend("ohwx")], "ohwxyz, man", "ohwx, ohwxyz, man"),
+            (
+                vec![prepend("ohwx, man")],
+                "ohwx, man, beach",
+                "ohwx, man, beach",
+            ),
+            (vec![prepend("ohwx")], "", "ohwx"),
+            (
+                vec![CaptionEdit::Prepend {
+                    text: "ohwx".into(),
+                    separator: Some(" ".into()),
+                }],
+                "a man",
+                "ohwx a man",
+            ),
+            (
+                vec![append("film grain")],
+                "man, beach",
+                "man, beach, film grain",
+            ),
+            (
+                vec![append("film grain")],
+                "man,film grain",
+                "man,film grain",
+            ),
+            (
+                vec![append("grain")],
+                "man, film grain",
+                "man, film grain, grain",
+            ),
+            (vec![append("film grain")], "", "film grain"),
+            (
+                vec![replace(r"\bman\b", "person")],
+                "man, woman, man",
+                "person, woman, person",
+            ),
+            (
+                vec![replace(r"(\w+) hair", "hair $1")],
+                "red hair",
+                "hair red",
+            ),
+            (vec![CaptionEdit::DedupeTags], "a, b,a, c, b", "a, b, c"),
+            (vec![CaptionEdit::DedupeTags], "a,, b", "a, b"),
+            (
+                vec![CaptionEdit::SortTags { keep_first: 0 }],
+                "b, C, a",
+                "a, b, C",
+            ),
+            (
+                vec![CaptionEdit::SortTags { keep_first: 1 }],
+                "ohwx, b, a",
+                "ohwx, a, b",
+            ),
+            (
+                vec![CaptionEdit::SortTags { keep_first: 5 }],
+                "b, a",
+                "b, a",
+            ),
+            // Steps run in order
+            (
+                vec![
+                    CaptionEdit::DedupeTags,
+                    CaptionEdit::SortTags { keep_first: 0 },
+                    prepend("ohwx"),
+                ],
+                "b, a, b",
+                "ohwx, a, b",
+            ),
+        ];
+        for (edits, before, after) in cases {
+            let editor = CaptionEditor::new(&edits).unwrap();
+            assert_eq!(editor.apply(before), after, "{:?} on {:?}", edits, before);
+        }
+    }
+
+    #[test]
+    fn caption_editor_keeps_trailing_whitespace() {
+        let editor = CaptionEditor::new(&[append("ohwx"), CaptionEdit::DedupeTags]).unwrap();
+        assert_eq!(editor.apply("man, man\n"), "man, ohwx\n");
+        assert_eq!(editor.apply("man\r\n"), "man, ohwx\r\n");
+        assert_eq!(editor.apply("\n"), "ohwx\n");
+    }
+
+    #[test]
+    fn caption_editor_rejects_invalid_patterns() {
+        assert!(CaptionEditor::new(&[replace("(", "")]).is_err());
+    }
+
+    #[test]
+    fn caption_stats_counts_a_folder() {
+        let dir = std::env::temp_dir().join(format!("lumous-sidecar-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        let files = [
+            // A tag repeated in one caption counts once
+            ("a.png", ""),
+            ("a.caption.txt", "ohwx, man, man, beach"),
+            ("b.png", ""),
+            ("b.txt", "ohwx, woman"),
+            // Blank captions count as missing
+            ("c.png", ""),
+            ("c.txt", "  \n"),
+            ("d.jpg", ""),
+            // `.txt` of the image `z.caption`, and over the limit
+            ("z.caption.jpg", ""),
+            ("z.caption.txt", "ohwx, one, two, three, four"),
+            ("q.caption.txt", "lost"),
+            ("notes.md", "lost"),
+            ("nested/e.png", ""),
+            ("nested/e.txt", "ohwx"),
+        ];
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let stats = caption_stats(&dir, 7).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let in_dir = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        assert_eq!(stats.images, 5);
+        assert_eq!(stats.captioned, 3);
+        assert_eq!(stats.uncaptioned, [in_dir("c.png"), in_dir("d.jpg")]);
+        assert_eq!(stats.orphans, [in_dir("notes.md"), in_dir("q.caption.txt")]);
+        let too_long: Vec<_> = stats.too_long.iter().map(|l| (&l.path, l.tokens)).collect();
+        assert_eq!(too_long, [(&in_dir("z.caption.jpg"), 9)]);
+        let tags: Vec<_> = stats
+            .tags
+            .iter()
+            .map(|t| (t.tag.as_str(), t.count))
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                ("ohwx", 3),
+                ("beach", 1),
+                ("four", 1),
+                ("man", 1),
+                ("one", 1),
+                ("three", 1),
+                ("two", 1),
+                ("woman", 1),
+            ]
+        );
+    }
+}
@@ -1,46 +1,38 @@
+use crate::formats::{self, Format};
+use crate::{animation, raw, video};
 use anyhow::{anyhow, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
 use image::codecs::jpeg::JpegEncoder;
+use image::{Delay, DynamicImage, Frame};
 use sha2::{Digest, Sha256};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Animated previews keep at most this many frames; longer animations are
+/// sampled evenly, with each kept frame holding for the skipped ones.
+const MAX_PREVIEW_FRAMES: u32 = 48;
 
 pub async fn generate_thumbnail(file_path: &str, size: u32) -> Result<String> {
     let file_path = file_path.to_string();
     let res = tokio::task::spawn_blocking(move || {
         let source = Path::new(&file_path);
+        let format = match formats::detect(source) {
+            Some(format) if format.capabilities().thumbnail => format,
+            _ => return Err(anyhow!("No thumbnail decoder for {}", file_path)),
+        };
 
         // Create thumbnails directory if it doesn't exist
         let thumbnails_dir = get_thumbnails_dir()?;
         std::fs::create_dir_all(&thumbnails_dir)?;
 
         // Generate thumbnail filename based on full path hash + size to avoid collisions
-        let mut hasher = Sha256::new();
-        hasher.update(file_path.as_bytes());
-        let hash = hasher.finalize();
-        let short = &hex::encode(hash)[..16];
-        let thumbnail_filename = format!("{}_{}.jpg", short, size);
-        let thumbnail_path = thumbnails_dir.join(thumbnail_filename);
-
-        // Check if thumbnail exists and is fresh
-        if thumbnail_path.exists() {
-            // Get metadata for both source and thumbnail
-            // Use if let to safely handle cases where file might be locked or unreadable
-            if let (Ok(src_meta), Ok(thumb_meta)) = (
-                std::fs::metadata(source),
-                std::fs::metadata(&thumbnail_path),
-            ) {
-                if let (Ok(src_time), Ok(thumb_time)) = (src_meta.modified(), thumb_meta.modified())
-                {
-                    // If thumbnail is newer or equal to source, return it
-                    if thumb_time >= src_time {
-                        return Ok(thumbnail_path.to_string_lossy().to_string());
-                    }
-                }
-            }
-            // If we fall through here, the thumbnail is stale or metadata failed, so we regenerate.
+        let thumbnail_path = thumbnails_dir.join(format!("{}_{}.jpg", path_hash(&file_path), size));
+
+        if is_fresh(source, &thumbnail_path) {
+            return Ok(thumbnail_path.to_string_lossy().to_string());
         }
 
         // Load and resize image
-        let img = image::open(source)?;
+        let img = decode_image(source, format)?;
         let thumbnail = img.thumbnail(size, size);
 
         // Save thumbnail as JPEG with tuned quality for speed/size tradeoff
@@ -56,7 +48,146 @@ pub async fn generate_thumbnail(file_path: &str, size: u32) -> Result<String> {
     Ok(res)
 }
 
-pub fn get_thumbnails_dir() -> Result<std::path::PathBuf> {
+/// Small looping GIF of an animated GIF/WebP for hover playback in the grid.
+/// Generated on demand and cached next to the still thumbnails.
+pub async fn generate_animated_preview(file_path: &str, size: u32) -> Result<String> {
+    let file_path = file_path.to_string();
+    tokio::task::spawn_blocking(move || {
+        let source = Path::new(&file_path);
+        let format = formats::detect(source)
+            .filter(|f| f.capabilities().animation)
+            .ok_or_else(|| anyhow!("No animation decoder for {}", file_path))?;
+        let info = animation::probe(source, format)?
+            .ok_or_else(|| anyhow!("{} is not animated", file_path))?;
+
+        let thumbnails_dir = get_thumbnails_dir()?;
+        std::fs::create_dir_all(&thumbnails_dir)?;
+        let preview_path =
+            thumbnails_dir.join(format!("{}_{}_anim.gif", path_hash(&file_path), size));
+        if is_fresh(source, &preview_path) {
+            return Ok(preview_path.to_string_lossy().to_string());
+        }
+
+        let step = info.frame_count.div_ceil(MAX_PREVIEW_FRAMES).max(1) as usize;
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut current: Option<(image::RgbaImage, f64)> = None;
+        for (i, frame) in animation::decode_frames(source, format)?.enumerate() {
+            let frame = frame?;
+            let (num, den) = frame.delay().numer_denom_ms();
+            let delay_ms = animation::playback_delay_ms(format, num as f64 / den.max(1) as f64);
+            if i % step == 0 {
+                if let Some((buf, ms)) = current.take() {
+                    frames.push(preview_frame(buf, ms));
+                }
+                let buf = frame.into_buffer();
+                let (w, h) = fit_within(buf.width(), buf.height(), size);
+                current = Some((image::imageops::thumbnail(&buf, w, h), delay_ms));
+            } else if let Some((_, ms)) = current.as_mut() {
+                *ms += delay_ms;
+            }
+        }
+        if let Some((buf, ms)) = current.take() {
+            frames.push(preview_frame(buf, ms));
+        }
+
+        // Write to a temporary name so a half-written preview is never served
+        let tmp_path = preview_path.with_extension("gif.tmp");
+        {
+            let out = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+            // Speed 10 trades a little palette quality for much faster encoding
+            let mut encoder = GifEncoder::new_with_speed(out, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(frames)?;
+        }
+        std::fs::rename(&tmp_path, &preview_path)?;
+        Ok(preview_path.to_string_lossy().to_string())
+    })
+    .await
+    .map_err(|e| anyhow!("Join error generating animated preview: {}", e))?
+}
+
+fn preview_frame(buf: image::RgbaImage, delay_ms: f64) -> Frame {
+    Frame::from_parts(
+        buf,
+        0,
+        0,
+        Delay::from_numer_denom_ms(delay_ms.round() as u32, 1),
+    )
+}
+
+/// Scale `(width, height)` to fit a `size` square, keeping the aspect ratio.
+fn fit_within(width: u32, height: u32, size: u32) -> (u32, u32) {
+    if width <= size && height <= size {
+        return (width, height);
+    }
+    let scale = size as f64 / width.max(height) as f64;
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+/// Short, stable cache key for a source path.
+fn path_hash(path: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_bytes());
+    hex::encode(hasher.finalize())[..16].to_string()
+}
+
+/// A cached file is fresh when it is at least as new as its source. Any
+/// metadata failure counts as stale so the cache gets regenerated.
+fn is_fresh(source: &Path, cached: &Path) -> bool {
+    match (std::fs::metadata(source), std::fs::metadata(cached)) {
+        (Ok(src), Ok(cache)) => match (src.modified(), cache.modified()) {
+            (Ok(src_time), Ok(cache_time)) => cache_time >= src_time,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub fn decode_image(source: &Path, format: Format) -> Result<DynamicImage> {
+    match format {
+        #[cfg(feature = "heif")]
+        Format::Heif | Format::Avif => decode_heif(source),
+        Format::Raw => raw::decode_preview(source),
+        Format::Mp4 | Format::QuickTime => video::poster_frame(source),
+        // The format is guessed from the content so files with a wrong or
+        // missing extension still decode.
+        _ => Ok(image::ImageReader::open(source)?
+            .with_guessed_format()?
+            .decode()?),
+    }
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(source: &Path) -> Result<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let path = source
+        .to_str()
+        .ok_or_else(|| anyhow!("Non UTF-8 path: {}", source.display()))?;
+    let ctx = HeifContext::read_from_file(path)?;
+    let handle = ctx.primary_image_handle()?;
+    // libheif applies the irot/imir transforms while decoding
+    let decoded = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
+    let planes = decoded.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| anyhow!("HEIF decode produced no interleaved plane"))?;
+
+    // Rows may be padded, so copy them out using the stride
+    let row_len = plane.width as usize * 3;
+    let mut buf = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        buf.extend_from_slice(&row[..row_len]);
+    }
+    let rgb = image::RgbImage::from_raw(plane.width, plane.height, buf)
+        .ok_or_else(|| anyhow!("HEIF plane size mismatch"))?;
+    Ok(DynamicImage::ImageRgb8(rgb))
+}
+
+pub fn get_thumbnails_dir() -> Result<PathBuf> {
     // Get app data directory
     let app_data = dirs::cache_dir()
         .or_else(|| dirs::home_dir().map(|p| p.join(".cache")))
@@ -65,17 +196,14 @@ pub fn get_thumbnails_dir() -> Result<std::path::PathBuf> {
     Ok(app_data.join("local-gallery").join("thumbnails"))
 }
 
-/// Best-effort removal of all thumbnails for a given set of files and size.
+/// Best-effort removal of all thumbnails (and animated previews) for a given
+/// set of files and size.
 pub fn remove_thumbnails_for_paths(paths: &[String], size: u32) {
     if let Ok(dir) = get_thumbnails_dir() {
         for p in paths {
-            let mut hasher = Sha256::new();
-            hasher.update(p.as_bytes());
-            let hash = hasher.finalize();
-            let short = &hex::encode(hash)[..16];
-            let fname = format!("{}_{}.jpg", short, size);
-            let target = dir.join(fname);
-            let _ = std::fs::remove_file(target);
+            let short = path_hash(p);
+            let _ = std::fs::remove_file(dir.join(format!("{}_{}.jpg", short, size)));
+            let _ = std::fs::remove_file(dir.join(format!("{}_{}_anim.gif", short, size)));
         }
     }
 }
@@ -0,0 +1,618 @@
+//! MP4/QuickTime support: container metadata from the `moov` box tree and
+//! poster frames for thumbnails.
+
+use crate::isobmff::{self, BoxHeader};
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Seconds between the QuickTime epoch (1904-01-01) and the Unix epoch.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Longest ffmpeg may take over one poster frame; a corrupt file can make it
+/// hang, which would stall indexing.
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Key under which iPhones and most Android phones store the local capture
+/// time, including the UTC offset.
+const QUICKTIME_CREATION_DATE: &[u8] = b"com.apple.quicktime.creationdate";
+
+/// Cover art and other item values larger than this are skipped rather than
+/// read into memory; a crafted size could otherwise ask for gigabytes.
+const MAX_ITEM_LEN: u64 = 16 * 1024 * 1024;
+
+/// `keys` entries are short reverse-DNS names.
+const MAX_KEY_LEN: u64 = 1024;
+
+/// iTunes-style item data types (the first word of a `data` box).
+const DATA_TYPE_UTF8: u32 = 1;
+
+#[derive(Debug, Clone, Default)]
+pub struct VideoInfo {
+    pub duration_ms: Option<u64>,
+    /// Display size, after the track matrix rotation.
+    pub dimensions: Option<(u32, u32)>,
+    pub codec: Option<String>,
+    /// ISO 8601; carries the recorded UTC offset when the file has one.
+    pub creation_date: Option<String>,
+}
+
+/// Read duration, resolution, codec and creation date from the `moov` box.
+/// Returns `None` when the file has no video track (e.g. an M4A).
+pub fn probe(path: &Path) -> io::Result<Option<VideoInfo>> {
+    let (mut r, top) = isobmff::open(path)?;
+    let moov = match isobmff::find(&top, b"moov") {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+    let moov_kids = isobmff::children(&mut r, &moov, 0)?;
+
+    let mut info = VideoInfo::default();
+    let mut has_video = false;
+    for trak in moov_kids.iter().filter(|b| b.is(b"trak")) {
+        let trak_kids = isobmff::children(&mut r, trak, 0)?;
+        if handler_type(&mut r, &trak_kids)? != Some(*b"vide") {
+            continue;
+        }
+        has_video = true;
+        info.dimensions = match track_header_size(&mut r, &trak_kids)? {
+            Some(size) => Some(size),
+            None => isobmff::track_sample_size(&mut r, trak)?,
+        };
+        info.codec = sample_entry_kind(&mut r, &trak_kids)?.map(|kind| codec_name(&kind));
+        break;
+    }
+    if !has_video {
+        return Ok(None);
+    }
+
+    let mut mvhd_created = None;
+    if let Some(mvhd) = isobmff::find(&moov_kids, b"mvhd") {
+        let (created, duration_ms) = movie_header(&mut r, mvhd)?;
+        mvhd_created = created;
+        info.duration_ms = duration_ms;
+    }
+
+    let items = metadata_items(&mut r, &moov_kids)?;
+    info.creation_date = items
+        .creation_date
+        .or(items.day)
+        .as_deref()
+        .and_then(parse_date)
+        .or(mvhd_created);
+    Ok(Some(info))
+}
+
+/// A poster image for thumbnails: the embedded cover art when the file has
+/// one, otherwise a frame decoded by `ffmpeg` (which must be on `PATH`).
+pub fn poster_frame(path: &Path) -> Result<DynamicImage> {
+    if let Some(cover) = embedded_cover(path)? {
+        if let Ok(img) = image::load_from_memory(&cover) {
+            return Ok(img);
+        }
+    }
+    let duration_ms = probe(path).ok().flatten().and_then(|i| i.duration_ms);
+    extract_frame(path, poster_time(duration_ms))
+}
+
+fn embedded_cover(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let (mut r, top) = isobmff::open(path)?;
+    let moov = match isobmff::find(&top, b"moov") {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+    let moov_kids = isobmff::children(&mut r, &moov, 0)?;
+    Ok(metadata_items(&mut r, &moov_kids)?.cover)
+}
+
+/// Skip black lead-in frames: one second in, or a tenth of the clip when it
+/// is shorter than ten seconds.
+fn poster_time(duration_ms: Option<u64>) -> f64 {
+    match duration_ms {
+        Some(ms) => (ms as f64 / 10_000.0).min(1.0),
+        None => 0.0,
+    }
+}
+
+fn extract_frame(path: &Path, seconds: f64) -> Result<DynamicImage> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-v", "error", "-ss", &format!("{:.3}", seconds), "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"])
+        .stdin(Stdio::null());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW: don't flash a console for every thumbnail
+        cmd.creation_flags(0x0800_0000);
+    }
+
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                anyhow!("ffmpeg not found; install it to get video thumbnails")
+            }
+            _ => anyhow!("Failed to run ffmpeg: {}", e),
+        })?;
+    // Drain both pipes while waiting so a full pipe can't block ffmpeg
+    let stdout = child.stdout.take().map(drain);
+    let stderr = child.stderr.take().map(drain);
+    let deadline = Instant::now() + FFMPEG_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("ffmpeg timed out on {}", path.display()));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let collect = |reader: Option<thread::JoinHandle<Vec<u8>>>| {
+        reader.and_then(|r| r.join().ok()).unwrap_or_default()
+    };
+    let (stdout, stderr) = (collect(stdout), collect(stderr));
+    if !status.success() || stdout.is_empty() {
+        return Err(anyhow!(
+            "ffmpeg could not decode a frame from {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&stderr).trim()
+        ));
+    }
+    Ok(image::load_from_memory_with_format(
+        &stdout,
+        image::ImageFormat::Png,
+    )?)
+}
+
+fn drain<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// `hdlr` handler type of a track, e.g. `vide` or `soun`.
+fn handler_type<R: Read + Seek>(r: &mut R, trak_kids: &[BoxHeader]) -> io::Result<Option<[u8; 4]>> {
+    let hdlr = match isobmff::find_path(r, trak_kids, &[b"mdia", b"hdlr"])? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    if hdlr.end < hdlr.start + 12 {
+        return Ok(None);
+    }
+    // Full box header, then 4 bytes of pre_defined
+    r.seek(SeekFrom::Start(hdlr.start + 8))?;
+    let mut kind = [0u8; 4];
+    r.read_exact(&mut kind)?;
+    Ok(Some(kind))
+}
+
+/// Presentation size from `tkhd`, swapped when the matrix turns the track a
+/// quarter turn (phones record portrait clips as rotated landscape).
+fn track_header_size<R: Read + Seek>(
+    r: &mut R,
+    trak_kids: &[BoxHeader],
+) -> io::Result<Option<(u32, u32)>> {
+    let tkhd = match isobmff::find(trak_kids, b"tkhd") {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+    r.seek(SeekFrom::Start(tkhd.start))?;
+    let version = isobmff::read_u32(r)? >> 24;
+    // times, track id, reserved and duration; then reserved, layer,
+    // alternate group, volume and reserved
+    let skip = if version == 1 { 32 } else { 20 } + 16;
+    if tkhd.end < tkhd.start + 4 + skip + 36 + 8 {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::Current(skip as i64))?;
+    let mut matrix = [0i32; 9];
+    for m in matrix.iter_mut() {
+        *m = isobmff::read_u32(r)? as i32;
+    }
+    // 16.16 fixed point
+    let width = isobmff::read_u32(r)? >> 16;
+    let height = isobmff::read_u32(r)? >> 16;
+    if width == 0 || height == 0 {
+        return Ok(None);
+    }
+    let quarter_turn = matrix[0] == 0 && matrix[1] != 0;
+    Ok(Some(if quarter_turn {
+        (height, width)
+    } else {
+        (width, height)
+    }))
+}
+
+/// Fourcc of the first sample description, which names the codec.
+fn sample_entry_kind<R: Read + Seek>(
+    r: &mut R,
+    trak_kids: &[BoxHeader],
+) -> io::Result<Option<[u8; 4]>> {
+    let stsd = match isobmff::find_path(r, trak_kids, &[b"mdia", b"minf", b"stbl", b"stsd"])? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    // Full box header and entry count precede the entries
+    let entries = isobmff::read_boxes(r, stsd.start + 8, stsd.end)?;
+    Ok(entries.first().map(|e| e.kind))
+}
+
+fn codec_name(kind: &[u8; 4]) -> String {
+    match kind {
+        b"avc1" | b"avc3" => "h264".to_string(),
+        b"hvc1" | b"hev1" => "hevc".to_string(),
+        b"av01" => "av1".to_string(),
+        b"vp08" => "vp8".to_string(),
+        b"vp09" => "vp9".to_string(),
+        b"mp4v" => "mpeg4".to_string(),
+        b"jpeg" | b"mjpa" | b"mjpb" => "mjpeg".to_string(),
+        b"apch" | b"apcn" | b"apcs" | b"apco" | b"ap4h" | b"ap4x" => "prores".to_string(),
+        b"s263" | b"h263" => "h263".to_string(),
+        other => String::from_utf8_lossy(other).trim().to_string(),
+    }
+}
+
+/// Creation time (UTC) and duration from `mvhd`.
+fn movie_header<R: Read + Seek>(
+    r: &mut R,
+    mvhd: &BoxHeader,
+) -> io::Result<(Option<String>, Option<u64>)> {
+    r.seek(SeekFrom::Start(mvhd.start))?;
+    let version = isobmff::read_u32(r)? >> 24;
+    let (created, timescale, duration) = if version == 1 {
+        let created = isobmff::read_u64(r)?;
+        isobmff::read_u64(r)?;
+        let timescale = isobmff::read_u32(r)?;
+        let duration = isobmff::read_u64(r)?;
+        (
+            created,
+            timescale,
+            (duration != u64::MAX).then_some(duration),
+        )
+    } else {
+        let created = isobmff::read_u32(r)? as u64;
+        isobmff::read_u32(r)?;
+        let timescale = isobmff::read_u32(r)?;
+        let duration = isobmff::read_u32(r)?;
+        (
+            created,
+            timescale,
+            (duration != u32::MAX).then_some(duration as u64),
+        )
+    };
+
+    let created = (created != 0)
+        .then(|| chrono::DateTime::from_timestamp(created as i64 - QUICKTIME_EPOCH_OFFSET, 0))
+        .flatten()
+        .map(|dt| dt.to_rfc3339());
+    let duration_ms = match (duration, timescale) {
+        (Some(d), ts) if ts > 0 => d.checked_mul(1000).map(|ms| ms / ts as u64),
+        _ => None,
+    };
+    Ok((created, duration_ms))
+}
+
+#[derive(Default)]
+struct MetadataItems {
+    cover: Option<Vec<u8>>,
+    /// iTunes `©day`
+    day: Option<String>,
+    /// QuickTime `com.apple.quicktime.creationdate`
+    creation_date: Option<String>,
+}
+
+/// Collect the items we care about from `moov/udta/meta` (iTunes style) and
+/// `moov/meta` (QuickTime `mdta` keys).
+fn metadata_items<R: Read + Seek>(r: &mut R, moov_kids: &[BoxHeader]) -> io::Result<MetadataItems> {
+    let mut items = MetadataItems::default();
+    let mut metas = Vec::new();
+    if let Some(meta) = isobmff::find(moov_kids, b"meta") {
+        metas.push(*meta);
+    }
+    if let Some(meta) = isobmff::find_path(r, moov_kids, &[b"udta", b"meta"])? {
+        metas.push(meta);
+    }
+
+    for meta in metas {
+        // MP4 defines `meta` as a full box, QuickTime as a plain container;
+        // a zero first word means version/flags are present.
+        r.seek(SeekFrom::Start(meta.start))?;
+        let skip = if isobmff::read_u32(r)? == 0 { 4 } else { 0 };
+        let kids = isobmff::children(r, &meta, skip)?;
+        let keys = match isobmff::find(&kids, b"keys") {
+            Some(k) => read_keys(r, k)?,
+            None => Vec::new(),
+        };
+        let ilst = match isobmff::find(&kids, b"ilst") {
+            Some(b) => *b,
+            None => continue,
+        };
+        for item in isobmff::children(r, &ilst, 0)? {
+            // With a `keys` box, item types are 1-based indices into it
+            let index = u32::from_be_bytes(item.kind) as usize;
+            let key = keys.get(index.wrapping_sub(1)).map(Vec::as_slice);
+            if item.is(b"covr") && items.cover.is_none() {
+                items.cover = item_data(r, &item)?.map(|(_, bytes)| bytes);
+            } else if item.is(b"\xA9day") && items.day.is_none() {
+                items.day = item_text(r, &item)?;
+            } else if key == Some(QUICKTIME_CREATION_DATE) && items.creation_date.is_none() {
+                items.creation_date = item_text(r, &item)?;
+            }
+        }
+    }
+    Ok(items)
+}
+
+fn read_keys<R: Read + Seek>(r: &mut R, keys: &BoxHeader) -> io::Result<Vec<Vec<u8>>> {
+    // Each entry is sized like a box, with the namespace in the type slot
+    let entries = isobmff::read_boxes(r, keys.start + 8, keys.end)?;
+    let mut out = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let len = entry.end - entry.start;
+        // Oversized keys stay as empty placeholders so the indices still line up
+        if len > MAX_KEY_LEN {
+            out.push(Vec::new());
+            continue;
+        }
+        r.seek(SeekFrom::Start(entry.start))?;
+        let mut value = vec![0u8; len as usize];
+        r.read_exact(&mut value)?;
+        out.push(value);
+    }
+    Ok(out)
+}
+
+/// Type indicator and payload of the first `data` box under an item.
+fn item_data<R: Read + Seek>(r: &mut R, item: &BoxHeader) -> io::Result<Option<(u32, Vec<u8>)>> {
+    let data = match isobmff::children(r, item, 0)?
+        .into_iter()
+        .find(|b| b.is(b"data"))
+    {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    if data.end < data.start + 8 || data.end - data.start - 8 > MAX_ITEM_LEN {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::Start(data.start))?;
+    let data_type = isobmff::read_u32(r)? & 0x00FF_FFFF;
+    isobmff::read_u32(r)?; // locale
+    let mut bytes = vec![0u8; (data.end - data.start - 8) as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(Some((data_type, bytes)))
+}
+
+fn item_text<R: Read + Seek>(r: &mut R, item: &BoxHeader) -> io::Result<Option<String>> {
+    Ok(item_data(r, item)?
+        .filter(|(data_type, _)| *data_type == DATA_TYPE_UTF8)
+        .map(|(_, bytes)| String::from_utf8_lossy(&bytes).trim().to_string())
+        .filter(|s| !s.is_empty()))
+}
+
+/// Normalise the date formats phones and editors write: RFC 3339, ISO 8601
+/// with a `+hhmm` offset, or a bare local date-time.
+fn parse_date(raw: &str) -> Option<String> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(raw) {
+        return Some(dt.to_rfc3339());
+    }
+    if let Ok(dt) = chrono::DateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S%z") {
+        return Some(dt.to_rfc3339());
+    }
+    chrono::NaiveDateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S")
+        .ok()
+        .map(|dt| dt.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn bx(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn full(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        bx(kind, &[&[0u8; 4][..], payload].concat())
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    /// Version 0 `mvhd`: created, modified, timescale and duration.
+    fn mvhd(created: u32, timescale: u32, duration: u32) -> Vec<u8> {
+        full(b"mvhd", &words(&[created, 0, timescale, duration]))
+    }
+
+    fn trak(handler: &[u8; 4], codec: &[u8; 4], size: (u32, u32), portrait: bool) -> Vec<u8> {
+        let matrix: [u32; 9] = if portrait {
+            [0, 0x10000, 0, 0xFFFF_0000, 0, 0, 0, 0, 0x4000_0000]
+        } else {
+            [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000]
+        };
+        let tkhd = full(
+            b"tkhd",
+            &[
+                vec![0; 36],
+                words(&matrix),
+                words(&[size.0 << 16, size.1 << 16]),
+            ]
+            .concat(),
+        );
+        let hdlr = full(b"hdlr", &[&[0u8; 4][..], handler, &[0; 12]].concat());
+        let stsd = full(b"stsd", &[words(&[1]), bx(codec, &[0; 78])].concat());
+        let stbl = bx(b"stbl", &stsd);
+        let mdia = bx(b"mdia", &[hdlr, bx(b"minf", &stbl)].concat());
+        bx(b"trak", &[tkhd, mdia].concat())
+    }
+
+    fn data(data_type: u32, value: &[u8]) -> Vec<u8> {
+        bx(b"data", &[&words(&[data_type, 0])[..], value].concat())
+    }
+
+    /// iTunes-style `udta/meta/ilst`.
+    fn udta(items: &[Vec<u8>]) -> Vec<u8> {
+        let hdlr = full(b"hdlr", &[&[0u8; 4][..], b"mdir", &[0; 12]].concat());
+        bx(
+            b"udta",
+            &full(b"meta", &[hdlr, bx(b"ilst", &items.concat())].concat()),
+        )
+    }
+
+    /// QuickTime `meta` with `mdta` keys; item types are 1-based key indices.
+    fn mdta(entries: &[(&[u8], Vec<u8>)]) -> Vec<u8> {
+        let keys: Vec<u8> = entries
+            .iter()
+            .map(|(key, _)| bx(b"mdta", key))
+            .collect::<Vec<_>>()
+            .concat();
+        let keys = full(b"keys", &[words(&[entries.len() as u32]), keys].concat());
+        let items: Vec<u8> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (_, value))| bx(&(i as u32 + 1).to_be_bytes(), value))
+            .collect::<Vec<_>>()
+            .concat();
+        bx(b"meta", &[keys, bx(b"ilst", &items)].concat())
+    }
+
+    fn movie(moov: &[Vec<u8>]) -> Vec<u8> {
+        [
+            bx(b"ftyp", b"qt  \0\0\0\0qt  "),
+            bx(b"moov", &moov.concat()),
+        ]
+        .concat()
+    }
+
+    fn with_file<T>(bytes: &[u8], f: impl FnOnce(&Path) -> T) -> T {
+        let path = std::env::temp_dir().join(format!("lumous-video-{}.mov", uuid::Uuid::new_v4()));
+        std::fs::write(&path, bytes).unwrap();
+        let out = f(&path);
+        let _ = std::fs::remove_file(&path);
+        out
+    }
+
+    #[test]
+    fn probes_a_phone_clip() {
+        let bytes = movie(&[
+            mvhd(3_797_856_000, 600, 6300),
+            trak(b"soun", b"mp4a", (0, 0), false),
+            trak(b"vide", b"hvc1", (1920, 1080), true),
+            udta(&[bx(b"\xA9day", &data(1, b"2020-01-01T00:00:00Z"))]),
+            mdta(&[
+                (b"com.apple.quicktime.make", data(1, b"Apple")),
+                (
+                    QUICKTIME_CREATION_DATE,
+                    data(1, b"2024-05-01T10:30:00+0200"),
+                ),
+            ]),
+        ]);
+        let info = with_file(&bytes, probe).unwrap().unwrap();
+        assert_eq!(info.dimensions, Some((1080, 1920)));
+        assert_eq!(info.codec.as_deref(), Some("hevc"));
+        assert_eq!(info.duration_ms, Some(10_500));
+        // The keyed creation date keeps its offset and wins over the others
+        assert_eq!(
+            info.creation_date.as_deref(),
+            Some("2024-05-01T10:30:00+02:00")
+        );
+    }
+
+    #[test]
+    fn dates_fall_back_to_day_then_mvhd() {
+        let bytes = movie(&[
+            mvhd(3_797_856_000, 600, 600),
+            trak(b"vide", b"avc1", (1280, 720), false),
+            udta(&[bx(b"\xA9day", &data(1, b"2021-07-04T18:00:00"))]),
+        ]);
+        let info = with_file(&bytes, probe).unwrap().unwrap();
+        assert_eq!(info.dimensions, Some((1280, 720)));
+        assert_eq!(info.creation_date.as_deref(), Some("2021-07-04T18:00:00"));
+
+        let bytes = movie(&[
+            mvhd(3_797_856_000, 600, 600),
+            trak(b"vide", b"avc1", (1280, 720), false),
+        ]);
+        let info = with_file(&bytes, probe).unwrap().unwrap();
+        assert_eq!(
+            info.creation_date.as_deref(),
+            Some("2024-05-06T16:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn files_without_video_are_not_videos() {
+        let audio = movie(&[mvhd(0, 44100, 44100), trak(b"soun", b"mp4a", (0, 0), false)]);
+        assert!(with_file(&audio, probe).unwrap().is_none());
+        let no_moov = bx(b"ftyp", b"isom\0\0\0\0isom");
+        assert!(with_file(&no_moov, probe).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_files_do_not_panic() {
+        let bytes = movie(&[
+            mvhd(3_797_856_000, 600, 6300),
+            trak(b"vide", b"hvc1", (1920, 1080), true),
+            mdta(&[(
+                QUICKTIME_CREATION_DATE,
+                data(1, b"2024-05-01T10:30:00+0200"),
+            )]),
+        ]);
+        for len in (0..bytes.len()).step_by(7) {
+            // A cut moov box is dropped whole by the box walk
+            let info = with_file(&bytes[..len], probe);
+            assert!(matches!(info, Ok(None) | Err(_)), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn reads_cover_art() {
+        let bytes = movie(&[udta(&[bx(b"covr", &data(13, b"\xFF\xD8\xFFjpeg"))])]);
+        let cover = with_file(&bytes, embedded_cover).unwrap();
+        assert_eq!(cover.as_deref(), Some(&b"\xFF\xD8\xFFjpeg"[..]));
+    }
+
+    #[test]
+    fn oversized_items_are_skipped() {
+        // A `data` box claiming 4 GB, in an item that claims as much
+        let bytes = [&words(&[u32::MAX])[..], b"data", &words(&[13, 0])].concat();
+        let item = BoxHeader {
+            kind: *b"covr",
+            start: 0,
+            end: u32::MAX as u64,
+        };
+        assert_eq!(item_data(&mut Cursor::new(&bytes), &item).unwrap(), None);
+
+        let bytes = [
+            bx(b"mdta", b"short"),
+            [&words(&[u32::MAX])[..], b"mdta"].concat(),
+        ]
+        .concat();
+        // Full box header and entry count, then the entries
+        let mut r = Cursor::new([vec![0; 8], bytes].concat());
+        let keys = BoxHeader {
+            kind: *b"keys",
+            start: 0,
+            end: 21 + u32::MAX as u64,
+        };
+        assert_eq!(
+            read_keys(&mut r, &keys).unwrap(),
+            [b"short".to_vec(), Vec::new()]
+        );
+    }
+}
@@ -0,0 +1,348 @@
+//! XMP sidecars next to images, as written by Lightroom (`IMG_1.xmp`) and
+//! darktable (`IMG_1.CR2.xmp`).
+
+use crate::keywords::{self, LEVEL_SEPARATOR};
+use crate::models::XmpSidecar;
+use crate::sidecar;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NAMESPACES: [(&str, &str); 3] = [
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("lr", "http://ns.adobe.com/lightroom/1.0/"),
+];
+
+/// darktable's color label numbers, in order.
+const DARKTABLE_LABELS: [&str; 5] = ["Red", "Yellow", "Green", "Blue", "Purple"];
+
+static RATING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"xmp:Rating(?:\s*=\s*"|>)\s*(-?\d+)"#).unwrap());
+static LABEL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"xmp:Label(?:\s*=\s*"([^"]*)"|>([^<]*)<)"#).unwrap());
+static COLOR_LABELS: Lazy<Regex> = Lazy::new(|| keywords::bag("darktable:colorlabels"));
+static DESCRIPTION: Lazy<Regex> = Lazy::new(|| keywords::bag("dc:description"));
+static DESCRIPTION_TAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<rdf:Description\b([^>]*?)(/?)>").unwrap());
+/// Properties [`write`] owns, in element form.
+static MANAGED: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?s)\s*<dc:subject\b.*?</dc:subject>|\s*<lr:hierarchicalSubject\b.*?</lr:hierarchicalSubject>|\s*<dc:description\b.*?</dc:description>"#,
+    )
+    .unwrap()
+});
+/// The rating, in attribute or element form. Only replaced when there is a
+/// new one, so ratings the gallery can't hold, such as Lightroom's -1 for
+/// rejected, survive.
+static RATING_PROPERTY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)\s+xmp:Rating\s*=\s*"[^"]*"|\s*<xmp:Rating>.*?</xmp:Rating>"#).unwrap()
+});
+
+/// The sidecar of `image`, if there is one. darktable's naming wins when
+/// both exist since it can't be shared by a RAW+JPEG pair.
+pub fn sidecar_path(image: &Path) -> Option<PathBuf> {
+    let name = image.file_name()?.to_string_lossy();
+    let stem = image.file_stem()?.to_string_lossy();
+    [name, stem]
+        .iter()
+        .flat_map(|base| [format!("{}.xmp", base), format!("{}.XMP", base)])
+        .map(|file| image.with_file_name(file))
+        .find(|p| p.is_file())
+}
+
+/// Parse the sidecar of `image`; `None` if it has none or it can't be read.
+pub fn read(image: &Path) -> Option<XmpSidecar> {
+    let packet = fs::read_to_string(sidecar_path(image)?).ok()?;
+    Some(parse(&packet))
+}
+
+pub fn parse(packet: &str) -> XmpSidecar {
+    let rating = RATING
+        .captures(packet)
+        .and_then(|c| c[1].parse::<i32>().ok())
+        // -1 marks a rejected photo in Lightroom
+        .filter(|r| (0..=5).contains(r));
+
+    let mut labels: Vec<String> = LABEL
+        .captures_iter(packet)
+        .filter_map(|c| c.get(1).or(c.get(2)))
+        .map(|m| keywords::unescape(m.as_str().trim()))
+        .filter(|l| !l.is_empty())
+        .collect();
+    for number in keywords::items(&COLOR_LABELS, packet) {
+        let label = number.trim().parse::<usize>().ok();
+        if let Some(label) = label.and_then(|n| DARKTABLE_LABELS.get(n)) {
+            if !labels.iter().any(|l| l.eq_ignore_ascii_case(label)) {
+                labels.push(label.to_string());
+            }
+        }
+    }
+
+    XmpSidecar {
+        rating,
+        labels,
+        keywords: keywords::from_xmp(packet),
+        description: keywords::items(&DESCRIPTION, packet)
+            .into_iter()
+            .map(|d| d.trim().to_string())
+            .find(|d| !d.is_empty()),
+    }
+}
+
+/// Write `data`'s rating, keywords and description into the sidecar of
+/// `image`, creating `<stem>.xmp` if there is none. Everything else already
+/// in the sidecar, labels included, is kept, and so is its rating when
+/// `data` has none. Returns the sidecar's path.
+pub fn write(image: &Path, data: &XmpSidecar) -> Result<PathBuf> {
+    let path = match sidecar_path(image) {
+        Some(path) => path,
+        None => image.with_extension("xmp"),
+    };
+    let packet = match fs::read_to_string(&path) {
+        Ok(existing) => merge(&existing, data)
+            .ok_or_else(|| anyhow!("No rdf:Description in {}", path.display()))?,
+        Err(_) => new_packet(&properties(data)),
+    };
+    sidecar::write_atomic(&path, packet.as_bytes(), false)?;
+    Ok(path)
+}
+
+/// `existing` with the managed properties replaced by `data`'s.
+fn merge(existing: &str, data: &XmpSidecar) -> Option<String> {
+    let mut stripped = MANAGED.replace_all(existing, "").into_owned();
+    if data.rating.is_some() {
+        stripped = RATING_PROPERTY.replace_all(&stripped, "").into_owned();
+    }
+    let properties = properties(data);
+    let tag = DESCRIPTION_TAG.captures(&stripped)?;
+    let whole = tag.get(0)?;
+    let mut opening = format!("<rdf:Description{}", &tag[1]);
+    for (prefix, uri) in NAMESPACES {
+        if !stripped.contains(&format!("xmlns:{}=", prefix)) {
+            opening.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri));
+        }
+    }
+    opening.push('>');
+    let self_closing = !tag[2].is_empty();
+    Some(format!(
+        "{}{}{}{}{}",
+        &stripped[..whole.start()],
+        opening,
+        properties,
+        if self_closing {
+            "\n  </rdf:Description>"
+        } else {
+            ""
+        },
+        &stripped[whole.end()..]
+    ))
+}
+
+fn new_packet(properties: &str) -> String {
+    let namespaces: Vec<String> = NAMESPACES
+        .iter()
+        .map(|(prefix, uri)| format!("\n    xmlns:{}=\"{}\"", prefix, uri))
+        .collect();
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"{}>{}
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+",
+        namespaces.concat(),
+        properties
+    )
+}
+
+/// Elements for the managed properties, each on its own line.
+fn properties(data: &XmpSidecar) -> String {
+    let mut out = String::new();
+    if let Some(rating) = data.rating {
+        out.push_str(&format!("\n   <xmp:Rating>{}</xmp:Rating>", rating));
+    }
+    if let Some(description) = &data.description {
+        out.push_str(&format!(
+            "\n   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+            escape(description)
+        ));
+    }
+    if !data.keywords.is_empty() {
+        // Flat subjects list every level, the way Lightroom writes them
+        let mut subjects: Vec<&str> = Vec::new();
+        for level in data.keywords.iter().flat_map(|k| k.split(LEVEL_SEPARATOR)) {
+            if !subjects.contains(&level) {
+                subjects.push(level);
+            }
+        }
+        out.push_str(&bag_element("dc:subject", subjects));
+        out.push_str(&bag_element(
+            "lr:hierarchicalSubject",
+            data.keywords.iter().map(String::as_str),
+        ));
+    }
+    out
+}
+
+fn bag_element<'a>(property: &str, items: impl IntoIterator<Item = &'a str>) -> String {
+    let items: String = items
+        .into_iter()
+        .map(|item| format!("<rdf:li>{}</rdf:li>", escape(item)))
+        .collect();
+    format!("\n   <{0}><rdf:Bag>{1}</rdf:Bag></{0}>", property, items)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lightroom's layout: simple properties as attributes.
+    const ATTRIBUTES: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:lr="http://ns.adobe.com/lightroom/1.0/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+    xmp:Rating="3"
+    xmp:Label="Red"
+    crs:Exposure2012="+0.50">
+   <dc:subject><rdf:Bag><rdf:li>Places</rdf:li><rdf:li>Paris</rdf:li><rdf:li>sunset</rdf:li></rdf:Bag></dc:subject>
+   <lr:hierarchicalSubject><rdf:Bag><rdf:li>Places|Paris</rdf:li></rdf:Bag></lr:hierarchicalSubject>
+   <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Eiffel &amp; lights</rdf:li></rdf:Alt></dc:description>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    /// darktable's layout: properties as elements.
+    const ELEMENTS: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:darktable="http://darktable.sf.net/">
+   <xmp:Rating>2</xmp:Rating>
+   <darktable:colorlabels><rdf:Seq><rdf:li>0</rdf:li><rdf:li>3</rdf:li></rdf:Seq></darktable:colorlabels>
+   <darktable:history><rdf:Seq><rdf:li darktable:operation="exposure"/></rdf:Seq></darktable:history>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    /// A packet with nothing in its description yet.
+    const SELF_CLOSING: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:tiff="http://ns.adobe.com/tiff/1.0/" tiff:Make="Canon"/>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    fn sample() -> XmpSidecar {
+        XmpSidecar {
+            rating: Some(5),
+            labels: vec![],
+            keywords: vec!["Places|Lyon".into(), "food & wine".into()],
+            description: Some("Dinner <3".into()),
+        }
+    }
+
+    #[test]
+    fn parse_reads_attribute_form() {
+        assert_eq!(
+            parse(ATTRIBUTES),
+            XmpSidecar {
+                rating: Some(3),
+                labels: vec!["Red".into()],
+                keywords: vec!["Places|Paris".into(), "sunset".into()],
+                description: Some("Eiffel & lights".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_reads_element_form() {
+        let data = parse(ELEMENTS);
+        assert_eq!(data.rating, Some(2));
+        assert_eq!(data.labels, ["Red", "Blue"]);
+        assert!(data.keywords.is_empty());
+        assert_eq!(data.description, None);
+    }
+
+    #[test]
+    fn parse_leaves_out_rejected() {
+        let packet = ATTRIBUTES.replace(r#"xmp:Rating="3""#, r#"xmp:Rating="-1""#);
+        assert_eq!(parse(&packet).rating, None);
+    }
+
+    #[test]
+    fn properties_round_trip_through_a_new_packet() {
+        let packet = new_packet(&properties(&sample()));
+        assert_eq!(parse(&packet), sample());
+        assert_eq!(
+            parse(&new_packet(&properties(&XmpSidecar::default()))),
+            XmpSidecar::default()
+        );
+    }
+
+    #[test]
+    fn merge_replaces_attribute_form_and_keeps_foreign_properties() {
+        let merged = merge(ATTRIBUTES, &sample()).unwrap();
+        let data = parse(&merged);
+        assert_eq!(data.rating, Some(5));
+        assert_eq!(data.keywords, sample().keywords);
+        assert_eq!(data.description, sample().description);
+        // Labels aren't managed
+        assert_eq!(data.labels, ["Red"]);
+        assert!(merged.contains(r#"crs:Exposure2012="+0.50""#));
+        assert!(!merged.contains(r#"xmp:Rating="3""#));
+        assert_eq!(merged.matches("xmlns:xmp=").count(), 1);
+    }
+
+    #[test]
+    fn merge_replaces_element_form_and_keeps_foreign_properties() {
+        let merged = merge(ELEMENTS, &sample()).unwrap();
+        let data = parse(&merged);
+        assert_eq!(data.rating, Some(5));
+        assert_eq!(data.labels, ["Red", "Blue"]);
+        assert_eq!(data.keywords, sample().keywords);
+        assert!(merged.contains(r#"<rdf:li darktable:operation="exposure"/>"#));
+        // Namespaces the packet lacked are declared
+        assert!(merged.contains("xmlns:dc=") && merged.contains("xmlns:lr="));
+    }
+
+    #[test]
+    fn merge_opens_a_self_closing_description() {
+        let merged = merge(SELF_CLOSING, &sample()).unwrap();
+        assert_eq!(parse(&merged), sample());
+        assert!(merged.contains(r#"tiff:Make="Canon""#));
+        assert!(merged.contains("</rdf:Description>"));
+        assert!(!merged.contains("/>"));
+    }
+
+    #[test]
+    fn merge_clears_removed_keywords_and_keeps_a_rating_it_has_none_for() {
+        let rejected = ATTRIBUTES.replace(r#"xmp:Rating="3""#, r#"xmp:Rating="-1""#);
+        let merged = merge(&rejected, &XmpSidecar::default()).unwrap();
+        assert!(merged.contains(r#"xmp:Rating="-1""#));
+        let data = parse(&merged);
+        assert!(data.keywords.is_empty());
+        assert_eq!(data.description, None);
+        // Merging again changes nothing
+        assert_eq!(merge(&merged, &XmpSidecar::default()).unwrap(), merged);
+    }
+
+    #[test]
+    fn merge_needs_a_description() {
+        assert_eq!(merge("<x:xmpmeta/>", &sample()), None);
+    }
+}
@@ -0,0 +1,47 @@
+-- library.db as written by the last release before schema versioning: still
+-- at user_version 0. The statements are `Database::init_schema` of that
+-- release, in order.
+CREATE TABLE IF NOT EXISTS files (
+    id TEXT PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    modified TEXT NOT NULL,
+    file_type TEXT,
+    thumbnail_path TEXT,
+    folder_path TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_folder_path ON files(folder_path);
+CREATE TABLE IF NOT EXISTS folder_snapshots (
+    path TEXT PRIMARY KEY,
+    file_count INTEGER NOT NULL,
+    agg_mtime INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS kv_store (
+    key TEXT PRIMARY KEY,
+    value TEXT
+);
+CREATE TABLE IF NOT EXISTS albums (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT,
+    cover_image_id TEXT
+);
+CREATE TABLE IF NOT EXISTS album_files (
+    album_id TEXT NOT NULL,
+    file_id TEXT NOT NULL,
+    PRIMARY KEY (album_id, file_id),
+    FOREIGN KEY(album_id) REFERENCES albums(id) ON DELETE CASCADE,
+    FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+
+INSERT INTO files (id, path, name, size, modified, file_type, thumbnail_path, folder_path)
+VALUES ('f1', '/pics/a.jpg', 'a.jpg', 10, '2024-01-01T00:00:00+00:00', 'jpg', NULL, '/pics');
+INSERT INTO files (id, path, name, size, modified, file_type, thumbnail_path, folder_path)
+VALUES ('f2', '/pics/IMG_10.jpg', 'IMG_10.jpg', 2000, '2024-06-01T12:00:00+02:00', 'jpg',
+        '/thumbs/f2.jpg', '/pics');
+INSERT INTO albums (id, name, cover_image_id) VALUES ('al1', 'Trip', 'f2');
+INSERT INTO album_files (album_id, file_id) VALUES ('al1', 'f1');
+INSERT INTO album_files (album_id, file_id) VALUES ('al1', 'f2');
+INSERT INTO kv_store (key, value) VALUES ('last_selected_folder', '/pics');
+INSERT INTO folder_snapshots (path, file_count, agg_mtime) VALUES ('/pics', 2, 42);
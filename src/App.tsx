@@ -1,15 +1,21 @@
-import { useState, useEffect, useMemo } from "react";
-import { useQuery, useQueryClient } from "@tanstack/react-query";
+import { useState, useEffect } from "react";
+import { useInfiniteQuery, useQuery, useQueryClient, type InfiniteData } from "@tanstack/react-query";
 import { FileGrid } from "./components/FileGrid";
 import { Sidebar } from "./components/Sidebar";
 import { TopBar } from "./components/TopBar";
 import { WelcomeScreen } from "./components/WelcomeScreen";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import type { AppSettings, FileMeta } from "./types";
-import { naturalSortFiles } from "./lib/utils";
+import type { AppSettings, FileCursor, FileMeta, FilePage } from "./types";
+import type { SortDir, SortKey } from "./components/SortDropdown";
 import { ToastProvider, AppToast } from "./components/ui/Toast";
 
+const FILES_PAGE_SIZE = 500;
+// Indexing events arrive in bursts; refetch once they settle
+const FILES_REFETCH_DELAY_MS = 500;
+
+type FilePages = InfiniteData<FilePage, FileCursor | null>;
+
 function App() {
   const [selectedFolder, setSelectedFolder] = useState<string | null>(null);
   const [currentView, setCurrentView] = useState<"grid" | "albums">("grid");
@@ -18,8 +24,10 @@ function App() {
   const [indexingProgress, setIndexingProgress] = useState<string>("");
   // Removed includedFolders concept; we now show only the selected folder
   const [isSidebarSlim, setIsSidebarSlim] = useState(false);
-  const [sortKey, setSortKey] = useState<'name' | 'date' | 'size'>('name');
-  const [sortDir, setSortDir] = useState<'asc' | 'desc'>('asc');
+  const [sortKey, setSortKey] = useState<SortKey>('name');
+  const [sortDir, setSortDir] = useState<SortDir>('asc');
+  // Re-rolled each time random order is picked so it reshuffles
+  const [sortSeed, setSortSeed] = useState(() => Math.floor(Math.random() * 2 ** 32));
   const queryClient = useQueryClient();
   const [toastOpen, setToastOpen] = useState(false);
   const [toastTitle, setToastTitle] = useState("");
@@ -67,6 +75,38 @@ function App() {
 
   // Listen for indexing events
   useEffect(() => {
+    let refetchTimer: ReturnType<typeof setTimeout> | undefined;
+    const scheduleRefetch = () => {
+      clearTimeout(refetchTimer);
+      refetchTimer = setTimeout(() => {
+        queryClient.invalidateQueries({ queryKey: ["files", selectedFolder] });
+      }, FILES_REFETCH_DELAY_MS);
+    };
+    // Files already on a loaded page are replaced in place. Any other file
+    // waits for a refetch, since only the backend knows where the sort order
+    // puts it.
+    const mergeIntoLoadedPages = (incoming: FileMeta[]) => {
+      const norm = (p: string) => p.toLowerCase();
+      const byPath = new Map(incoming.map(f => [norm(f.path), f]));
+      let missing = false;
+      queryClient.setQueriesData<FilePages>({ queryKey: ["files", selectedFolder] }, (oldData) => {
+        if (!oldData) return oldData;
+        const found = new Set<string>();
+        const pages = oldData.pages.map(page => ({
+          ...page,
+          files: page.files.map(f => {
+            const updated = byPath.get(norm(f.path));
+            if (!updated) return f;
+            found.add(norm(f.path));
+            return updated;
+          }),
+        }));
+        if (found.size < byPath.size) missing = true;
+        return { ...oldData, pages };
+      });
+      if (missing) scheduleRefetch();
+    };
+
     const setupEventListeners = async () => {
       // Listen for indexing progress
       const progressUnlisten = await listen('indexing-progress', (event) => {
@@ -120,12 +160,7 @@ function App() {
       const fileIndexedUnlisten = await listen('file-indexed', (event) => {
         const file = event.payload as FileMeta;
         if (selectedFolder && file.path.toLowerCase().startsWith(selectedFolder.toLowerCase())) {
-          queryClient.setQueryData(["files", selectedFolder], (oldData: FileMeta[] | undefined) => {
-            if (!oldData) return [file];
-            const norm = (p: string) => p.toLowerCase();
-            const exists = oldData.some(f => norm(f.path) === norm(file.path));
-            return exists ? oldData : [...oldData, file];
-          });
+          mergeIntoLoadedPages([file]);
         }
       });
 
@@ -137,15 +172,7 @@ function App() {
           const folderLc = selectedFolder.toLowerCase();
           const forThisFolder = batch.filter(f => f.path.toLowerCase().startsWith(folderLc));
           if (forThisFolder.length === 0) return;
-          queryClient.setQueryData(["files", selectedFolder], (oldData: FileMeta[] | undefined) => {
-            const norm = (p: string) => p.toLowerCase();
-            const map = new Map<string, FileMeta>();
-            if (oldData) {
-              for (const f of oldData) map.set(norm(f.path), f);
-            }
-            for (const f of forThisFolder) map.set(norm(f.path), f);
-            return Array.from(map.values());
-          });
+          mergeIntoLoadedPages(forThisFolder);
         }
       });
 
@@ -156,15 +183,7 @@ function App() {
         if (selectedFolder) {
           const folderLc = selectedFolder.toLowerCase();
           if (!updated.path.toLowerCase().startsWith(folderLc)) return;
-          queryClient.setQueryData(["files", selectedFolder], (oldData: FileMeta[] | undefined) => {
-            if (!oldData) return [updated];
-            const norm = (p: string) => p.toLowerCase();
-            const idx = oldData.findIndex(f => norm(f.path) === norm(updated.path));
-            if (idx === -1) return [...oldData, updated];
-            const copy = oldData.slice();
-            copy[idx] = updated;
-            return copy;
-          });
+          mergeIntoLoadedPages([updated]);
         }
       });
 
@@ -190,6 +209,7 @@ function App() {
     };
 
     setupEventListeners();
+    return () => clearTimeout(refetchTimer);
   }, [queryClient, selectedFolder]);
 
   // Load app settings
@@ -233,53 +253,42 @@ function App() {
     return () => window.removeEventListener('keydown', onKey);
   }, [imagesPerRow, settings?.thumbnailSize]);
 
-  // Load files from included folders when selection changes
+  // Load files of the selected folder a keyset page at a time as the grid
+  // scrolls; the cursor keeps pages stable while indexing inserts rows
   const {
-    data: allFiles = [],
+    data: filePages,
     isLoading: filesLoading,
-    error: filesError
-  } = useQuery({
-    queryKey: ["files", selectedFolder],
-    queryFn: async (): Promise<FileMeta[]> => {
-      if (!selectedFolder) return [];
+    error: filesError,
+    hasNextPage,
+    isFetchingNextPage,
+    fetchNextPage,
+  } = useInfiniteQuery({
+    queryKey: ["files", selectedFolder, searchQuery, sortKey, sortDir, sortSeed],
+    queryFn: async ({ pageParam }): Promise<FilePage> => {
+      if (!selectedFolder) return { files: [], total: 0 };
       try {
-        const files = await invoke("get_files", {
+        return await invoke<FilePage>("get_files", {
           folderPath: selectedFolder,
-          offset: 0,
-          limit: 2000
-        }) as FileMeta[];
-        return files;
+          filter: searchQuery ? { search: { query: searchQuery } } : null,
+          sort: { key: sortKey, direction: sortDir, seed: sortSeed },
+          cursor: pageParam,
+          limit: FILES_PAGE_SIZE
+        });
       } catch (error) {
         console.warn("Failed to load files for folder", selectedFolder, error);
-        return [];
+        return { files: [], total: 0 };
       }
     },
+    initialPageParam: null as FileCursor | null,
+    getNextPageParam: (lastPage) => lastPage.next_cursor ?? undefined,
     enabled: !!selectedFolder,
   });
-
-  // Filter files based on search query
-  const filtered = allFiles.filter(file => {
-    if (!searchQuery) return true;
-    const query = searchQuery.toLowerCase();
-    return (
-      file.name.toLowerCase().includes(query) ||
-      file.path.toLowerCase().includes(query) ||
-      file.file_type.toLowerCase().includes(query)
-    );
-  });
-  // Sort according to sortKey/sortDir
-  const files = useMemo(() => {
-    let base: FileMeta[];
-    if (sortKey === 'name') {
-      base = naturalSortFiles(filtered);
-    } else if (sortKey === 'date') {
-      base = [...filtered].sort((a, b) => (new Date(a.modified).getTime() - new Date(b.modified).getTime()));
-    } else { // size
-      base = [...filtered].sort((a, b) => a.size - b.size);
-    }
-    if (sortDir === 'desc') base.reverse();
-    return base;
-  }, [filtered, sortKey, sortDir]);
+  const allFiles = filePages?.pages.flatMap(page => page.files) ?? [];
+  const loadMoreFiles = () => {
+    if (hasNextPage && !isFetchingNextPage) fetchNextPage();
+  };
+  // Already searched and in sortKey/sortDir order from the backend
+  const files = allFiles;
 
   const handleFolderSelect = async (folderPath: string) => {
     try {
@@ -351,11 +360,14 @@ function App() {
           <TopBar
             folderPath={selectedFolder}
             onFolderChange={handleFolderSelect}
-            fileCount={files.length}
+            fileCount={filePages?.pages[0]?.total ?? files.length}
             onSearch={handleSearch}
             sortKey={sortKey}
             sortDir={sortDir}
-            onChangeSortKey={setSortKey}
+            onChangeSortKey={(k) => {
+              if (k === 'random') setSortSeed(Math.floor(Math.random() * 2 ** 32));
+              setSortKey(k);
+            }}
             onChangeSortDir={() => setSortDir(d => d === 'asc' ? 'desc' : 'asc')}
             imagesPerRow={imagesPerRow}
             onDecPerRow={() => updateImagesPerRow(Math.max(1, (imagesPerRow ?? Math.max(1, Math.floor((window.innerWidth || 1200) / ((settings?.thumbnailSize || 200) + 12))))) - 1)}
@@ -380,6 +392,7 @@ function App() {
                 loadingMessage={filesLoading ? "Loading images..." : ""}
                 isSidebarSlim={isSidebarSlim}
                 imagesPerRow={imagesPerRow}
+                onEndReached={loadMoreFiles}
               />
             )}
           </main>
@@ -1,7 +1,7 @@
 /* eslint-disable */
 import { useState, useEffect, useRef, useMemo, useCallback, useLayoutEffect } from "react";
 /* eslint-disable */
-import { convertFileSrc } from '@tauri-apps/api/core';
+import { convertFileSrc, invoke } from '@tauri-apps/api/core';
 import type { FileMeta } from "../types";
 import { cn, getAssetUrl } from "../lib/utils";
 import { ImageViewer } from "./ImageViewer";
@@ -14,12 +14,14 @@ interface FileGridProps {
   loadingMessage?: string;
   isSidebarSlim: boolean; // kept (future styling) but no longer used for width calc
   imagesPerRow?: number | null;
+  // Called when the last rows come into view, to load the next page
+  onEndReached?: () => void;
 }
 
 // NOTE: Virtualization enabled via react-window FixedSizeGrid for performance with large libraries.
 // We keep layout math simple (square cells) for predictable virtualization.
 
-export function FileGrid({ files, isLoading, thumbnailSize, loadingMessage, imagesPerRow }: FileGridProps) {
+export function FileGrid({ files, isLoading, thumbnailSize, loadingMessage, imagesPerRow, onEndReached }: FileGridProps) {
   const [selectedImageIndex, setSelectedImageIndex] = useState<number | null>(null);
   // Measure the scroll container (fills flex space) to size the virtual grid correctly
   const containerRef = useRef<HTMLDivElement | null>(null);
@@ -54,6 +56,18 @@ export function FileGrid({ files, isLoading, thumbnailSize, loadingMessage, imag
     };
   }, [isLoading]);
 
+  // Hover playback for animated GIF/WebP: previews are generated on first hover and cached by id
+  const [hoveredId, setHoveredId] = useState<string | null>(null);
+  const [animatedPreviews, setAnimatedPreviews] = useState<Record<string, string>>({});
+  const handleHoverStart = useCallback((file: FileMeta) => {
+    if (!file.is_animated) return;
+    setHoveredId(file.id);
+    if (animatedPreviews[file.id]) return;
+    invoke<string>("get_animated_preview", { fileId: file.id, size: 300 })
+      .then((path) => setAnimatedPreviews((prev) => ({ ...prev, [file.id]: path })))
+      .catch((e) => console.error("Failed to load animated preview:", e));
+  }, [animatedPreviews]);
+
   const handleImageClick = (index: number) => {
     setSelectedImageIndex(index);
   };
@@ -95,12 +109,15 @@ export function FileGrid({ files, isLoading, thumbnailSize, loadingMessage, imag
     }
     const file = files[index];
     const fileName = file.path.split(/[\\/]/).pop() || file.path;
-    const displayPath = file.thumbnail_path || file.path;
+    const animatedPreview = hoveredId === file.id ? animatedPreviews[file.id] : undefined;
+    const displayPath = animatedPreview || file.thumbnail_path || file.path;
     return (
       <div style={style}>
         <div
           className="group cursor-pointer select-none"
           onClick={() => handleImageClick(index)}
+          onMouseEnter={() => handleHoverStart(file)}
+          onMouseLeave={() => setHoveredId(null)}
         >
           <div
             className={cn("relative rounded-lg overflow-hidden bg-muted hover:shadow-lg transition-shadow")}
@@ -125,7 +142,7 @@ export function FileGrid({ files, isLoading, thumbnailSize, loadingMessage, imag
         </div>
       </div>
     );
-  }, [files, cols, contentSize]);
+  }, [files, cols, contentSize, hoveredId, animatedPreviews, handleHoverStart]);
 
   // Early returns (after hooks to keep order stable across renders)
   if (isLoading) {
@@ -189,6 +206,9 @@ export function FileGrid({ files, isLoading, thumbnailSize, loadingMessage, imag
             width={Math.max(160, containerSize.width - padding * 2)}
             overscanRowCount={4}
             overscanColumnCount={2}
+            onItemsRendered={({ overscanRowStopIndex }) => {
+              if (overscanRowStopIndex >= rowCount - 1) onEndReached?.();
+            }}
           >
             {cellRenderer}
           </Grid>
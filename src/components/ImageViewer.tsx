@@ -6,7 +6,7 @@ import {
 } from "lucide-react";
 import { Button } from "./ui/Button";
 import { AppToast } from "./ui/Toast";
-import type { FileMeta } from "../types";
+import type { FileMeta, XmpSidecar } from "../types";
 import { cn } from "../lib/utils";
 
 interface ImageViewerProps {
@@ -20,6 +20,7 @@ interface ImageViewerProps {
 interface SidecarData {
   caption: string | null;
   metadata: Record<string, any> | null;
+  xmp?: XmpSidecar | null;
 }
 
 // --- Helpers ---
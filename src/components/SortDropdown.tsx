@@ -1,9 +1,10 @@
 import { useState, useRef, useEffect, useCallback } from 'react';
 import { Button } from './ui/Button';
-import { ArrowUpDown, ArrowUp, ArrowDown, Calendar, FileText, HardDrive } from 'lucide-react';
+import { ArrowUpDown, ArrowUp, ArrowDown, Calendar, Camera, FileText, HardDrive, Maximize2, Shuffle, Star } from 'lucide-react';
 import { cn } from '../lib/utils';
 
-export type SortKey = 'name' | 'date' | 'size';
+// Mirrors the backend's SortKey
+export type SortKey = 'name' | 'size' | 'date_taken' | 'modified' | 'dimensions' | 'rating' | 'random';
 export type SortDir = 'asc' | 'desc';
 
 interface SortDropdownProps {
@@ -15,8 +16,12 @@ interface SortDropdownProps {
 
 const OPTIONS: Array<{ key: SortKey; label: string; icon: JSX.Element; desc: string; }>= [
   { key: 'name', label: 'Name (Natural)', icon: <FileText className="w-4 h-4"/>, desc: 'image2 before image10' },
-  { key: 'date', label: 'Date Modified', icon: <Calendar className="w-4 h-4"/>, desc: 'Newest or oldest first' },
+  { key: 'date_taken', label: 'Date Taken', icon: <Camera className="w-4 h-4"/>, desc: 'Capture date, else modified' },
+  { key: 'modified', label: 'Date Modified', icon: <Calendar className="w-4 h-4"/>, desc: 'Newest or oldest first' },
   { key: 'size', label: 'File Size', icon: <HardDrive className="w-4 h-4"/>, desc: 'By bytes on disk' },
+  { key: 'dimensions', label: 'Dimensions', icon: <Maximize2 className="w-4 h-4"/>, desc: 'By pixel count' },
+  { key: 'rating', label: 'Rating', icon: <Star className="w-4 h-4"/>, desc: 'Unrated counts as zero' },
+  { key: 'random', label: 'Random', icon: <Shuffle className="w-4 h-4"/>, desc: 'Reshuffles each time' },
 ];
 
 export function SortDropdown({ sortKey, sortDir, onChangeSortKey, onChangeSortDir }: SortDropdownProps) {
@@ -5,15 +5,16 @@ import { useEffect, useState } from "react";
 import { Button } from "./ui/Button";
 import { SearchBar } from "./SearchBar";
 import { SortDropdown } from "./SortDropdown";
+import type { SortDir, SortKey } from "./SortDropdown";
 
 interface TopBarProps {
   folderPath: string;
   onFolderChange: (folderPath: string) => void;
   fileCount: number;
   onSearch: (query: string) => void;
-  sortKey: 'name'|'date'|'size';
-  sortDir: 'asc'|'desc';
-  onChangeSortKey: (k: 'name'|'date'|'size') => void;
+  sortKey: SortKey;
+  sortDir: SortDir;
+  onChangeSortKey: (k: SortKey) => void;
   onChangeSortDir: () => void;
   imagesPerRow: number | null;
   onDecPerRow: () => void;
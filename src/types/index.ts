@@ -15,6 +15,7 @@ export interface FileMeta {
   modified: string; // ISO timestamp
   created: string; // ISO timestamp
   file_type: string;
+  media_type: "image" | "video";
   dimensions?: {
     width: number;
     height: number;
@@ -24,6 +25,194 @@ export interface FileMeta {
   albums: string[];
   rating?: number;
   metadata?: ImageMetadata;
+  video?: VideoMetadata;
+  is_animated: boolean;
+  animation?: AnimationMetadata;
+  place?: Place;
+  // Color labels such as "Red", from an XMP sidecar
+  labels?: string[];
+  description?: string | null;
+  // Text of the <stem>.txt caption sidecar
+  caption?: string | null;
+}
+
+// Parsed XMP sidecar (Lightroom / darktable)
+export interface XmpSidecar {
+  rating?: number | null;
+  labels: string[];
+  keywords: string[];
+  description?: string | null;
+}
+
+// One step of a bulk caption edit; tag steps split captions on commas
+export type CaptionEdit =
+  | { op: "prepend"; text: string; separator?: string | null }
+  | { op: "append"; text: string; separator?: string | null }
+  | { op: "replace"; pattern: string; replacement: string }
+  | { op: "dedupe_tags" }
+  | { op: "sort_tags"; keep_first?: number };
+
+export interface CaptionChange {
+  file_id: string;
+  sidecar_path: string;
+  before: string;
+  after: string;
+}
+
+// Caption statistics of a training-dataset folder
+export interface CaptionStats {
+  images: number;
+  captioned: number;
+  // Most used first
+  tags: { tag: string; count: number }[];
+  uncaptioned: string[];
+  too_long: { path: string; tokens: number }[];
+  // Caption sidecars without an image
+  orphans: string[];
+}
+
+export type DatasetLayout = "kohya" | "image_folder" | "coco";
+
+export interface DatasetExportOptions {
+  layout: DatasetLayout;
+  output_dir: string;
+  // Target size; omitted keeps each image's size
+  resolution?: { width: number; height: number } | null;
+  // "fit" scales down inside the resolution, "crop" fills it and center-crops
+  resize?: "fit" | "crop";
+  format?: "png" | "jpeg" | "webp" | null;
+  // Share of files (0 to 1) held out for validation
+  validation_split?: number;
+  // kohya folder name parts: <repeats>_<concept>
+  repeats?: number | null;
+  concept?: string | null;
+}
+
+export interface DatasetExportSummary {
+  train: number;
+  validation: number;
+  skipped: string[];
+}
+
+// Aspect-ratio buckets and quality limits for check_dataset; all optional
+export interface BucketOptions {
+  // Explicit buckets; empty generates kohya-ss style ones
+  buckets?: { width: number; height: number }[];
+  resolution?: number;
+  min_side?: number;
+  max_side?: number;
+  step?: number;
+  min_resolution?: number;
+  max_aspect_ratio?: number;
+  verify_decode?: boolean;
+}
+
+export interface BucketReport {
+  buckets: { bucket: { width: number; height: number }; count: number }[];
+  assignments: {
+    file_id: string;
+    path: string;
+    dimensions: { width: number; height: number };
+    bucket: { width: number; height: number };
+  }[];
+  // Flagged images, by path
+  too_small: string[];
+  extreme_aspect: string[];
+  corrupt: string[];
+  unmeasured: string[];
+  unsupported: string[];
+}
+
+// Frame count and loop duration of animated GIF/WebP files
+export interface AnimationMetadata {
+  frame_count: number;
+  duration_ms: number;
+}
+
+// Keyset position in a file listing: sort value and id of the last item returned.
+// Only valid with the sort it came from.
+export interface FileCursor {
+  key: number | string;
+  id: string;
+}
+
+// Files without known dimensions never match a dimension filter
+export interface FileFilter {
+  orientation?: "portrait" | "landscape" | "square";
+  min_width?: number;
+  min_height?: number;
+  bounds?: GeoBounds;
+  place?: PlaceFilter;
+  // For a smart album, files matching its query
+  album_id?: string;
+  search?: SearchQuery;
+}
+
+// Nearest bundled place to a file's GPS position
+export interface Place {
+  city: string;
+  region: string;
+  country_code: string;
+  country: string;
+}
+
+export interface PlaceCount {
+  place: Place;
+  count: number;
+}
+
+export interface PlaceFilter {
+  country_code: string;
+  region?: string;
+  city?: string;
+}
+
+// west > east crosses the antimeridian
+export interface GeoBounds {
+  south: number;
+  west: number;
+  north: number;
+  east: number;
+}
+
+export interface MapCluster {
+  latitude: number;
+  longitude: number;
+  count: number;
+  bounds: GeoBounds;
+  sample_id: string;
+}
+
+export interface FileSort {
+  key: "name" | "size" | "date_taken" | "modified" | "dimensions" | "rating" | "random";
+  direction: "asc" | "desc";
+  seed?: number;
+}
+
+// Local calendar period: "2024", "2024-03" or "2024-03-15"
+export interface DateBucket {
+  period: string;
+  count: number;
+}
+
+export type DateGranularity = "year" | "month" | "day";
+
+// Position of a period in a date_taken listing
+export interface DateJump {
+  offset: number;
+  cursor?: FileCursor | null;
+}
+
+export interface FilePage {
+  files: FileMeta[];
+  next_cursor?: FileCursor | null;
+  total: number;
+}
+
+// Container details for video files
+export interface VideoMetadata {
+  duration_ms?: number;
+  codec?: string;
 }
 
 // Image metadata extracted from EXIF
@@ -57,20 +246,49 @@ export interface Album {
   created: string;
   modified: string;
   file_count: number;
+  kind: AlbumKind;
+  // Capture time span of an event album
+  starts?: string | null;
+  ends?: string | null;
+  // What a smart album contains
+  query?: SearchQuery | null;
+}
+
+export type AlbumKind = "manual" | "event" | "smart";
+
+export interface EventOptions {
+  max_gap_hours?: number;
+  max_distance_km?: number | null;
+  min_files?: number;
+}
+
+export interface Tag {
+  id: string;
+  name: string;
+  color?: string | null;
+  created: string;
+  parent_id?: string | null;
+  // Levels from the root joined by "|", e.g. "Places|Europe|Paris"
+  path: string;
+  // Files with this tag or one below it
+  file_count: number;
 }
 
 // Search and filtering
 export interface SearchQuery {
   query?: string;
   file_types?: string[];
+  // Inclusive; a bound may be a timestamp or a period such as "2024" or "2024-03"
   date_range?: {
-    start: string;
-    end: string;
+    start?: string;
+    end?: string;
   };
+  // Inclusive, in bytes
   size_range?: {
-    min: number;
-    max: number;
+    min?: number;
+    max?: number;
   };
+  // Tag paths; each also matches the tags below it
   tags?: string[];
   albums?: string[];
   rating?: number;